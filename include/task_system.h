@@ -0,0 +1,101 @@
+#ifndef TASK_SYSTEM_H
+#define TASK_SYSTEM_H
+
+/* Generated by cbindgen from src/capi.rs, do not edit. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+#define TS_STATUS_NONE 0
+
+#define TS_STATUS_WAITING 1
+
+#define TS_STATUS_QUEUED 2
+
+#define TS_STATUS_RUNNING 3
+
+#define TS_STATUS_COMPLETED 4
+
+#define TS_STATUS_CANCELLED 5
+
+#define TS_STATUS_FAILED 6
+
+#define TS_STATUS_TIMED_OUT 7
+
+#define TS_STATUS_ABORTED 8
+
+/**
+ * Task system created with [`ts_create`].
+ */
+typedef struct TsSystem TsSystem;
+
+/**
+ * Task queued with [`ts_run`].
+ */
+typedef struct TsTask TsTask;
+
+/**
+ * Function run by a task, called with the `user_data` given to [`ts_run`].
+ */
+typedef void (*TsTaskFn)(void *user_data);
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Creates a task system with `workers` worker threads.
+ */
+TsSystem *ts_create(size_t workers);
+
+/**
+ * Waits for the queued tasks to finish, joins the workers and frees `system`. Tasks of the system
+ * stay valid until they are released.
+ *
+ * # Safety
+ * `system` must come from [`ts_create`] and not be used afterwards. Null is ignored.
+ */
+void ts_destroy(TsSystem *system);
+
+/**
+ * Queues `fun` to be called with `user_data` on a worker. Returns null if `system` or `fun` is
+ * null.
+ *
+ * # Safety
+ * `system` must come from [`ts_create`] and not be destroyed yet. `user_data` must stay valid
+ * and be safe to use from another thread until the task finishes.
+ */
+TsTask *ts_run(const TsSystem *system, TsTaskFn fun, void *user_data);
+
+/**
+ * Returns the current status of `task`, one of the `TS_STATUS_*` values.
+ *
+ * # Safety
+ * `task` must come from [`ts_run`] and not be released yet.
+ */
+uint8_t ts_task_status(const TsTask *task);
+
+/**
+ * Blocks until `task` finishes and returns its final status.
+ *
+ * # Safety
+ * `task` must come from [`ts_run`] and not be released yet.
+ */
+uint8_t ts_task_wait(TsTask *task);
+
+/**
+ * Frees the handle of `task`. The task still runs if it didn't yet.
+ *
+ * # Safety
+ * `task` must come from [`ts_run`] and not be used afterwards. Null is ignored.
+ */
+void ts_task_release(TsTask *task);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* TASK_SYSTEM_H */
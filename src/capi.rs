@@ -0,0 +1,110 @@
+//! C interface to the task system, enabled with the `capi` feature.
+//!
+//! Build a library C and C++ code can link with `cargo rustc --release --features capi
+//! --crate-type staticlib` (or `cdylib`). The matching header is `include/task_system.h`,
+//! regenerated after changes to this module with
+//! `cbindgen --config cbindgen.toml --output include/task_system.h`.
+//!
+//! Systems and tasks are handed out as opaque pointers owned by the caller, released with
+//! [`ts_destroy`] and [`ts_task_release`]. Task statuses are the values of [`TaskStatus`], see the
+//! `TS_STATUS_*` constants.
+use std::{
+    ffi::c_void,
+    ptr,
+};
+
+use crate::{TaskBase, TaskStatus, TaskSystem};
+
+// *************************************************************************************************
+pub const TS_STATUS_NONE: u8 = TaskStatus::None as u8;
+pub const TS_STATUS_WAITING: u8 = TaskStatus::Waiting as u8;
+pub const TS_STATUS_QUEUED: u8 = TaskStatus::Queued as u8;
+pub const TS_STATUS_RUNNING: u8 = TaskStatus::Running as u8;
+pub const TS_STATUS_COMPLETED: u8 = TaskStatus::Completed as u8;
+pub const TS_STATUS_CANCELLED: u8 = TaskStatus::Cancelled as u8;
+pub const TS_STATUS_FAILED: u8 = TaskStatus::Failed as u8;
+pub const TS_STATUS_TIMED_OUT: u8 = TaskStatus::TimedOut as u8;
+pub const TS_STATUS_ABORTED: u8 = TaskStatus::Aborted as u8;
+
+/// Function run by a task, called with the `user_data` given to [`ts_run`].
+pub type TsTaskFn = unsafe extern "C" fn(user_data: *mut c_void);
+
+/// Task system created with [`ts_create`].
+pub struct TsSystem(TaskSystem);
+
+/// Task queued with [`ts_run`].
+pub struct TsTask(crate::Task<()>);
+
+// The caller vouches that `user_data` can be used from the workers.
+struct UserData(*mut c_void);
+
+unsafe impl Send for UserData {}
+
+// *************************************************************************************************
+/// Creates a task system with `workers` worker threads.
+#[no_mangle]
+pub extern "C" fn ts_create(workers: usize) -> *mut TsSystem {
+    return Box::into_raw(Box::new(TsSystem(TaskSystem::new(workers))));
+}
+
+/// Waits for the queued tasks to finish, joins the workers and frees `system`. Tasks of the system
+/// stay valid until they are released.
+///
+/// # Safety
+/// `system` must come from [`ts_create`] and not be used afterwards. Null is ignored.
+#[no_mangle]
+pub unsafe extern "C" fn ts_destroy(system: *mut TsSystem) {
+    if !system.is_null() {
+        drop(Box::from_raw(system));
+    }
+}
+
+/// Queues `fun` to be called with `user_data` on a worker. Returns null if `system` or `fun` is
+/// null.
+///
+/// # Safety
+/// `system` must come from [`ts_create`] and not be destroyed yet. `user_data` must stay valid
+/// and be safe to use from another thread until the task finishes.
+#[no_mangle]
+pub unsafe extern "C" fn ts_run(system: *const TsSystem, fun: Option<TsTaskFn>, user_data: *mut c_void) -> *mut TsTask {
+    let (Some(system), Some(fun)) = (system.as_ref(), fun) else {
+        return ptr::null_mut();
+    };
+    let user_data = UserData(user_data);
+    let task = system.0.run(move || {
+        let user_data = user_data;
+        fun(user_data.0);
+    });
+    return Box::into_raw(Box::new(TsTask(task)));
+}
+
+/// Returns the current status of `task`, one of the `TS_STATUS_*` values.
+///
+/// # Safety
+/// `task` must come from [`ts_run`] and not be released yet.
+#[no_mangle]
+pub unsafe extern "C" fn ts_task_status(task: *const TsTask) -> u8 {
+    return (*task).0.status() as u8;
+}
+
+/// Blocks until `task` finishes and returns its final status.
+///
+/// # Safety
+/// `task` must come from [`ts_run`] and not be released yet.
+#[no_mangle]
+pub unsafe extern "C" fn ts_task_wait(task: *mut TsTask) -> u8 {
+    let task = &mut (*task).0;
+    task.wait();
+    return task.status() as u8;
+}
+
+/// Frees the handle of `task`. The task still runs if it didn't yet.
+///
+/// # Safety
+/// `task` must come from [`ts_run`] and not be used afterwards. Null is ignored.
+#[no_mangle]
+pub unsafe extern "C" fn ts_task_release(task: *mut TsTask) {
+    if !task.is_null() {
+        drop(Box::from_raw(task));
+    }
+}
@@ -1,195 +1,7432 @@
+//! A small task system running closures on a fixed set of worker threads.
+//!
+//! Submit work with [`TaskSystem::run`] and get back a [`Task`] handle that can be polled with
+//! [`TaskBase::status`], blocked on with [`TaskBase::wait`] and finally consumed with
+//! [`Task::value`].
+//!
+//! ```
+//! use task_system::{TaskBase, TaskStatus, TaskSystem};
+//!
+//! let system = TaskSystem::new(2);
+//! let mut task = system.run(|| 6 * 7);
+//!
+//! task.wait();
+//! assert_eq!(task.status(), TaskStatus::Completed);
+//! assert_eq!(task.value(), Ok(42));
+//! ```
+#![allow(clippy::needless_return)]
+
+pub use tasks::{
+    AdmissionOrder,
+    AsCompleted,
+    Cadence,
+    CancellationToken,
+    ChildSystem,
+    DeadlineScheduler,
+    DeadlineScope,
+    Disagreement,
+    ExecutionOrder,
+    Executor,
+    FifoScheduler,
+    FileTaskStore,
+    FramePhase,
+    GetValueError,
+    HungTask,
+    LifoScheduler,
+    Limiter,
+    LocalTask,
+    Metrics,
+    PeriodicTask,
+    Pipeline,
+    PipelineHandle,
+    Priority,
+    PriorityScheduler,
+    Progress,
+    QueueFull,
+    RecordingScheduler,
+    RemoteTask,
+    ReplayScheduler,
+    Reply,
+    RetryPolicy,
+    SaturationPolicy,
+    Schedule,
+    ScheduleEntry,
+    ScheduleRecording,
+    ScheduledJob,
+    Scheduler,
+    Scope,
+    ScopedTask,
+    StatusChange,
+    Step,
+    StoredTask,
+    StreamSender,
+    StreamTask,
+    SubTask,
+    SubmitError,
+    Target,
+    Task,
+    TaskBase,
+    TaskContext,
+    TaskDescriptor,
+    TaskError,
+    TaskGroup,
+    TaskId,
+    TaskInfo,
+    TaskLocal,
+    TaskOutput,
+    TaskPayload,
+    TaskIteratorExt,
+    TaskProfile,
+    TaskStatus,
+    TaskStore,
+    TaskSystem,
+    TaskSystemBuilder,
+    TaskTimings,
+    Throttle,
+    TraceEvent,
+    TrackingAllocator,
+    WeightedFairScheduler,
+    WorkGroup,
+    global,
+    heartbeat,
+    init,
+    join_all,
+    join_all_ok,
+    spawn,
+    spawn_blocking,
+    wait_any,
+};
+
+#[cfg(feature = "serde")]
+pub use tasks::{SerdeOutput, SerdePayload};
+
+#[cfg(feature = "capi")]
+pub mod capi;
+
 pub mod tasks {
     use std::{
-        sync::{Arc, Mutex},
+        sync::{Arc, Weak, LockResult, PoisonError, OnceLock, mpsc},
         marker::Send,
+        panic::{self, AssertUnwindSafe},
+        thread,
+        mem,
+        ops::Deref,
+        fmt,
+        marker::PhantomData,
+        any::Any,
+        cell::RefCell,
+        collections::{HashMap, VecDeque},
+        iter::FromIterator,
+        hash::Hash,
+        future::Future,
+        pin::Pin,
+        task::{Context, Poll, Waker},
+        time::{Duration, Instant},
+        fs,
+        io,
+        path::Path,
     };
 
-    use threadpool::ThreadPool;
+    use self::sync::{Condvar, Mutex, MutexGuard, atomic::{AtomicBool, AtomicUsize, Ordering}};
 
     // *********************************************************************************************
+    /// Lifecycle of a task, from creation to the moment its output is available.
     #[derive(Debug, PartialEq, Eq, Copy, Clone)]
+    #[repr(u8)]
     pub enum TaskStatus {
+        /// The task has not been submitted yet.
         None,
+        /// The task is waiting for other tasks to complete before it can be queued.
         Waiting,
+        /// The task is queued and will be picked up by the next free worker.
         Queued,
+        /// A worker is executing the task.
         Running,
+        /// The task finished and its output is ready.
         Completed,
+        /// The task was cancelled before it could produce an output.
+        Cancelled,
+        /// The task panicked.
+        Failed,
+        /// The task didn't finish before its deadline.
+        TimedOut,
+        /// The task was aborted before it started and never ran.
+        Aborted,
+    }
+
+    impl TaskStatus {
+        /// Whether the task reached a final status and will not change anymore.
+        pub fn is_finished(self) -> bool {
+            return matches!(self, TaskStatus::Completed | TaskStatus::Cancelled | TaskStatus::Failed | TaskStatus::TimedOut | TaskStatus::Aborted);
+        }
+
+        fn from_u8(status: u8) -> Self {
+            match status {
+                0 => return TaskStatus::None,
+                1 => return TaskStatus::Waiting,
+                2 => return TaskStatus::Queued,
+                3 => return TaskStatus::Running,
+                4 => return TaskStatus::Completed,
+                5 => return TaskStatus::Cancelled,
+                6 => return TaskStatus::Failed,
+                7 => return TaskStatus::TimedOut,
+                8 => return TaskStatus::Aborted,
+                _ => unreachable!("invalid task status {}", status),
+            }
+        }
     }
 
     // *********************************************************************************************
-    #[derive(Debug, PartialEq, Eq, Copy, Clone)]
+    /// Reason [`Task::value`] could not return the output.
+    #[derive(Debug, PartialEq, Eq, Clone)]
     pub enum GetValueError {
+        /// The task has not completed yet.
         NotReady,
+        /// The output was already taken by a previous call.
         AlreadyTaken,
+        /// The task was cancelled and has no output.
+        Cancelled,
+        /// The task didn't finish before its deadline.
+        TimedOut,
+        /// The task was aborted before it started.
+        Aborted,
+        /// The queue was full and the task was turned away, see [`SaturationPolicy::RejectNewest`].
+        Rejected,
+        /// The task's work group used up its memory budget before the task started, see
+        /// [`WorkGroup::set_memory_budget`].
+        MemoryBudgetExceeded,
+        /// A thread panicked while holding the task's lock, e.g. in a [`Task::on_complete`]
+        /// callback, so the output can't be trusted anymore.
+        Poisoned,
+        /// The task panicked with the given message.
+        Panicked(String),
+        /// The encoded output of a task run through an [`Executor`] couldn't be decoded, see
+        /// [`TaskOutput::from_bytes`].
+        InvalidOutput,
+    }
+
+    /// Reason a task returning a `Result` has no `Ok` output, see [`Task::try_join`].
+    #[derive(Debug, PartialEq, Eq, Clone)]
+    pub enum TaskError<E> {
+        /// The task has no output at all.
+        Task(GetValueError),
+        /// The task returned this error.
+        Err(E),
+    }
+
+    impl<E> From<GetValueError> for TaskError<E> {
+        fn from(error: GetValueError) -> Self {
+            return TaskError::Task(error);
+        }
+    }
+
+    // *********************************************************************************************
+    /// Scheduling lane of a task. Workers always drain higher priority lanes first. Higher
+    /// priorities compare as smaller.
+    #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Copy, Clone, Default)]
+    pub enum Priority {
+        /// Latency sensitive work such as UI or audio.
+        High,
+        /// Default lane used by [`TaskSystem::run`].
+        #[default]
+        Normal,
+        /// Bulk work that can wait.
+        Low,
+    }
+
+    // *********************************************************************************************
+    /// Order in which a worker runs the tasks it spawned itself. Tasks submitted from outside of the
+    /// workers always start in submission order.
+    #[derive(Debug, PartialEq, Eq, Copy, Clone, Default)]
+    pub enum ExecutionOrder {
+        /// Most recently spawned first, keeping the data of recursive workloads in cache.
+        #[default]
+        Lifo,
+        /// Oldest first, so no spawned task waits behind ones submitted after it.
+        Fifo,
+    }
+
+    /// What [`TaskSystem::run`] does with a task while the queue holds
+    /// [`TaskSystemBuilder::max_queue_depth`] tasks.
+    #[derive(Debug, PartialEq, Eq, Copy, Clone, Default)]
+    pub enum SaturationPolicy {
+        /// Blocks until a queued task starts and there is room again.
+        #[default]
+        Queue,
+        /// Returns a task that already failed with [`GetValueError::Rejected`].
+        RejectNewest,
+        /// Runs the task on the calling thread before returning, which keeps the caller from
+        /// submitting more in the meantime.
+        CallerRuns,
+    }
+
+    /// Order in which submitters blocked on a full [`TaskSystemBuilder::max_queue_depth`] queue get
+    /// to queue their task once there is room again.
+    #[derive(Debug, PartialEq, Eq, Copy, Clone, Default)]
+    pub enum AdmissionOrder {
+        /// Longest waiting first, so every submitter waits at most as long as the ones ahead of it.
+        #[default]
+        Fifo,
+        /// Most recent first, so fresh work gets in quickly while older submitters may starve
+        /// under a sustained load.
+        Lifo,
+    }
+
+    /// Where [`Task::then_on`] runs a continuation.
+    #[derive(Debug, PartialEq, Eq, Copy, Clone, Default)]
+    pub enum Target {
+        /// Any worker, like [`Task::then`].
+        #[default]
+        Pool,
+        /// The worker that ran the task before, whose cache still holds its data. Any worker if
+        /// the task didn't run on one or that worker was retired.
+        SameWorker,
+        /// The thread calling [`TaskSystem::pump_main_tasks`], e.g. to update a UI. The
+        /// continuation is cancelled if the task system is gone by then.
+        MainThread,
+    }
+
+    impl Priority {
+        const COUNT: usize = 3;
+
+        fn lane(self) -> usize {
+            return self as usize;
+        }
     }
 
     // *********************************************************************************************
-    trait TaskBase {
+    /// Reason a task was not accepted by the task system.
+    #[derive(Debug, PartialEq, Eq, Copy, Clone)]
+    pub enum SubmitError {
+        /// Admitting the task would exceed the configured memory budget.
+        MemoryBudgetExceeded,
+    }
+
+    // *********************************************************************************************
+    /// Returned by [`TaskSystem::try_run`] when the queue is full, handing the closure back.
+    pub struct QueueFull<F>(pub F);
+
+    impl<F> fmt::Debug for QueueFull<F> {
+        fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+            return formatter.write_str("QueueFull(..)");
+        }
+    }
+
+    // *********************************************************************************************
+    /// Returned by [`TaskSystem::run_replicated`] when no result was produced by a strict majority.
+    #[derive(Debug, PartialEq, Eq, Clone)]
+    pub struct Disagreement<O> {
+        /// Every distinct result, in the order of the replicas that first produced it.
+        pub results: Vec<O>,
+    }
+
+    // *********************************************************************************************
+    /// Output independent part of a task handle.
+    pub trait TaskBase: sealed::Dependency {
+        /// Current status of the task.
         fn status(&self) -> TaskStatus;
+        /// Whether the task is queued.
         fn queued(&self) -> bool;
+        /// Whether the task is being executed.
         fn running(&self) -> bool;
+        /// Whether the task has completed.
         fn completed(&self) -> bool;
 
+        /// Blocks the calling thread until the task finishes, successfully or not.
         fn wait(&mut self);
+        /// Like [`TaskBase::wait`] but gives up at `deadline`. Returns whether the task finished.
+        fn wait_deadline(&mut self, deadline: Instant) -> bool;
+
+        /// Like [`TaskBase::wait`] but gives up after `timeout`. Returns whether the task finished.
+        fn wait_timeout(&mut self, timeout: Duration) -> bool {
+            match Instant::now().checked_add(timeout) {
+                Some(deadline) => return self.wait_deadline(deadline),
+                None => {
+                    self.wait();
+                    return true;
+                },
+            }
+        }
     }
 
+    mod sealed {
+        // Lets the task system hook into tasks behind a `dyn TaskBase`. Living in a private module it
+        // also keeps `TaskBase` from being implemented outside of the crate.
+        pub trait Dependency {
+            fn on_completed(&self, continuation: super::Job);
+        }
+    }
+
+    use self::sealed::Dependency;
+
     // *********************************************************************************************
-    struct TaskSharedState<O> {
-        status: TaskStatus,
-        output: Option<O>,
+    /// Cooperative cancellation flag handed to tasks started with [`TaskSystem::run_cancellable`].
+    #[derive(Clone, Default)]
+    pub struct CancellationToken {
+        state: Arc<TokenState>,
     }
-    
-    impl<O> TaskSharedState<O> {
-        fn new() -> Self {
-            return Self{ 
-                status: TaskStatus::None,
-                output: None,
+
+    #[derive(Default)]
+    struct TokenState {
+        cancelled: AtomicBool,
+        // Cancel the subtasks forked under this token.
+        children: Mutex<Vec<Job>>,
+    }
+
+    impl CancellationToken {
+        /// Whether the task was asked to stop. Long-running tasks should check it regularly and bail
+        /// out early.
+        pub fn is_cancelled(&self) -> bool {
+            return self.state.cancelled.load(Ordering::Acquire);
+        }
+
+        fn cancel(&self) {
+            let children = {
+                let mut mutex = self.state.children.lock().unpoison();
+                self.state.cancelled.store(true, Ordering::Release);
+                mem::take(&mut *mutex)
             };
+            for child in children {
+                child.run();
+            }
+        }
+
+        // Runs `child` once the token is cancelled, right away if it already is.
+        fn on_cancel(&self, child: Job) {
+            let mut mutex = self.state.children.lock().unpoison();
+            if self.is_cancelled() {
+                drop(mutex);
+                child.run();
+            } else {
+                mutex.push(child);
+            }
+        }
+    }
+
+    impl fmt::Debug for CancellationToken {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            return f.debug_struct("CancellationToken").field("cancelled", &self.is_cancelled()).finish();
         }
     }
 
     // *********************************************************************************************
-    pub struct Task<O> {
-        shared_state: Arc<Mutex<TaskSharedState<O>>>,
+    struct TaskState<O> {
+        output: Option<O>,
+        error: Option<GetValueError>,
+        continuations: Vec<Job>,
+        // Threads blocked in `wait`, so finishing a task nobody waits for skips the notify syscall.
+        waiters: usize,
+        // Async executors to notify once the task finishes, one per handle being awaited.
+        wakers: Vec<Waker>,
+        // When the task was last queued and started, and when it finished.
+        queued_at: Option<Instant>,
+        started_at: Option<Instant>,
+        finished_at: Option<Instant>,
+        // Worker that started the task, if it ran on one.
+        worker: Option<usize>,
     }
 
-    impl<O> Task<O> {
-        fn new() -> Self {
+    impl<O> TaskState<O> {
+        fn timings(&self) -> TaskTimings {
+            let between = |from: Option<Instant>, to: Option<Instant>| Some(to?.saturating_duration_since(from?));
+            return TaskTimings{
+                queued: between(self.queued_at, self.started_at),
+                running: between(self.started_at, self.finished_at),
+            };
+        }
+    }
+
+    struct TaskSharedState<O> {
+        // Holds the status, read without the lock so polling it never blocks, but only written while
+        // holding it, so the status can't change under the feet of someone who locked the state.
+        meta: Arc<TaskMeta>,
+        registry: Arc<TaskRegistry>,
+        state: Mutex<TaskState<O>>,
+        completed: Condvar,
+        cancellation: CancellationToken,
+    }
+    
+    impl<O> TaskSharedState<O> {
+        fn new(registry: &Arc<TaskRegistry>, name: Option<String>) -> Self {
             return Self{ 
-                shared_state: Arc::new(Mutex::new(TaskSharedState::new())),
+                meta: registry.register(name),
+                registry: registry.clone(),
+                state: Mutex::new(TaskState{
+                    output: None,
+                    error: None,
+                    continuations: Vec::new(),
+                    waiters: 0,
+                    wakers: Vec::new(),
+                    queued_at: None,
+                    started_at: None,
+                    finished_at: None,
+                    worker: None,
+                }),
+                completed: Condvar::new(),
+                cancellation: CancellationToken::default(),
             };
         }
 
-        pub fn value(&mut self) -> Result<O, GetValueError> {
-            let mut mutex = self.shared_state.lock().unwrap();
+        fn lock(&self) -> LockResult<MutexGuard<'_, TaskState<O>>> {
+            return self.state.lock();
+        }
+
+        fn status(&self) -> TaskStatus {
+            return TaskStatus::from_u8(self.meta.status.load(Ordering::Acquire));
+        }
+
+        // Takes the guard to prove the state is locked, and to note the time of the change.
+        fn store_status(&self, mutex: &mut MutexGuard<'_, TaskState<O>>, status: TaskStatus) {
+            let old = self.status();
+            let now = Instant::now();
+            self.meta.status.store(status as u8, Ordering::Release);
+            trace::status_changed(&self.meta, status);
+            match status {
+                TaskStatus::Queued => mutex.queued_at = Some(now),
+                TaskStatus::Running => {
+                    mutex.started_at = Some(now);
+                    mutex.worker = scheduler::current_worker_index();
+                },
+                status if status.is_finished() => mutex.finished_at = Some(now),
+                _ => {},
+            }
+            if let Some(hook) = &self.registry.on_status_change {
+                hook(&StatusChange{ id: self.meta.id, old, new: status, submitted: self.meta.submitted, at: now });
+            }
+            // Finishing tasks leave once their continuations are queued, see `finish_locked`.
+            match (TaskRegistry::is_busy(old), TaskRegistry::is_busy(status)) {
+                (false, true) => self.registry.enter_busy(),
+                (true, false) if !status.is_finished() => self.registry.leave_busy(),
+                _ => {},
+            }
+        }
+
+        fn set_status(&self, status: TaskStatus) {
+            let mut mutex = self.lock().unpoison();
+            self.store_status(&mut mutex, status);
+        }
+
+        fn transition(&self, from: TaskStatus, to: TaskStatus) -> bool {
+            let mut mutex = self.lock().unpoison();
+            if self.status() != from {
+                return false;
+            }
+            self.store_status(&mut mutex, to);
+            return true;
+        }
+
+        fn start(&self) -> bool {
+            if !self.transition(TaskStatus::Queued, TaskStatus::Running) {
+                return false;
+            }
+            self.registry.counters.record_started(self.meta.submitted.elapsed());
+            self.meta.beat();
+            self.meta.worker.store(scheduler::current_worker_index().unwrap_or(usize::MAX), Ordering::Relaxed);
+            return true;
+        }
+
+        fn complete(&self, output: O) {
+            // A task cancelled while running loses its output, even if it didn't observe the token.
+            if self.cancellation.is_cancelled() {
+                self.finish(Err(GetValueError::Cancelled));
+            } else {
+                self.finish(Ok(output));
+            }
+        }
+
+        fn finish(&self, result: Result<O, GetValueError>) {
+            self.finish_locked(self.lock().unpoison(), result);
+        }
+
+        fn finish_locked(&self, mut mutex: MutexGuard<'_, TaskState<O>>, result: Result<O, GetValueError>) {
+            // A task that timed out is finished while its closure may still run.
+            if self.status().is_finished() {
+                return;
+            }
+            let busy = TaskRegistry::is_busy(self.status());
+            // Leaves the registry before anyone can see the task finish.
+            self.registry.unregister(self.meta.id);
+            match result {
+                Ok(output) => {
+                    mutex.output = Some(output);
+                    self.store_status(&mut mutex, TaskStatus::Completed);
+                },
+                Err(error) => {
+                    let status = match error {
+                        GetValueError::Cancelled => TaskStatus::Cancelled,
+                        GetValueError::TimedOut => TaskStatus::TimedOut,
+                        GetValueError::Aborted => TaskStatus::Aborted,
+                        _ => TaskStatus::Failed,
+                    };
+                    mutex.error = Some(error);
+                    self.store_status(&mut mutex, status);
+                },
+            }
+            self.registry.counters.record_finished(self.status());
+            if let (Some(profiler), Some(timings)) = (&self.registry.profiler, mutex.timings().running) {
+                profiler.record(self.meta.name.as_deref(), timings);
+            }
+            if let (Some(recorder), Some(started), Some(finished)) = (&self.registry.recorder, mutex.started_at, mutex.finished_at) {
+                recorder.record(self.meta.id, self.meta.name.as_deref(), mutex.worker, started, finished);
+            }
+            if mutex.waiters > 0 {
+                self.completed.notify_all();
+            }
+            self.registry.notify_helpers();
+            let continuations = mem::take(&mut mutex.continuations);
+            let wakers = mem::take(&mut mutex.wakers);
+            drop(mutex);
+            for waker in wakers {
+                waker.wake();
+            }
+            for continuation in continuations {
+                continuation.run();
+            }
+            if busy {
+                self.registry.leave_busy();
+            }
+        }
+
+        fn cancel(&self) {
+            self.cancellation.cancel();
+            self.discard();
+        }
+
+        // Finishes the task as timed out unless it finished already. A running closure is asked to
+        // stop through its token, whatever it returns is dropped.
+        fn time_out(&self) {
+            let mutex = self.lock().unpoison();
+            if self.status().is_finished() {
+                return;
+            }
+            self.finish_locked(mutex, Err(GetValueError::TimedOut));
+            self.cancellation.cancel();
+        }
+
+        // Finishes the task as cancelled if it hasn't started yet.
+        fn discard(&self) {
+            let mutex = self.lock().unpoison();
+            if matches!(self.status(), TaskStatus::None | TaskStatus::Waiting | TaskStatus::Queued) {
+                self.finish_locked(mutex, Err(GetValueError::Cancelled));
+            }
+        }
+
+        // Finishes the task as aborted if it hasn't started yet. Its job is left in the queue as a
+        // tombstone, `start` fails once a worker pops it and the job is dropped without running.
+        fn abort(&self) -> bool {
+            let mutex = self.lock().unpoison();
+            if !matches!(self.status(), TaskStatus::None | TaskStatus::Waiting | TaskStatus::Queued) {
+                return false;
+            }
+            self.finish_locked(mutex, Err(GetValueError::Aborted));
+            return true;
+        }
+
+        // Output of a finished predecessor. Its absence means the dependency bookkeeping is broken.
+        fn take_input(&self) -> Result<O, GetValueError> {
+            match self.take_result() {
+                Err(error @ GetValueError::NotReady) | Err(error @ GetValueError::AlreadyTaken) => {
+                    panic!("predecessor output is unavailable: {:?}", error);
+                },
+                result => return result,
+            }
+        }
+
+        fn take_result(&self) -> Result<O, GetValueError> {
+            let mut mutex = match self.lock() {
+                Ok(mutex) => mutex,
+                Err(_) => return Err(GetValueError::Poisoned),
+            };
 
-            match mutex.status {
+            match self.status() {
                 TaskStatus::Completed => {
                     match mutex.output.take() {
                         Some(v) => return Ok(v),
                         None => return Err(GetValueError::AlreadyTaken),
                     }
                 },
+                TaskStatus::Cancelled | TaskStatus::Failed | TaskStatus::TimedOut | TaskStatus::Aborted => return Err(mutex.error.clone().unwrap()),
                 _ => return Err(GetValueError::NotReady),
             }
         }
     }
 
-    impl<O> TaskBase for Task<O> {
-        fn status(&self) -> TaskStatus {
-            let shared_state = self.shared_state.lock().unwrap();
-            return shared_state.status;
-        }
+    // Owned by the job of a task. Cancels the task if the job is dropped without running, e.g. by
+    // `TaskSystem::shutdown_now`.
+    struct PendingTask<O> {
+        shared_state: Option<Arc<TaskSharedState<O>>>,
+    }
 
-        fn queued(&self) -> bool {
-            let shared_state = self.shared_state.lock().unwrap();
-            return shared_state.status == TaskStatus::Queued;
+    impl<O> PendingTask<O> {
+        fn new(shared_state: Arc<TaskSharedState<O>>) -> Self {
+            return Self{ shared_state: Some(shared_state) };
         }
 
-        fn running(&self) -> bool {
-            let shared_state = self.shared_state.lock().unwrap();
-            return shared_state.status == TaskStatus::Running;
+        fn shared_state(&self) -> &Arc<TaskSharedState<O>> {
+            return self.shared_state.as_ref().unwrap();
         }
 
-        fn completed(&self) -> bool {
-            let shared_state = self.shared_state.lock().unwrap();
-            return shared_state.status == TaskStatus::Completed;
+        // Called once the job runs, from then on it is in charge of finishing the task.
+        fn take(mut self) -> Arc<TaskSharedState<O>> {
+            return self.shared_state.take().unwrap();
         }
+    }
 
-        fn wait(&mut self) {
-            {
-                loop {
-                    let mutex = self.shared_state.lock().unwrap();
-                    if mutex.status == TaskStatus::Completed {
-                        break;
-                    }
-                }
+    impl<O> Drop for PendingTask<O> {
+        fn drop(&mut self) {
+            if let Some(shared_state) = self.shared_state.take() {
+                shared_state.discard();
             }
         }
     }
 
     // *********************************************************************************************
-    pub struct TaskSystem {
-        pool: ThreadPool,
+    /// Handle to a task submitted to a [`TaskSystem`].
+    pub struct Task<O> {
+        shared_state: Arc<TaskSharedState<O>>,
+        pool: Arc<WorkerPool>,
     }
 
-    impl TaskSystem {
-        pub fn new(n_workers: usize) -> Self {
-            return Self{ pool: ThreadPool::new(n_workers) };
+    impl<O> Task<O> {
+        fn new(pool: Arc<WorkerPool>, name: Option<String>) -> Self {
+            // Unlike its metadata, the state is allocated for every task, as it is typed by the output
+            // and lives as long as any handle does.
+            return Self{ 
+                shared_state: Arc::new(TaskSharedState::new(&pool.registry, name)),
+                pool,
+            };
         }
-    
-        pub fn run<F, O>(&mut self, fun: F) -> Task<O>
-            where F: FnOnce() -> O + Send + 'static, O: Send + 'static
-        {
-            let task = Task::<O>::new();
-            
-            {
-                let mut mutex = task.shared_state.lock().unwrap();
-                mutex.status = TaskStatus::Queued;
+
+        /// Identifier of the task.
+        pub fn id(&self) -> TaskId {
+            return self.shared_state.meta.id;
+        }
+
+        /// Name given with [`TaskSystem::run_named`].
+        pub fn name(&self) -> Option<&str> {
+            return self.shared_state.meta.name.as_deref();
+        }
+
+        /// Progress reported through the task's [`Progress`], or 1 once the task completed.
+        pub fn progress(&self) -> f32 {
+            if self.status() == TaskStatus::Completed {
+                return 1.0;
             }
+            return self.shared_state.meta.progress.get();
+        }
 
-            let shared_state = task.shared_state.clone();
+        /// Calls `listener` with every progress the task reports from now on. The listener runs on
+        /// the reporting thread and must not register further listeners.
+        pub fn on_progress<L>(&self, listener: L) where L: Fn(f32) + Send + 'static {
+            self.shared_state.meta.progress.subscribe(Box::new(listener));
+        }
+
+        /// Bytes the task allocated and didn't free while running, as tracked by
+        /// [`TrackingAllocator`]. Always 0 unless that is the global allocator.
+        pub fn memory_used(&self) -> usize {
+            return self.shared_state.meta.memory.load(Ordering::Relaxed).max(0) as usize;
+        }
+
+        /// How long the task waited in the queue and how long it ran.
+        pub fn timings(&self) -> TaskTimings {
+            return self.shared_state.lock().unpoison().timings();
+        }
+
+        /// Takes the output of a completed task. The output can only be taken once, while the error of
+        /// a cancelled or failed task is returned on every call.
+        pub fn value(&mut self) -> Result<O, GetValueError> {
+            return self.shared_state.take_result();
+        }
+
+        /// Waits for the task to finish and takes its output, or returns the error it finished with,
+        /// like a [`TaskBase::wait`] followed by [`Task::value`].
+        ///
+        /// ```
+        /// use task_system::{GetValueError, TaskSystem};
+        ///
+        /// let system = TaskSystem::new(1);
+        /// assert_eq!(system.run(|| 6 * 7).join(), Ok(42));
+        ///
+        /// let failing = system.run(|| -> i32 { panic!("out of cheese") });
+        /// assert_eq!(failing.join(), Err(GetValueError::Panicked(String::from("out of cheese"))));
+        /// ```
+        pub fn join(mut self) -> Result<O, GetValueError> {
+            self.wait();
+            return self.value();
+        }
+
+        /// Like [`TaskBase::wait`] but runs queued tasks on the calling thread until this one
+        /// finishes, so a thread waiting for results adds to the workers instead of idling next to
+        /// them.
+        ///
+        /// ```
+        /// use std::sync::mpsc;
+        /// use task_system::{TaskBase, TaskSystem};
+        ///
+        /// let system = TaskSystem::new(1);
+        /// let (tx, rx) = mpsc::channel::<()>();
+        /// let blocker = system.run(move || rx.recv().unwrap());
+        /// while !blocker.running() {}
+        ///
+        /// // The only worker is busy, so the waiting thread runs the task itself.
+        /// let mut task = system.run(|| 6 * 7);
+        /// task.wait_and_help();
+        /// assert_eq!(task.join(), Ok(42));
+        /// tx.send(()).unwrap();
+        /// ```
+        pub fn wait_and_help(&mut self) {
+            let pool = self.pool.clone();
+            pool.help_until(|| pool.help(), || self.status().is_finished());
+        }
+
+        /// Cancels the task. A task that hasn't started yet never runs; a running task gets its
+        /// [`CancellationToken`] tripped and finishes as cancelled once its closure returns.
+        pub fn cancel(&self) {
+            self.shared_state.cancel();
+        }
+
+        /// Takes the task out of the queue if it hasn't started yet, so it is guaranteed never to
+        /// run and finishes as [`TaskStatus::Aborted`]. Returns false if the task already started
+        /// or finished, in which case it is left alone, unlike with [`Task::cancel`].
+        ///
+        /// ```
+        /// use task_system::{GetValueError, TaskSystem};
+        ///
+        /// let system = TaskSystem::new_deterministic();
+        /// let mut task = system.run(|| 42);
+        /// assert!(task.abort());
+        /// assert_eq!(system.run_until_idle(), 1);
+        /// assert_eq!(task.value(), Err(GetValueError::Aborted));
+        /// assert!(!task.abort());
+        /// ```
+        pub fn abort(&self) -> bool {
+            return self.shared_state.abort();
+        }
+
+        /// Views the task as its output independent part, to mix tasks of different outputs in
+        /// [`wait_any`].
+        pub fn as_base(&mut self) -> &mut dyn TaskBase {
+            return self;
+        }
+
+        fn take_input(&mut self) -> Result<O, GetValueError> {
+            return self.shared_state.take_input();
+        }
+
+        /// Takes the output if the task completed and the output is still there, without reporting
+        /// why it isn't otherwise.
+        pub fn try_value(&mut self) -> Option<O> {
+            return self.value().ok();
+        }
+
+        /// Borrows the output of a completed task, or returns `None` if there is none (yet). The
+        /// task's lock is held while the guard lives, so it shouldn't be kept around.
+        ///
+        /// ```
+        /// use task_system::{TaskBase, TaskSystem};
+        ///
+        /// let system = TaskSystem::new(1);
+        /// let mut task = system.run(|| vec![1, 2, 3]);
+        /// let mut observer = task.clone();
+        ///
+        /// task.wait();
+        /// assert_eq!(observer.peek().map(|output| output.len()), Some(3));
+        /// assert_eq!(observer.try_value(), Some(vec![1, 2, 3]));
+        /// assert!(task.peek().is_none());
+        /// ```
+        pub fn peek(&self) -> Option<impl Deref<Target = O> + '_> {
+            let mutex = self.shared_state.lock().unpoison();
+            if self.shared_state.status() == TaskStatus::Completed && mutex.output.is_some() {
+                return Some(TaskOutputRef{ mutex });
+            }
+            return None;
+        }
+
+        /// Borrows the output of a completed task.
+        ///
+        /// Meant for tests and examples where the task is known to be done; panics with the current
+        /// status otherwise.
+        pub fn expect_ref(&self) -> impl Deref<Target = O> + '_ {
+            let mutex = self.shared_state.lock().unpoison();
+            let status = self.shared_state.status();
+            if status == TaskStatus::Completed && mutex.output.is_some() {
+                return TaskOutputRef{ mutex };
+            }
+
+            // Release the lock before panicking so the shared state isn't poisoned.
+            drop(mutex);
+            match status {
+                TaskStatus::Completed => panic!("expected a completed task value, but it was already taken"),
+                status => panic!("expected a completed task value, but the task is {:?}", status),
+            }
+        }
+    }
+
+    /// Clones share the task: every clone observes its status, while the output can still be taken
+    /// only once.
+    impl<O> Clone for Task<O> {
+        fn clone(&self) -> Self {
+            return Self{
+                shared_state: self.shared_state.clone(),
+                pool: self.pool.clone(),
+            };
+        }
+    }
 
-            self.pool.execute(move || {
-                {
-                    let mut mutex = shared_state.lock().unwrap();
-                    mutex.status = TaskStatus::Running;
+    impl<O> Task<O> where O: Send + 'static {
+        /// Calls `callback` with the output right after the task completes, on the thread that
+        /// completed it, or right away if it already did. A task that is cancelled or fails, or
+        /// whose output was taken before, drops the callback without calling it.
+        ///
+        /// The task's lock is held during the call, so the callback must not take the output or
+        /// wait on the task.
+        pub fn on_complete<C>(&self, callback: C) where C: FnOnce(&O) + Send + 'static {
+            let shared_state = self.shared_state.clone();
+            self.on_completed(Job::new(move || {
+                let mutex = shared_state.lock().unpoison();
+                if let Some(output) = mutex.output.as_ref() {
+                    callback(output);
                 }
+            }));
+        }
 
-                let output = fun();
+        /// Sends the task's id through `sender` once it finishes, whether it completed or not, or
+        /// right away if it already did. Tasks sharing clones of one sender report to a single
+        /// receiver, so an event loop can poll all of them along with its other channels.
+        ///
+        /// ```
+        /// use std::sync::mpsc;
+        /// use task_system::TaskSystem;
+        ///
+        /// let system = TaskSystem::new(2);
+        /// let (tx, rx) = mpsc::channel();
+        /// let tasks: Vec<_> = (0..3).map(|i| system.run(move || i)).collect();
+        /// for task in &tasks {
+        ///     task.notify_finished(tx.clone());
+        /// }
+        /// let mut finished: Vec<_> = rx.iter().take(3).collect();
+        /// finished.sort();
+        /// assert_eq!(finished, tasks.iter().map(|task| task.id()).collect::<Vec<_>>());
+        /// ```
+        pub fn notify_finished(&self, sender: mpsc::Sender<TaskId>) {
+            let id = self.id();
+            self.on_completed(Job::new(move || {
+                // Nobody listening anymore is fine.
+                let _ = sender.send(id);
+            }));
+        }
 
-                {
-                    let mut mutex = shared_state.lock().unwrap();
-                    mutex.output = Some(output);
-                    mutex.status = TaskStatus::Completed;
+        /// Receiver getting the task's id once it finishes, see [`Task::notify_finished`].
+        pub fn completion_receiver(&self) -> mpsc::Receiver<TaskId> {
+            let (sender, receiver) = mpsc::channel();
+            self.notify_finished(sender);
+            return receiver;
+        }
+
+        /// Queues `fun` with the output of this task as soon as it completes. Within a
+        /// [`DeadlineScope`], `fun` is skipped once the scope's deadline passed.
+        ///
+        /// ```
+        /// use task_system::{TaskBase, TaskSystem};
+        ///
+        /// let system = TaskSystem::new(1);
+        /// let mut task = system.run(|| "21").then(|text| text.parse::<i32>().unwrap() * 2);
+        /// task.wait();
+        /// assert_eq!(task.value(), Ok(42));
+        /// ```
+        pub fn then<F, P>(self, fun: F) -> Task<P>
+            where F: FnOnce(O) -> P + Send + 'static, P: Send + 'static
+        {
+            return self.then_on(Target::Pool, fun);
+        }
+
+        /// Like [`Task::then`] but queues `fun` for the given [`Target`].
+        ///
+        /// ```
+        /// use std::time::Duration;
+        /// use task_system::{Target, TaskBase, TaskSystem};
+        ///
+        /// let system = TaskSystem::new(2);
+        /// let mut task = system.run(|| 42)
+        ///     .then_on(Target::SameWorker, |n| (n, TaskSystem::current_worker_index()))
+        ///     .then_on(Target::MainThread, |(n, worker)| (n, worker, TaskSystem::current_worker_index()));
+        /// while !task.completed() {
+        ///     system.pump_main_tasks(Duration::from_millis(1));
+        /// }
+        /// let (n, worker, main) = task.value().unwrap();
+        /// assert_eq!(n, 42);
+        /// assert!(worker.is_some());
+        /// assert_eq!(main, None);
+        /// ```
+        pub fn then_on<F, P>(self, target: Target, fun: F) -> Task<P>
+            where F: FnOnce(O) -> P + Send + 'static, P: Send + 'static
+        {
+            let deadline = DeadlineScope::current();
+            let latch = DependencyLatch::new();
+            latch.depend_on(&self);
+
+            let pool = self.pool.clone();
+            let source_state = self.shared_state.clone();
+            let mut source = self;
+            let (task, job) = pool.prepare(move |_| Ok(fun(source.take_input()?)));
+            task.shared_state.set_status(TaskStatus::Waiting);
+
+            let shared_state = task.shared_state.clone();
+            latch.arm(Job::new(move || {
+                if deadline::expired(deadline) {
+                    return shared_state.time_out();
                 }
-            });
-            
+                if !shared_state.transition(TaskStatus::Waiting, TaskStatus::Queued) {
+                    return;
+                }
+                let job = Job::new(job);
+                match target {
+                    Target::Pool => pool.execute(job),
+                    Target::SameWorker => {
+                        let worker = source_state.lock().unpoison().worker;
+                        pool.execute_near(worker, job);
+                    },
+                    // Dropping the job cancels the task once the system is gone.
+                    Target::MainThread => if let Some(main_tasks) = pool.main_tasks.upgrade() {
+                        main_tasks.lock().unpoison().push_back(job);
+                    },
+                }
+            }));
+
             return task;
         }
-    }
 
-    // *********************************************************************************************
-    #[cfg(test)]
-    mod tests {
-        use std::{
-            thread,
-            time,
-            sync::{Barrier},
-        };
-        use super::*;
-    
-        #[test]
-        fn run_single_task() {
-            let mut system = TaskSystem::new(1);
+        /// Queues `fun` with the output of this task as soon as it completes and completes with the
+        /// output of the task `fun` returns. If this task already completed, `fun` runs right away
+        /// on the calling thread instead.
+        ///
+        /// ```
+        /// use task_system::TaskSystem;
+        ///
+        /// let system = TaskSystem::new(2);
+        /// let factor = system.run(|| 7);
+        /// let task = system.run(|| 6).and_then(move |n| factor.map(move |m| n * m));
+        /// assert_eq!(task.join(), Ok(42));
+        /// ```
+        pub fn and_then<F, P>(self, fun: F) -> Task<P>
+            where F: FnOnce(O) -> Task<P> + Send + 'static, P: Send + 'static
+        {
+            let task = Task::<P>::new(self.pool.clone(), None);
+            task.shared_state.set_status(TaskStatus::Waiting);
+            let pending = PendingTask::new(task.shared_state.clone());
+            let deadline = DeadlineScope::current();
 
-            let mut task = system.run(move|| {
-                return 1;
-            });
+            let mut source = self.clone();
+            let step = move |pending: PendingTask<P>| {
+                let shared_state = pending.take();
+                if !shared_state.start() {
+                    return;
+                }
 
-            task.wait();
-            assert_eq!(task.status(), TaskStatus::Completed);
-            assert_eq!(task.value().unwrap(), 1);
-            assert_eq!(task.value(), Err(GetValueError::AlreadyTaken));
-        }
+                let inner = match catch_panic(move || Ok(fun(source.take_input()?))) {
+                    Ok(inner) => inner,
+                    Err(error) => return shared_state.finish(Err(error)),
+                };
 
-        #[test]
-        fn run_single_blocking_task() {
-            let mut system = TaskSystem::new(1);
+                let inner_state = inner.shared_state.clone();
+                inner.on_completed(Job::new(move || {
+                    match inner_state.take_result() {
+                        Ok(output) => shared_state.complete(output),
+                        Err(error) => shared_state.finish(Err(error)),
+                    }
+                }));
+            };
 
-            let barrier = Arc::new(Barrier::new(2));
-            let barrier_clone = barrier.clone();
-            let mut task = system.run(move|| {
-                barrier_clone.wait();
-            });
+            // The output is there already, so it isn't worth a round-trip through the queue.
+            if self.status() == TaskStatus::Completed {
+                if deadline::expired(deadline) {
+                    pending.take().time_out();
+                } else if pending.shared_state().transition(TaskStatus::Waiting, TaskStatus::Queued) {
+                    step(pending);
+                }
+                return task;
+            }
 
-            thread::sleep(time::Duration::from_secs(1));
-            assert_eq!(task.status(), TaskStatus::Running);
-            barrier.wait();
-            task.wait();
+            let latch = DependencyLatch::new();
+            latch.depend_on(&self);
+            let pool = self.pool.clone();
+            latch.arm(Job::new(move || {
+                if deadline::expired(deadline) {
+                    return pending.take().time_out();
+                }
+                if !pending.shared_state().transition(TaskStatus::Waiting, TaskStatus::Queued) {
+                    return;
+                }
+                pool.execute(Job::new(move || step(pending)));
+            }));
+
+            return task;
+        }
+
+        /// Derives a task completing with `fun` applied to this task's output. Unlike
+        /// [`Task::then`], `fun` isn't queued but runs on the thread completing this task, or right
+        /// away on the calling thread if this task already completed, so it should be cheap. A failed
+        /// or cancelled task passes its error on.
+        ///
+        /// ```
+        /// use task_system::{Task, TaskBase, TaskSystem};
+        ///
+        /// let system = TaskSystem::new(1);
+        /// let width = system.run(|| 4).map(|n| n * 2);
+        /// let height = system.run(|| 3);
+        /// let mut area = Task::zip(width, height).map(|(w, h)| w * h);
+        /// area.wait();
+        /// assert_eq!(area.value(), Ok(24));
+        /// ```
+        pub fn map<F, P>(self, fun: F) -> Task<P>
+            where F: FnOnce(O) -> P + Send + 'static, P: Send + 'static
+        {
+            let (task, pending) = self.derive();
+            let deadline = DeadlineScope::current();
+            let mut source = self.clone();
+            self.on_completed(Job::new(move || {
+                let shared_state = pending.take();
+                if deadline::expired(deadline) {
+                    return shared_state.time_out();
+                }
+                match catch_panic(move || Ok(fun(source.take_input()?))) {
+                    Ok(output) => shared_state.complete(output),
+                    Err(error) => shared_state.finish(Err(error)),
+                }
+            }));
+            return task;
+        }
+
+        /// Derives a task completing with the outputs of both tasks once both completed, or with
+        /// the first error among them.
+        pub fn zip<P>(first: Task<O>, second: Task<P>) -> Task<(O, P)> where P: Send + 'static {
+            let (task, pending) = first.derive();
+
+            let latch = DependencyLatch::new();
+            latch.depend_on(&first);
+            latch.depend_on(&second);
+            let (mut first, mut second) = (first, second);
+            latch.arm(Job::new(move || {
+                let shared_state = pending.take();
+                let outputs = first.take_input().and_then(|first| Ok((first, second.take_input()?)));
+                match outputs {
+                    Ok(outputs) => shared_state.complete(outputs),
+                    Err(error) => shared_state.finish(Err(error)),
+                }
+            }));
+            return task;
+        }
+
+        // Task finished by a continuation of this one, waiting until then.
+        fn derive<P>(&self) -> (Task<P>, PendingTask<P>) {
+            let task = Task::<P>::new(self.pool.clone(), None);
+            task.shared_state.set_status(TaskStatus::Waiting);
+            let pending = PendingTask::new(task.shared_state.clone());
+            return (task, pending);
+        }
+    }
+
+    impl<O> Task<Task<O>> where O: Send + 'static {
+        /// Derives a task completing with the output of the task this one completes with.
+        pub fn flatten(self) -> Task<O> {
+            let (task, pending) = self.derive();
+            let mut outer = self.clone();
+            self.on_completed(Job::new(move || {
+                let shared_state = pending.take();
+                let inner = match outer.take_input() {
+                    Ok(inner) => inner,
+                    Err(error) => return shared_state.finish(Err(error)),
+                };
+
+                let inner_state = inner.shared_state.clone();
+                inner.on_completed(Job::new(move || {
+                    match inner_state.take_result() {
+                        Ok(output) => shared_state.complete(output),
+                        Err(error) => shared_state.finish(Err(error)),
+                    }
+                }));
+            }));
+            return task;
+        }
+    }
+
+    impl<T, E> Task<Result<T, E>> where T: Send + 'static, E: Send + 'static {
+        /// Like [`Task::join`] but flattens the error the task returned into the one of the task.
+        ///
+        /// ```
+        /// use task_system::{TaskError, TaskSystem};
+        ///
+        /// let system = TaskSystem::new(1);
+        /// assert_eq!(system.run(|| "42".parse::<i32>().map_err(|_| "not a number")).try_join(), Ok(42));
+        /// assert_eq!(system.run(|| "4x".parse::<i32>().map_err(|_| "not a number")).try_join(), Err(TaskError::Err("not a number")));
+        /// ```
+        pub fn try_join(self) -> Result<T, TaskError<E>> {
+            return self.join()?.map_err(TaskError::Err);
+        }
+
+        /// Like [`Task::map`] but only applies `fun` to an `Ok` output, passing an error on as is.
+        pub fn map_ok<F, U>(self, fun: F) -> Task<Result<U, E>>
+            where F: FnOnce(T) -> U + Send + 'static, U: Send + 'static
+        {
+            return self.map(move |result| result.map(fun));
+        }
+
+        /// Like [`Task::then`] but only calls `fun` with an `Ok` output, passing an error on as is.
+        ///
+        /// ```
+        /// use task_system::{TaskError, TaskSystem};
+        ///
+        /// let system = TaskSystem::new(1);
+        /// let half = |n: i32| if n % 2 == 0 { Ok(n / 2) } else { Err(n) };
+        /// assert_eq!(system.run(move || half(84)).then_ok(half).try_join(), Ok(21));
+        /// assert_eq!(system.run(move || half(42)).then_ok(half).then_ok(half).try_join(), Err(TaskError::Err(21)));
+        /// ```
+        pub fn then_ok<F, U>(self, fun: F) -> Task<Result<U, E>>
+            where F: FnOnce(T) -> Result<U, E> + Send + 'static, U: Send + 'static
+        {
+            return self.then(move |result| result.and_then(fun));
+        }
+    }
+
+    struct TaskOutputRef<'a, O> {
+        mutex: MutexGuard<'a, TaskState<O>>,
+    }
+
+    impl<O> Deref for TaskOutputRef<'_, O> {
+        type Target = O;
+
+        fn deref(&self) -> &O {
+            return self.mutex.output.as_ref().unwrap();
+        }
+    }
+
+    impl<O> TaskBase for Task<O> {
+        fn status(&self) -> TaskStatus {
+            return self.shared_state.status();
+        }
+
+        fn queued(&self) -> bool {
+            return self.status() == TaskStatus::Queued;
+        }
+
+        fn running(&self) -> bool {
+            return self.status() == TaskStatus::Running;
+        }
+
+        fn completed(&self) -> bool {
+            return self.status() == TaskStatus::Completed;
+        }
+
+        fn wait(&mut self) {
+            if self.status().is_finished() {
+                return;
+            }
+            // Tasks queued by the running one of an inline system only run once it returns, unless
+            // it runs them while it waits.
+            if self.pool.inline() {
+                let pool = self.pool.clone();
+                return pool.help_until(|| pool.help_once(), || self.status().is_finished());
+            }
+
+            let mut mutex = self.shared_state.lock().unpoison();
+            while !self.shared_state.status().is_finished() {
+                mutex.waiters += 1;
+                mutex = self.shared_state.completed.wait(mutex).unpoison();
+                mutex.waiters -= 1;
+            }
+        }
+
+        fn wait_deadline(&mut self, deadline: Instant) -> bool {
+            let mut mutex = self.shared_state.lock().unpoison();
+            while !self.shared_state.status().is_finished() {
+                let now = Instant::now();
+                if now >= deadline {
+                    return false;
+                }
+                mutex.waiters += 1;
+                mutex = self.shared_state.completed.wait_timeout(mutex, deadline - now).unpoison().0;
+                mutex.waiters -= 1;
+            }
+            return true;
+        }
+    }
+
+    /// Awaiting a task yields the same result as [`Task::value`] once it finished, so tasks can be
+    /// awaited from any async executor.
+    ///
+    /// ```
+    /// use task_system::{GetValueError, TaskSystem};
+    ///
+    /// async fn answer(system: &TaskSystem) -> Result<i32, GetValueError> {
+    ///     return system.run(|| 6 * 7).await;
+    /// }
+    /// ```
+    impl<O> Future for Task<O> {
+        type Output = Result<O, GetValueError>;
+
+        fn poll(self: Pin<&mut Self>, context: &mut Context<'_>) -> Poll<Self::Output> {
+            let mut mutex = self.shared_state.lock().unpoison();
+            if !self.shared_state.status().is_finished() {
+                if !mutex.wakers.iter().any(|waker| waker.will_wake(context.waker())) {
+                    mutex.wakers.push(context.waker().clone());
+                }
+                return Poll::Pending;
+            }
+            drop(mutex);
+
+            return Poll::Ready(self.shared_state.take_result());
+        }
+    }
+
+    impl<O> Dependency for Task<O> {
+        fn on_completed(&self, continuation: Job) {
+            {
+                let mut mutex = self.shared_state.lock().unpoison();
+                if !self.shared_state.status().is_finished() {
+                    mutex.continuations.push(continuation);
+                    return;
+                }
+            }
+
+            continuation.run();
+        }
+    }
+
+    // *********************************************************************************************
+    struct DependencyLatch {
+        remaining: AtomicUsize,
+        launch: Mutex<Option<Job>>,
+    }
+
+    impl DependencyLatch {
+        fn new() -> Arc<Self> {
+            // The extra count is held until `arm` so the latch can't fire before it has a job.
+            return Arc::new(Self{
+                remaining: AtomicUsize::new(1),
+                launch: Mutex::new(None),
+            });
+        }
+
+        fn depend_on(self: &Arc<Self>, task: &dyn Dependency) {
+            self.remaining.fetch_add(1, Ordering::AcqRel);
+            let latch = self.clone();
+            task.on_completed(Job::new(move || latch.release()));
+        }
+
+        fn arm(&self, launch: Job) {
+            *self.launch.lock().unpoison() = Some(launch);
+            self.release();
+        }
+
+        fn release(&self) {
+            if self.remaining.fetch_sub(1, Ordering::AcqRel) == 1 {
+                let launch = self.launch.lock().unpoison().take();
+                if let Some(launch) = launch {
+                    launch.run();
+                }
+            }
+        }
+    }
+
+    // *********************************************************************************************
+    // Indices of finished group members, in the order they finished.
+    struct CompletionQueue {
+        finished: Mutex<VecDeque<usize>>,
+        condvar: Condvar,
+    }
+
+    impl CompletionQueue {
+        fn new() -> Arc<Self> {
+            return Arc::new(Self{
+                finished: Mutex::new(VecDeque::new()),
+                condvar: Condvar::new(),
+            });
+        }
+
+        fn push(&self, index: usize) {
+            self.finished.lock().unpoison().push_back(index);
+            self.condvar.notify_one();
+        }
+
+        fn pop(&self) -> usize {
+            let mut mutex = self.finished.lock().unpoison();
+            loop {
+                if let Some(index) = mutex.pop_front() {
+                    return index;
+                }
+                mutex = self.condvar.wait(mutex).unpoison();
+            }
+        }
+    }
+
+    // *********************************************************************************************
+    /// Set of tasks waited on together. Members report to a single condvar when they finish, so
+    /// waiting on the group costs one blocked thread no matter how many tasks it holds.
+    ///
+    /// ```
+    /// use task_system::{TaskGroup, TaskSystem};
+    ///
+    /// let system = TaskSystem::new(2);
+    /// let group: TaskGroup<i32> = (0..4).map(|i| system.run(move || i * i)).collect();
+    /// assert_eq!(group.join(), Ok(vec![0, 1, 4, 9]));
+    /// ```
+    pub struct TaskGroup<O> {
+        tasks: Vec<Option<Task<O>>>,
+        completions: Arc<CompletionQueue>,
+        pending: usize,
+    }
+
+    impl<O> TaskGroup<O> {
+        /// Creates an empty group.
+        pub fn new() -> Self {
+            return Self{
+                tasks: Vec::new(),
+                completions: CompletionQueue::new(),
+                pending: 0,
+            };
+        }
+
+        /// Adds a task to the group. Its index is the number of tasks pushed before it.
+        pub fn push(&mut self, task: Task<O>) {
+            let index = self.tasks.len();
+            let completions = self.completions.clone();
+            task.on_completed(Job::new(move || completions.push(index)));
+
+            self.tasks.push(Some(task));
+            self.pending += 1;
+        }
+
+        /// Blocks until the next member finishes and returns its index and output. Returns `None` once
+        /// every member was returned.
+        pub fn next_completed(&mut self) -> Option<(usize, Result<O, GetValueError>)> {
+            if self.pending == 0 {
+                return None;
+            }
+
+            let index = self.completions.pop();
+            self.pending -= 1;
+            let mut task = self.tasks[index].take().unwrap();
+            return Some((index, task.value()));
+        }
+
+        /// Blocks until every member finishes and returns the outputs in the order the tasks were
+        /// pushed, leaving out those already returned by [`TaskGroup::next_completed`]. Fails with the
+        /// error of the first task that didn't complete.
+        pub fn join(mut self) -> Result<Vec<O>, GetValueError> {
+            while self.pending > 0 {
+                self.completions.pop();
+                self.pending -= 1;
+            }
+
+            return self.tasks.into_iter()
+                .flatten()
+                .map(|mut task| task.value())
+                .collect();
+        }
+
+        /// Like [`TaskGroup::join`] but runs queued tasks on the calling thread while waiting, see
+        /// [`Task::wait_and_help`].
+        pub fn join_and_help(mut self) -> Result<Vec<O>, GetValueError> {
+            for task in self.tasks.iter_mut().flatten() {
+                task.wait_and_help();
+            }
+            return self.join();
+        }
+
+        fn into_remaining(self) -> Vec<Task<O>> {
+            return self.tasks.into_iter().flatten().collect();
+        }
+    }
+
+    impl<T, E> TaskGroup<Result<T, E>> {
+        /// Like [`TaskGroup::join`] for tasks returning a `Result`, but returns as soon as a member
+        /// fails or returns an error, cancelling the members that didn't finish yet.
+        ///
+        /// ```
+        /// use task_system::{TaskError, TaskGroup, TaskSystem};
+        ///
+        /// let system = TaskSystem::new(2);
+        /// let group: TaskGroup<Result<u32, String>> = (0..4).map(|i| system.run(move || {
+        ///     if i == 2 {
+        ///         return Err(format!("task {} failed", i));
+        ///     }
+        ///     std::thread::sleep(std::time::Duration::from_millis(10 * i as u64));
+        ///     return Ok(i);
+        /// })).collect();
+        /// assert_eq!(group.join_ok(), Err(TaskError::Err(String::from("task 2 failed"))));
+        /// ```
+        pub fn join_ok(mut self) -> Result<Vec<T>, TaskError<E>> {
+            let mut outputs: Vec<Option<T>> = self.tasks.iter().map(|_| None).collect();
+            while let Some((index, result)) = self.next_completed() {
+                match result.map_err(TaskError::Task).and_then(|result| result.map_err(TaskError::Err)) {
+                    Ok(output) => outputs[index] = Some(output),
+                    Err(error) => {
+                        for task in self.tasks.iter().flatten() {
+                            task.cancel();
+                        }
+                        return Err(error);
+                    },
+                }
+            }
+            return Ok(outputs.into_iter().flatten().collect());
+        }
+    }
+
+    impl<O> Default for TaskGroup<O> {
+        fn default() -> Self {
+            return Self::new();
+        }
+    }
+
+    impl<O> FromIterator<Task<O>> for TaskGroup<O> {
+        fn from_iter<I>(tasks: I) -> Self where I: IntoIterator<Item = Task<O>> {
+            let mut group = Self::new();
+            for task in tasks {
+                group.push(task);
+            }
+            return group;
+        }
+    }
+
+    /// Waits for every task and returns the outputs in submission order; see [`TaskGroup::join`].
+    pub fn join_all<O>(tasks: Vec<Task<O>>) -> Result<Vec<O>, GetValueError> {
+        return tasks.into_iter().collect::<TaskGroup<O>>().join();
+    }
+
+    /// Waits for every task and returns the `Ok` outputs in submission order, or the first error
+    /// after cancelling the rest; see [`TaskGroup::join_ok`].
+    pub fn join_all_ok<T, E>(tasks: Vec<Task<Result<T, E>>>) -> Result<Vec<T>, TaskError<E>> {
+        return tasks.into_iter().collect::<TaskGroup<Result<T, E>>>().join_ok();
+    }
+
+    /// Blocks until the first of `tasks` finishes, successfully or not, and returns its index. Picks
+    /// the lowest index if several already finished. Panics if `tasks` is empty.
+    ///
+    /// ```
+    /// use task_system::{TaskSystem, wait_any};
+    ///
+    /// let system = TaskSystem::new(2);
+    /// let mut number = system.run(|| 42);
+    /// let mut text = system.run(|| {
+    ///     std::thread::sleep(std::time::Duration::from_secs(1));
+    ///     return "late";
+    /// });
+    /// assert_eq!(wait_any(&mut [number.as_base(), text.as_base()]), 0);
+    /// ```
+    pub fn wait_any(tasks: &mut [&mut dyn TaskBase]) -> usize {
+        assert!(!tasks.is_empty(), "wait_any called on an empty slice of tasks");
+
+        let completions = CompletionQueue::new();
+        for (index, task) in tasks.iter().enumerate() {
+            let completions = completions.clone();
+            task.on_completed(Job::new(move || completions.push(index)));
+        }
+        return completions.pop();
+    }
+
+    // *********************************************************************************************
+    /// Waiting helpers for collections of tasks.
+    pub trait TaskIteratorExt<O>: Sized {
+        /// Waits for every task and returns the outputs in the original order.
+        fn await_all(self) -> Result<Vec<O>, GetValueError>;
+        /// Returns an iterator yielding the outputs in the order the tasks complete.
+        fn await_as_completed(self) -> AsCompleted<O>;
+        /// Waits for the first task to complete, returning its output, its index and the rest of the
+        /// tasks. Panics if the collection is empty.
+        fn await_any(self) -> (Result<O, GetValueError>, usize, Vec<Task<O>>);
+    }
+
+    impl<O, I> TaskIteratorExt<O> for I where I: IntoIterator<Item = Task<O>> {
+        fn await_all(self) -> Result<Vec<O>, GetValueError> {
+            return self.into_iter().collect::<TaskGroup<O>>().join();
+        }
+
+        fn await_as_completed(self) -> AsCompleted<O> {
+            return AsCompleted{ group: self.into_iter().collect() };
+        }
+
+        fn await_any(self) -> (Result<O, GetValueError>, usize, Vec<Task<O>>) {
+            let mut group: TaskGroup<O> = self.into_iter().collect();
+            let (index, result) = group.next_completed()
+                .expect("await_any called on an empty collection of tasks");
+            return (result, index, group.into_remaining());
+        }
+    }
+
+    // *********************************************************************************************
+    /// Iterator returned by [`TaskIteratorExt::await_as_completed`].
+    pub struct AsCompleted<O> {
+        group: TaskGroup<O>,
+    }
+
+    impl<O> Iterator for AsCompleted<O> {
+        type Item = Result<O, GetValueError>;
+
+        fn next(&mut self) -> Option<Self::Item> {
+            return self.group.next_completed().map(|(_, result)| result);
+        }
+
+        fn size_hint(&self) -> (usize, Option<usize>) {
+            return (self.group.pending, Some(self.group.pending));
+        }
+    }
+
+    // *********************************************************************************************
+    mod blocking;
+    mod cache;
+    mod child;
+    mod deadline;
+    mod job;
+    mod local;
+    mod memory;
+    mod metrics;
+    mod mpmc;
+    mod pipeline;
+    mod policy;
+    mod profile;
+    mod progress;
+    mod registry;
+    mod remote;
+    mod replay;
+    mod scheduler;
+    mod store;
+    mod stream;
+    mod sync;
+    mod timeline;
+    mod timer;
+    mod trace;
+    mod watchdog;
+
+    use self::job::Job;
+
+    pub use self::child::ChildSystem;
+    pub use self::deadline::DeadlineScope;
+    pub use self::local::TaskLocal;
+    pub use self::memory::TrackingAllocator;
+    pub use self::metrics::Metrics;
+    pub use self::pipeline::{Pipeline, PipelineHandle};
+    pub use self::policy::{DeadlineScheduler, FifoScheduler, LifoScheduler, PriorityScheduler, ScheduledJob, Scheduler, WeightedFairScheduler};
+    pub use self::profile::{TaskProfile, TaskTimings};
+    pub use self::progress::Progress;
+    pub use self::registry::{StatusChange, TaskId, TaskInfo};
+    pub use self::remote::{Executor, RemoteTask, Reply, TaskDescriptor, TaskOutput};
+    #[cfg(feature = "serde")]
+    pub use self::remote::SerdeOutput;
+    pub use self::replay::{RecordingScheduler, ReplayScheduler, Schedule, ScheduleEntry, ScheduleRecording};
+    pub use self::store::{FileTaskStore, StoredTask, TaskPayload, TaskStore};
+    #[cfg(feature = "serde")]
+    pub use self::store::SerdePayload;
+    pub use self::stream::{StreamSender, StreamTask};
+    pub use self::timeline::TraceEvent;
+    pub use self::watchdog::{HungTask, heartbeat};
+    use self::blocking::BlockingPool;
+    use self::cache::TaskCache;
+    use self::memory::GroupMemory;
+    use self::registry::{StatusHook, TaskMeta, TaskRegistry};
+    use self::remote::RemoteTasks;
+    use self::scheduler::{ThreadOptions, WorkerPool};
+    use self::store::{DurableTasks, PayloadHandler};
+    use self::timer::Timer;
+    use self::trace::TaskSpan;
+    use self::watchdog::HungTaskHandler;
+
+    impl WorkerPool {
+        // Closures passed to the pool return an error to finish without an output, which is how
+        // dependent tasks pass on the cancellation or failure of their inputs.
+        fn prepare<'a, F, O>(self: &Arc<Self>, fun: F) -> (Task<O>, impl FnOnce() + Send + 'a)
+            where F: FnOnce(CancellationToken) -> Result<O, GetValueError> + Send + 'a, O: Send + 'a
+        {
+            return self.prepare_named(None, fun);
+        }
+
+        fn prepare_named<'a, F, O>(self: &Arc<Self>, name: Option<String>, fun: F) -> (Task<O>, impl FnOnce() + Send + 'a)
+            where F: FnOnce(CancellationToken) -> Result<O, GetValueError> + Send + 'a, O: Send + 'a
+        {
+            let task = Task::<O>::new(self.clone(), name);
+            let pending = PendingTask::new(task.shared_state.clone());
+            let span = TaskSpan::new(&task.shared_state.meta);
+
+            let job = move || span.in_scope(move || {
+                let shared_state = pending.take();
+                if !shared_state.start() {
+                    return;
+                }
+
+                let token = shared_state.cancellation.clone();
+                let meta = &shared_state.meta;
+                match memory::charged(meta, || watchdog::run_as(meta, move || catch_panic(move || local::isolated(move || fun(token))))) {
+                    Ok(output) => shared_state.complete(output),
+                    Err(error) => shared_state.finish(Err(error)),
+                }
+            });
+
+            return (task, job);
+        }
+
+        fn submit<F, O>(self: &Arc<Self>, fun: F) -> Task<O>
+            where F: FnOnce(CancellationToken) -> Result<O, GetValueError> + Send + 'static, O: Send + 'static
+        {
+            return self.submit_with_priority(Priority::Normal, fun);
+        }
+
+        fn submit_with_priority<F, O>(self: &Arc<Self>, priority: Priority, fun: F) -> Task<O>
+            where F: FnOnce(CancellationToken) -> Result<O, GetValueError> + Send + 'static, O: Send + 'static
+        {
+            return self.submit_named(priority, None, fun);
+        }
+
+        fn submit_named<F, O>(self: &Arc<Self>, priority: Priority, name: Option<String>, fun: F) -> Task<O>
+            where F: FnOnce(CancellationToken) -> Result<O, GetValueError> + Send + 'static, O: Send + 'static
+        {
+            let (task, job) = self.prepare_named(name, fun);
+            task.shared_state.set_status(TaskStatus::Queued);
+            self.execute_with_priority(priority, Job::new(job));
+            return task;
+        }
+
+        fn run_when<F, O>(self: &Arc<Self>, latch: Arc<DependencyLatch>, fun: F) -> Task<O>
+            where F: FnOnce(CancellationToken) -> Result<O, GetValueError> + Send + 'static, O: Send + 'static
+        {
+            let (task, job) = self.prepare(fun);
+            task.shared_state.set_status(TaskStatus::Waiting);
+
+            let shared_state = task.shared_state.clone();
+            let pool = self.clone();
+            latch.arm(Job::new(move || {
+                if shared_state.transition(TaskStatus::Waiting, TaskStatus::Queued) {
+                    pool.execute(Job::new(job));
+                }
+            }));
+
+            return task;
+        }
+
+        fn run_at<F, O>(self: &Arc<Self>, timer: &Timer, at: Instant, fun: F) -> Task<O>
+            where F: FnOnce(CancellationToken) -> Result<O, GetValueError> + Send + 'static, O: Send + 'static
+        {
+            let (task, job) = self.prepare(fun);
+            task.shared_state.set_status(TaskStatus::Waiting);
+
+            let shared_state = task.shared_state.clone();
+            let pool = self.clone();
+            timer.schedule(at, Job::new(move || {
+                if shared_state.transition(TaskStatus::Waiting, TaskStatus::Queued) {
+                    pool.execute(Job::new(job));
+                }
+            }));
+
+            return task;
+        }
+    }
+
+    // *********************************************************************************************
+    macro_rules! run_after_fn {
+        ($name:ident, $($task:ident: $input:ident),+) => {
+            /// Runs `fun` with the outputs of all listed tasks once every one of them has completed.
+            #[allow(clippy::too_many_arguments)]
+            pub fn $name<$($input,)+ F, O>(&self, $($task: Task<$input>,)+ fun: F) -> Task<O>
+                where $($input: Send + 'static,)+
+                    F: FnOnce(($($input,)+)) -> O + Send + 'static,
+                    O: Send + 'static
+            {
+                let latch = DependencyLatch::new();
+                $(latch.depend_on(&$task);)+
+
+                return self.pool.run_when(latch, move |_| {
+                    $(let mut $task = $task;)+
+                    let inputs = ($($task.take_input()?,)+);
+                    return Ok(fun(inputs));
+                });
+            }
+        };
+    }
+
+    // *********************************************************************************************
+    /// Handed to tasks started with [`TaskSystem::run_with_ctx`] to fork subtasks.
+    pub struct TaskContext {
+        pool: Arc<WorkerPool>,
+        token: CancellationToken,
+    }
+
+    impl TaskContext {
+        /// Queues `fun` as a subtask. It gets a context of its own, so work can be split
+        /// recursively. Cancelling the current task cancels the subtask too, along with everything
+        /// it forked in turn.
+        pub fn spawn<F, O>(&self, fun: F) -> SubTask<O>
+            where F: FnOnce(&TaskContext) -> O + Send + 'static, O: Send + 'static
+        {
+            let pool = self.pool.clone();
+            let task = self.pool.submit(move |token| {
+                return Ok(fun(&TaskContext{ pool, token }));
+            });
+
+            let child = Arc::downgrade(&task.shared_state);
+            self.token.on_cancel(Job::new(move || {
+                if let Some(child) = Weak::upgrade(&child) {
+                    child.cancel();
+                }
+            }));
+            return SubTask{ task };
+        }
+
+        /// Whether the current task was cancelled, directly or through one of its parents.
+        pub fn is_cancelled(&self) -> bool {
+            return self.token.is_cancelled();
+        }
+
+        /// Runs one other queued task on this worker, if there is one, so a long task gives the
+        /// others a turn. Returns whether it did. The task runs on top of the current one's stack,
+        /// tasks that yield often should return [`Step::Yield`] from
+        /// [`TaskSystem::run_resumable`] instead.
+        pub fn yield_now(&self) -> bool {
+            return self.pool.help_once();
+        }
+    }
+
+    /// What a step of a task started with [`TaskSystem::run_resumable`] came to.
+    #[derive(Debug, PartialEq, Eq, Copy, Clone)]
+    pub enum Step<O> {
+        /// The task has more to do, and is queued again behind the tasks queued so far.
+        Yield,
+        /// The task completed with this output.
+        Done(O),
+    }
+
+    // Runs a step of a resumable task, which is in the running status, and queues the next one as
+    // long as it yields.
+    fn resume<F, O>(pool: Arc<WorkerPool>, shared_state: Arc<TaskSharedState<O>>, mut fun: F)
+        where F: FnMut(&TaskContext) -> Step<O> + Send + 'static, O: Send + 'static
+    {
+        let ctx = TaskContext{ pool: pool.clone(), token: shared_state.cancellation.clone() };
+        let step = catch_panic(|| Ok(local::isolated(|| fun(&ctx))));
+        match step {
+            Ok(Step::Done(output)) => shared_state.complete(output),
+            Ok(Step::Yield) if ctx.is_cancelled() => shared_state.finish(Err(GetValueError::Cancelled)),
+            Ok(Step::Yield) => {
+                if !shared_state.transition(TaskStatus::Running, TaskStatus::Queued) {
+                    return;
+                }
+                let pending = PendingTask::new(shared_state);
+                let next_pool = pool.clone();
+                pool.execute_last(Job::new(move || {
+                    let shared_state = pending.take();
+                    if shared_state.transition(TaskStatus::Queued, TaskStatus::Running) {
+                        resume(next_pool, shared_state, fun);
+                    }
+                }));
+            },
+            Err(error) => shared_state.finish(Err(error)),
+        }
+    }
+
+    /// Handle to a task forked with [`TaskContext::spawn`]. Dropping it without joining cancels the
+    /// subtask, as nobody is left to use its output.
+    pub struct SubTask<O> {
+        task: Task<O>,
+    }
+
+    impl<O> SubTask<O> {
+        /// Waits for the subtask and returns its output, propagating its panic. A worker waiting
+        /// here runs other queued tasks in the meantime, so joining never starves the pool.
+        pub fn join(mut self) -> O {
+            return join_helping(&mut self.task);
+        }
+    }
+
+    fn join_helping<O>(task: &mut Task<O>) -> O {
+        let pool = task.pool.clone();
+        pool.help_until(|| pool.help_once(), || task.status().is_finished());
+
+        match task.value() {
+            Ok(output) => return output,
+            Err(GetValueError::Panicked(message)) => panic!("subtask panicked: {}", message),
+            Err(error) => panic!("subtask did not complete: {:?}", error),
+        }
+    }
+
+    impl<O> Drop for SubTask<O> {
+        fn drop(&mut self) {
+            if !self.task.status().is_finished() {
+                self.task.cancel();
+            }
+        }
+    }
+
+    impl<O> TaskBase for SubTask<O> {
+        fn status(&self) -> TaskStatus {
+            return self.task.status();
+        }
+
+        fn queued(&self) -> bool {
+            return self.task.queued();
+        }
+
+        fn running(&self) -> bool {
+            return self.task.running();
+        }
+
+        fn completed(&self) -> bool {
+            return self.task.completed();
+        }
+
+        fn wait(&mut self) {
+            self.task.wait();
+        }
+
+        fn wait_deadline(&mut self, deadline: Instant) -> bool {
+            return self.task.wait_deadline(deadline);
+        }
+    }
+
+    impl<O> Dependency for SubTask<O> {
+        fn on_completed(&self, continuation: Job) {
+            self.task.on_completed(continuation);
+        }
+    }
+
+    // *********************************************************************************************
+    thread_local! {
+        // Finalized outputs of the local tasks owned by this thread, until their handle takes them.
+        static LOCAL_RESULTS: RefCell<HashMap<TaskId, Box<dyn Any>>> = RefCell::new(HashMap::new());
+    }
+
+    /// Handle to a task started with [`TaskSystem::run_with_local_result`], whose output doesn't
+    /// need to be `Send`. The handle stays on the thread that created it, which is the one its
+    /// output is built on.
+    pub struct LocalTask<L> {
+        // Finishes once the output was built.
+        task: Task<()>,
+        key: TaskId,
+        dropped: Arc<AtomicBool>,
+        local: PhantomData<*const L>,
+    }
+
+    impl<L> LocalTask<L> where L: 'static {
+        /// Takes the output once the task completed, see [`Task::value`].
+        pub fn value(&mut self) -> Result<L, GetValueError> {
+            self.task.value()?;
+            let output = LOCAL_RESULTS.with(|results| results.borrow_mut().remove(&self.key));
+            return Ok(*output.unwrap().downcast::<L>().unwrap());
+        }
+
+        /// Cancels the task, see [`Task::cancel`].
+        pub fn cancel(&self) {
+            self.task.cancel();
+        }
+    }
+
+    impl<L> Drop for LocalTask<L> {
+        fn drop(&mut self) {
+            self.dropped.store(true, Ordering::Release);
+            // Dropped outside of the borrow, as the output's destructor may reach for the map.
+            let output = LOCAL_RESULTS.try_with(|results| results.borrow_mut().remove(&self.key));
+            drop(output);
+        }
+    }
+
+    impl<L> TaskBase for LocalTask<L> {
+        fn status(&self) -> TaskStatus {
+            return self.task.status();
+        }
+
+        fn queued(&self) -> bool {
+            return self.task.queued();
+        }
+
+        fn running(&self) -> bool {
+            return self.task.running();
+        }
+
+        fn completed(&self) -> bool {
+            return self.task.completed();
+        }
+
+        fn wait(&mut self) {
+            self.task.wait();
+        }
+
+        fn wait_deadline(&mut self, deadline: Instant) -> bool {
+            return self.task.wait_deadline(deadline);
+        }
+    }
+
+    impl<L> Dependency for LocalTask<L> {
+        fn on_completed(&self, continuation: Job) {
+            self.task.on_completed(continuation);
+        }
+    }
+
+    // *********************************************************************************************
+    struct ScopeTracker {
+        running: Mutex<usize>,
+        finished: Condvar,
+    }
+
+    impl ScopeTracker {
+        fn wait_all(&self) {
+            let mut mutex = self.running.lock().unpoison();
+            while *mutex > 0 {
+                mutex = self.finished.wait(mutex).unpoison();
+            }
+        }
+    }
+
+    // Counts a scoped job as finished once dropped, which also covers jobs discarded by an unwind.
+    struct ScopedJobGuard {
+        tracker: Arc<ScopeTracker>,
+    }
+
+    impl Drop for ScopedJobGuard {
+        fn drop(&mut self) {
+            let mut mutex = self.tracker.running.lock().unpoison();
+            *mutex -= 1;
+            if *mutex == 0 {
+                self.tracker.finished.notify_all();
+            }
+        }
+    }
+
+    /// Spawns tasks that may borrow data living for `'env`, created by [`TaskSystem::scope`].
+    pub struct Scope<'scope, 'env: 'scope> {
+        pool: Arc<WorkerPool>,
+        tracker: Arc<ScopeTracker>,
+        scope: PhantomData<&'scope mut &'scope ()>,
+        env: PhantomData<&'env mut &'env ()>,
+    }
+
+    impl<'scope, 'env> Scope<'scope, 'env> {
+        /// Queues `fun`, which may borrow anything outliving the scope.
+        pub fn run<F, O>(&'scope self, fun: F) -> ScopedTask<'scope, O>
+            where F: FnOnce() -> O + Send + 'scope, O: Send + 'scope
+        {
+            let (task, job) = self.pool.prepare(move |_| Ok(fun()));
+
+            *self.tracker.running.lock().unpoison() += 1;
+            let guard = ScopedJobGuard{ tracker: self.tracker.clone() };
+            let job: Box<dyn FnOnce() + Send + 'scope> = Box::new(move || {
+                let _guard = guard;
+                job();
+            });
+            // SAFETY: `TaskSystem::scope` doesn't return before the guard of every job was dropped,
+            // and the job with everything it borrows is gone by then.
+            let job = unsafe { mem::transmute::<Box<dyn FnOnce() + Send + 'scope>, Box<dyn FnOnce() + Send>>(job) };
+
+            task.shared_state.set_status(TaskStatus::Queued);
+            self.pool.execute(Job::new(job));
+            return ScopedTask{ task, scope: PhantomData };
+        }
+    }
+
+    /// Handle to a task spawned in a [`Scope`].
+    pub struct ScopedTask<'scope, O> {
+        task: Task<O>,
+        scope: PhantomData<&'scope ()>,
+    }
+
+    impl<O> ScopedTask<'_, O> {
+        /// Takes the output of a completed task, see [`Task::value`].
+        pub fn value(&mut self) -> Result<O, GetValueError> {
+            return self.task.value();
+        }
+
+        /// Waits for the task to finish and takes its output.
+        pub fn join(&mut self) -> Result<O, GetValueError> {
+            self.task.wait();
+            return self.task.value();
+        }
+
+        /// Cancels the task, see [`Task::cancel`].
+        pub fn cancel(&self) {
+            self.task.cancel();
+        }
+    }
+
+    impl<O> TaskBase for ScopedTask<'_, O> {
+        fn status(&self) -> TaskStatus {
+            return self.task.status();
+        }
+
+        fn queued(&self) -> bool {
+            return self.task.queued();
+        }
+
+        fn running(&self) -> bool {
+            return self.task.running();
+        }
+
+        fn completed(&self) -> bool {
+            return self.task.completed();
+        }
+
+        fn wait(&mut self) {
+            self.task.wait();
+        }
+
+        fn wait_deadline(&mut self, deadline: Instant) -> bool {
+            return self.task.wait_deadline(deadline);
+        }
+    }
+
+    impl<O> Dependency for ScopedTask<'_, O> {
+        fn on_completed(&self, continuation: Job) {
+            self.task.on_completed(continuation);
+        }
+    }
+
+    // *********************************************************************************************
+    /// How [`TaskSystem::run_periodic_with`] spaces the runs of a periodic task.
+    #[derive(Debug, PartialEq, Eq, Copy, Clone, Default)]
+    pub enum Cadence {
+        /// Runs start `interval` apart. A run that overshoots is followed by the overdue runs
+        /// back to back.
+        #[default]
+        FixedRate,
+        /// Each run starts `interval` after the previous one returned.
+        FixedDelay,
+    }
+
+    /// Handle to a task started with [`TaskSystem::run_periodic`]. Dropping it leaves the task
+    /// running.
+    pub struct PeriodicTask {
+        stopped: Arc<AtomicBool>,
+    }
+
+    impl PeriodicTask {
+        /// Stops scheduling new runs. A run already in progress is finished.
+        pub fn stop(&self) {
+            self.stopped.store(true, Ordering::Release);
+        }
+
+        /// Whether [`PeriodicTask::stop`] was called.
+        pub fn is_stopped(&self) -> bool {
+            return self.stopped.load(Ordering::Acquire);
+        }
+    }
+
+    // The next run of a periodic task. Runs are only scheduled once the previous one returned, so
+    // they never overlap.
+    struct PeriodicRun<F> {
+        fun: F,
+        at: Instant,
+        interval: Duration,
+        cadence: Cadence,
+        stopped: Arc<AtomicBool>,
+        pool: Arc<WorkerPool>,
+        timer: Arc<Timer>,
+        panic_logger: Arc<Mutex<PanicLogger>>,
+    }
+
+    impl<F> PeriodicRun<F> where F: FnMut() + Send + 'static {
+        fn schedule(self) {
+            let timer = self.timer.clone();
+            timer.schedule(self.at, Job::new(move || {
+                if !self.stopped.load(Ordering::Acquire) {
+                    let pool = self.pool.clone();
+                    pool.execute(Job::new(move || self.run()));
+                }
+            }));
+        }
+
+        fn run(mut self) {
+            if self.stopped.load(Ordering::Acquire) {
+                return;
+            }
+
+            if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(&mut self.fun)) {
+                let logger = self.panic_logger.lock().unpoison().clone();
+                logger(&panic_message(payload.as_ref()));
+            }
+
+            self.at = match self.cadence {
+                Cadence::FixedRate => self.at + self.interval,
+                Cadence::FixedDelay => Instant::now() + self.interval,
+            };
+            self.schedule();
+        }
+    }
+
+    // *********************************************************************************************
+    /// How often and how fast [`TaskSystem::run_with_policy`] retries a failing closure.
+    #[derive(Debug, PartialEq, Eq, Copy, Clone)]
+    pub struct RetryPolicy {
+        max_attempts: u32,
+        delay: Duration,
+        multiplier: u32,
+    }
+
+    impl RetryPolicy {
+        /// Makes up to `max_attempts` attempts, waiting `delay` before the first retry and twice as
+        /// long before every further one.
+        pub fn exponential(max_attempts: u32, delay: Duration) -> Self {
+            return Self{ max_attempts, delay, multiplier: 2 };
+        }
+
+        /// Makes up to `max_attempts` attempts, waiting `delay` before every retry.
+        pub fn fixed(max_attempts: u32, delay: Duration) -> Self {
+            return Self{ max_attempts, delay, multiplier: 1 };
+        }
+
+        // Delay before the given retry, counted from 1.
+        fn delay_before(&self, retry: u32) -> Duration {
+            let factor = self.multiplier.saturating_pow(retry - 1);
+            return self.delay.checked_mul(factor).unwrap_or(Duration::MAX);
+        }
+    }
+
+    // One attempt of a task started with `run_with_policy`. A failed attempt waits for the backoff
+    // in the `Waiting` status and queues the next one from the timer thread.
+    struct RetryAttempt<F, T, E> {
+        fun: F,
+        policy: RetryPolicy,
+        attempt: u32,
+        pending: PendingTask<Result<T, E>>,
+        pool: Arc<WorkerPool>,
+        timer: Arc<Timer>,
+    }
+
+    impl<F, T, E> RetryAttempt<F, T, E>
+        where F: FnMut() -> Result<T, E> + Send + 'static, T: Send + 'static, E: Send + 'static
+    {
+        fn execute(self) {
+            let pool = self.pool.clone();
+            pool.execute(Job::new(move || self.run()));
+        }
+
+        fn run(mut self) {
+            let shared_state = self.pending.shared_state().clone();
+            if !shared_state.start() {
+                return;
+            }
+
+            let fun = &mut self.fun;
+            let result = match catch_panic(|| Ok(fun())) {
+                Ok(result) => result,
+                Err(error) => return self.pending.take().finish(Err(error)),
+            };
+
+            let retry = result.is_err()
+                && self.attempt < self.policy.max_attempts
+                && !shared_state.cancellation.is_cancelled();
+            if !retry || !shared_state.transition(TaskStatus::Running, TaskStatus::Waiting) {
+                return self.pending.take().complete(result);
+            }
+
+            let at = Instant::now() + self.policy.delay_before(self.attempt);
+            self.attempt += 1;
+            let timer = self.timer.clone();
+            timer.schedule(at, Job::new(move || {
+                if self.pending.shared_state().transition(TaskStatus::Waiting, TaskStatus::Queued) {
+                    self.execute();
+                }
+            }));
+        }
+    }
+
+    // *********************************************************************************************
+    struct MemoryBudget {
+        total: Option<usize>,
+        in_use: AtomicUsize,
+    }
+
+    impl MemoryBudget {
+        fn new(total: Option<usize>) -> Arc<Self> {
+            return Arc::new(Self{
+                total,
+                in_use: AtomicUsize::new(0),
+            });
+        }
+
+        fn reserve(self: &Arc<Self>, bytes: usize) -> Option<MemoryReservation> {
+            if let Some(total) = self.total {
+                let mut in_use = self.in_use.load(Ordering::Acquire);
+                loop {
+                    if in_use.saturating_add(bytes) > total {
+                        return None;
+                    }
+                    match self.in_use.compare_exchange_weak(in_use, in_use + bytes, Ordering::AcqRel, Ordering::Acquire) {
+                        Ok(_) => break,
+                        Err(current) => in_use = current,
+                    }
+                }
+            }
+
+            return Some(MemoryReservation{ budget: self.clone(), bytes });
+        }
+    }
+
+    struct MemoryReservation {
+        budget: Arc<MemoryBudget>,
+        bytes: usize,
+    }
+
+    impl Drop for MemoryReservation {
+        fn drop(&mut self) {
+            if self.budget.total.is_some() {
+                self.budget.in_use.fetch_sub(self.bytes, Ordering::AcqRel);
+            }
+        }
+    }
+
+    // *********************************************************************************************
+    // Limits the number of tasks submitted with `run` and `try_run` that haven't started yet.
+    struct QueueCapacity {
+        max: Option<usize>,
+        policy: SaturationPolicy,
+        order: AdmissionOrder,
+        state: Mutex<CapacityState>,
+        freed: Condvar,
+    }
+
+    #[derive(Default)]
+    struct CapacityState {
+        queued: usize,
+        // Tickets of the submitters blocked in `reserve`, oldest first.
+        waiting: VecDeque<u64>,
+        next_ticket: u64,
+    }
+
+    impl QueueCapacity {
+        fn new(max: Option<usize>, policy: SaturationPolicy, order: AdmissionOrder) -> Arc<Self> {
+            return Arc::new(Self{
+                max,
+                policy,
+                order,
+                state: Mutex::new(CapacityState::default()),
+                freed: Condvar::new(),
+            });
+        }
+
+        fn is_full(&self, state: &CapacityState) -> bool {
+            return self.max.is_some_and(|max| state.queued >= max);
+        }
+
+        fn try_reserve(self: &Arc<Self>) -> Option<QueueSlot> {
+            let mut state = self.state.lock().unpoison();
+            // Doesn't overtake blocked submitters.
+            if self.is_full(&state) || !state.waiting.is_empty() {
+                return None;
+            }
+            state.queued += 1;
+            return Some(QueueSlot{ capacity: self.clone() });
+        }
+
+        fn reserve(self: &Arc<Self>) -> QueueSlot {
+            let mut state = self.state.lock().unpoison();
+            if self.is_full(&state) || !state.waiting.is_empty() {
+                let ticket = state.next_ticket;
+                state.next_ticket += 1;
+                state.waiting.push_back(ticket);
+                loop {
+                    let next = match self.order {
+                        AdmissionOrder::Fifo => state.waiting.front(),
+                        AdmissionOrder::Lifo => state.waiting.back(),
+                    };
+                    if !self.is_full(&state) && next == Some(&ticket) {
+                        break;
+                    }
+                    state = self.freed.wait(state).unpoison();
+                }
+                match self.order {
+                    AdmissionOrder::Fifo => state.waiting.pop_front(),
+                    AdmissionOrder::Lifo => state.waiting.pop_back(),
+                };
+                // The next waiter may fit as well.
+                self.freed.notify_all();
+            }
+            state.queued += 1;
+            return QueueSlot{ capacity: self.clone() };
+        }
+    }
+
+    // Released once the task starts, or when its job is dropped without running.
+    struct QueueSlot {
+        capacity: Arc<QueueCapacity>,
+    }
+
+    impl Drop for QueueSlot {
+        fn drop(&mut self) {
+            self.capacity.state.lock().unpoison().queued -= 1;
+            // Only the waiter that is next in the admission order may take the slot.
+            self.capacity.freed.notify_all();
+        }
+    }
+
+    // *********************************************************************************************
+    type PanicLogger = Arc<dyn Fn(&str) + Send + Sync>;
+
+    fn panic_message(payload: &(dyn Any + Send)) -> String {
+        if let Some(message) = payload.downcast_ref::<&str>() {
+            return message.to_string();
+        }
+        if let Some(message) = payload.downcast_ref::<String>() {
+            return message.clone();
+        }
+        return String::from("<non-string panic payload>");
+    }
+
+    // Runs `fun`, reporting a panic to the panic logger instead of unwinding.
+    fn run_logged<F, O>(panic_logger: &Mutex<PanicLogger>, fun: F) where F: FnOnce() -> O {
+        if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(fun)) {
+            let logger = panic_logger.lock().unpoison().clone();
+            logger(&panic_message(payload.as_ref()));
+        }
+    }
+
+    // Lets the task system carry on after a panic poisoned one of its locks. Internal state is never
+    // left half updated across user code, so whatever the lock guards is still consistent.
+    trait Unpoison<T> {
+        fn unpoison(self) -> T;
+    }
+
+    impl<T> Unpoison<T> for LockResult<T> {
+        fn unpoison(self) -> T {
+            return self.unwrap_or_else(PoisonError::into_inner);
+        }
+    }
+
+    fn catch_panic<F, O>(fun: F) -> Result<O, GetValueError> where F: FnOnce() -> Result<O, GetValueError> {
+        return panic::catch_unwind(AssertUnwindSafe(fun))
+            .unwrap_or_else(|payload| Err(GetValueError::Panicked(panic_message(payload.as_ref()))));
+    }
+
+    // *********************************************************************************************
+    /// Caps how many tasks started with [`TaskSystem::run_limited`] through it run at once, created
+    /// by [`TaskSystem::limiter`]. Tasks over the limit wait in the [`TaskStatus::Waiting`] status
+    /// without taking up a worker. Clones share the limit.
+    #[derive(Clone)]
+    pub struct Limiter {
+        state: Arc<LimiterState>,
+    }
+
+    // Dispatches a limited task with the permit it runs under.
+    type LimitedJob = Box<dyn FnOnce(LimiterPermit) + Send>;
+
+    struct LimiterState {
+        max: usize,
+        queue: Mutex<LimiterQueue>,
+    }
+
+    struct LimiterQueue {
+        running: usize,
+        waiting: VecDeque<LimitedJob>,
+    }
+
+    impl Limiter {
+        /// Maximum number of tasks running at once.
+        pub fn max_concurrent(&self) -> usize {
+            return self.state.max;
+        }
+
+        fn acquire(&self, pool: &Arc<WorkerPool>, job: LimitedJob) {
+            {
+                let mut queue = self.state.queue.lock().unpoison();
+                if queue.running == self.state.max {
+                    queue.waiting.push_back(job);
+                    return;
+                }
+                queue.running += 1;
+            }
+            job(LimiterPermit{ state: self.state.clone(), pool: pool.clone() });
+        }
+    }
+
+    // Held by a limited task while it is dispatched, handing the slot to the next waiting task once
+    // dropped.
+    struct LimiterPermit {
+        state: Arc<LimiterState>,
+        pool: Arc<WorkerPool>,
+    }
+
+    impl Drop for LimiterPermit {
+        fn drop(&mut self) {
+            let next = {
+                let mut queue = self.state.queue.lock().unpoison();
+                let next = queue.waiting.pop_front();
+                if next.is_none() {
+                    queue.running -= 1;
+                }
+                next
+            };
+            if let Some(next) = next {
+                next(LimiterPermit{ state: self.state.clone(), pool: self.pool.clone() });
+            }
+        }
+    }
+
+    // *********************************************************************************************
+    /// Named category of tasks, created by [`TaskSystem::group`] or [`WorkGroup::group`], whose tasks
+    /// can be paused, resumed or cancelled at once along with the ones of its subgroups. Clones
+    /// share the group.
+    #[derive(Clone)]
+    pub struct WorkGroup {
+        tree: Arc<Mutex<GroupTree>>,
+        node: usize,
+        pool: Arc<WorkerPool>,
+    }
+
+    // A root group and its subgroups, behind one lock so changes to a subtree are atomic.
+    #[derive(Default)]
+    struct GroupTree {
+        nodes: Vec<GroupNode>,
+    }
+
+    struct GroupNode {
+        name: String,
+        parent: Option<usize>,
+        paused: bool,
+        // Jobs that reached a worker while the group or a parent was paused, run once they resume.
+        held: VecDeque<Job>,
+        // Cancels the unfinished tasks of the group.
+        tasks: HashMap<TaskId, Box<dyn Fn() + Send>>,
+        throttle: Option<Throttle>,
+        memory: Arc<GroupMemory>,
+    }
+
+    impl GroupTree {
+        fn add(&mut self, name: String, parent: Option<usize>) -> usize {
+            let memory = GroupMemory::new(parent.map(|parent| self.nodes[parent].memory.clone()));
+            self.nodes.push(GroupNode{ name, parent, paused: false, held: VecDeque::new(), tasks: HashMap::new(), throttle: None, memory });
+            return self.nodes.len() - 1;
+        }
+
+        fn is_paused(&self, mut node: usize) -> bool {
+            loop {
+                if self.nodes[node].paused {
+                    return true;
+                }
+                match self.nodes[node].parent {
+                    Some(parent) => node = parent,
+                    None => return false,
+                }
+            }
+        }
+
+        // Throttle of the group or, failing that, of its closest parent with one.
+        fn throttle(&self, mut node: usize) -> Option<Throttle> {
+            loop {
+                if let Some(throttle) = &self.nodes[node].throttle {
+                    return Some(throttle.clone());
+                }
+                node = self.nodes[node].parent?;
+            }
+        }
+
+        // Subgroups are always added after their parent.
+        fn subtree(&self, root: usize) -> impl Iterator<Item = usize> + '_ {
+            return (root..self.nodes.len()).filter(move |&node| {
+                let mut node = Some(node);
+                while let Some(current) = node {
+                    if current == root {
+                        return true;
+                    }
+                    node = self.nodes[current].parent;
+                }
+                return false;
+            });
+        }
+    }
+
+    impl WorkGroup {
+        /// Name the group was created with.
+        pub fn name(&self) -> String {
+            return self.tree.lock().unpoison().nodes[self.node].name.clone();
+        }
+
+        /// Creates a subgroup, paused whenever this group is and cancelled along with it.
+        pub fn group<S>(&self, name: S) -> WorkGroup where S: Into<String> {
+            let node = self.tree.lock().unpoison().add(name.into(), Some(self.node));
+            return WorkGroup{ tree: self.tree.clone(), node, pool: self.pool.clone() };
+        }
+
+        /// Queues `fun` as a task of the group. It stays queued while the group or a parent is
+        /// paused, and waits in the [`TaskStatus::Waiting`] status for the throttle of the group if
+        /// it has one. It fails with [`GetValueError::MemoryBudgetExceeded`] instead of starting
+        /// while the group or a parent used up its memory budget.
+        pub fn run<F, O>(&self, fun: F) -> Task<O>
+            where F: FnOnce() -> O + Send + 'static, O: Send + 'static
+        {
+            let memory = self.memory();
+            let budget = memory.clone();
+            let (task, job) = self.pool.prepare(move |_| {
+                if budget.exhausted() {
+                    return Err(GetValueError::MemoryBudgetExceeded);
+                }
+                return Ok(fun());
+            });
+            let _ = task.shared_state.meta.group_memory.set(memory);
+            let id = task.id();
+            let weak_state = Arc::downgrade(&task.shared_state);
+            let throttle = {
+                let mut tree = self.tree.lock().unpoison();
+                tree.nodes[self.node].tasks.insert(id, Box::new(move || {
+                    if let Some(shared_state) = Weak::upgrade(&weak_state) {
+                        shared_state.cancel();
+                    }
+                }));
+                tree.throttle(self.node)
+            };
+            let tree = self.tree.clone();
+            let node = self.node;
+            task.on_completed(Job::new(move || {
+                tree.lock().unpoison().nodes[node].tasks.remove(&id);
+            }));
+
+            match throttle {
+                Some(throttle) => {
+                    task.shared_state.set_status(TaskStatus::Waiting);
+                    let shared_state = task.shared_state.clone();
+                    let group = self.clone();
+                    throttle.acquire(Box::new(move || {
+                        if !shared_state.transition(TaskStatus::Waiting, TaskStatus::Queued) {
+                            return false;
+                        }
+                        group.dispatch(group.node, Job::new(job));
+                        return true;
+                    }));
+                },
+                None => {
+                    task.shared_state.set_status(TaskStatus::Queued);
+                    self.dispatch(self.node, Job::new(job));
+                },
+            }
+            return task;
+        }
+
+        /// Sends the tasks of the group, and of its subgroups without a throttle of their own,
+        /// through `throttle`. `None` lets them run as fast as they come again.
+        pub fn set_throttle(&self, throttle: Option<Throttle>) {
+            self.tree.lock().unpoison().nodes[self.node].throttle = throttle;
+        }
+
+        /// Keeps the tasks of the group and its subgroups that haven't started yet from starting,
+        /// running ones carry on.
+        pub fn pause(&self) {
+            self.tree.lock().unpoison().nodes[self.node].paused = true;
+        }
+
+        /// Lets the tasks held by [`WorkGroup::pause`] run again, unless a parent group is still
+        /// paused.
+        pub fn resume(&self) {
+            let held: Vec<(usize, Job)> = {
+                let mut tree = self.tree.lock().unpoison();
+                tree.nodes[self.node].paused = false;
+                let resumed: Vec<usize> = tree.subtree(self.node).filter(|&node| !tree.is_paused(node)).collect();
+                resumed.into_iter()
+                    .flat_map(|node| mem::take(&mut tree.nodes[node].held).into_iter().map(move |job| (node, job)))
+                    .collect()
+            };
+            for (node, job) in held {
+                self.dispatch(node, job);
+            }
+        }
+
+        /// Bytes allocated and not freed by the tasks of the group and its subgroups, as tracked by
+        /// [`TrackingAllocator`].
+        pub fn memory_used(&self) -> usize {
+            return self.memory().used();
+        }
+
+        /// Fails the tasks of the group and its subgroups that would start while they use
+        /// `budget` bytes or more, as tracked by [`TrackingAllocator`]. Running tasks carry on.
+        /// `None` removes the budget.
+        ///
+        /// ```
+        /// use std::alloc::System;
+        /// use task_system::{GetValueError, TaskSystem, TrackingAllocator};
+        ///
+        /// #[global_allocator]
+        /// static ALLOCATOR: TrackingAllocator = TrackingAllocator::new(System);
+        ///
+        /// let system = TaskSystem::new(1);
+        /// let assets = system.group("assets");
+        /// assets.set_memory_budget(Some(1 << 20));
+        /// let cache = assets.run(|| vec![0u8; 2 << 20]).join().unwrap();
+        /// assert!(assets.memory_used() >= 2 << 20);
+        /// assert_eq!(assets.run(|| ()).join(), Err(GetValueError::MemoryBudgetExceeded));
+        /// drop(cache);
+        /// ```
+        pub fn set_memory_budget(&self, budget: Option<usize>) {
+            self.memory().set_budget(budget);
+        }
+
+        fn memory(&self) -> Arc<GroupMemory> {
+            return self.tree.lock().unpoison().nodes[self.node].memory.clone();
+        }
+
+        /// Whether the group or one of its parents is paused.
+        pub fn is_paused(&self) -> bool {
+            return self.tree.lock().unpoison().is_paused(self.node);
+        }
+
+        /// Cancels every unfinished task of the group and its subgroups, as [`Task::cancel`] does.
+        /// Tasks queued afterwards aren't affected.
+        pub fn cancel_all(&self) {
+            let (cancels, held) = {
+                let mut tree = self.tree.lock().unpoison();
+                let nodes: Vec<usize> = tree.subtree(self.node).collect();
+                let mut cancels = Vec::new();
+                let mut held = Vec::new();
+                for node in nodes {
+                    cancels.extend(mem::take(&mut tree.nodes[node].tasks).into_values());
+                    held.extend(mem::take(&mut tree.nodes[node].held));
+                }
+                (cancels, held)
+            };
+            for cancel in cancels {
+                cancel();
+            }
+            // The tasks of these are cancelled already, so they are dropped without running.
+            drop(held);
+        }
+
+        // Queues a job of `node` that checks once more for a pause when a worker picks it up.
+        fn dispatch(&self, node: usize, job: Job) {
+            let tree = self.tree.clone();
+            self.pool.execute(Job::new(move || {
+                let mut mutex = tree.lock().unpoison();
+                if mutex.is_paused(node) {
+                    mutex.nodes[node].held.push_back(job);
+                    return;
+                }
+                drop(mutex);
+                job.run();
+            }));
+        }
+    }
+
+    // *********************************************************************************************
+    /// Token bucket capping the rate at which tasks started through it are queued, created by
+    /// [`TaskSystem::throttle`] and used by [`TaskSystem::run_throttled`] or
+    /// [`WorkGroup::set_throttle`]. Tasks over the rate wait in the [`TaskStatus::Waiting`] status,
+    /// in submission order. Clones share the bucket.
+    #[derive(Clone)]
+    pub struct Throttle {
+        state: Arc<ThrottleState>,
+    }
+
+    // Dispatches a throttled task, returns false if it was cancelled meanwhile so the token is
+    // handed back.
+    type ThrottledJob = Box<dyn FnOnce() -> bool + Send>;
+
+    struct ThrottleState {
+        burst: u32,
+        // Time for one token to come back.
+        refill: Duration,
+        timer: Arc<Timer>,
+        bucket: Mutex<Bucket>,
+    }
+
+    struct Bucket {
+        tokens: u32,
+        // When the next token comes back, while the bucket isn't full.
+        next_token: Instant,
+        waiting: VecDeque<ThrottledJob>,
+        // Whether the timer is due to release waiting jobs.
+        scheduled: bool,
+    }
+
+    impl Bucket {
+        fn refill(&mut self, burst: u32, refill: Duration) {
+            let now = Instant::now();
+            while self.tokens < burst && self.next_token <= now {
+                self.tokens += 1;
+                self.next_token += refill;
+            }
+        }
+
+        fn take(&mut self, burst: u32, refill: Duration) {
+            // A full bucket starts refilling from now on.
+            if self.tokens == burst {
+                self.next_token = Instant::now() + refill;
+            }
+            self.tokens -= 1;
+        }
+    }
+
+    impl Throttle {
+        /// Number of tasks let through at once after a quiet period.
+        pub fn burst(&self) -> u32 {
+            return self.state.burst;
+        }
+
+        fn acquire(&self, job: ThrottledJob) {
+            {
+                let mut bucket = self.state.bucket.lock().unpoison();
+                bucket.refill(self.state.burst, self.state.refill);
+                if !bucket.waiting.is_empty() || bucket.tokens == 0 {
+                    bucket.waiting.push_back(job);
+                    self.schedule(&mut bucket);
+                    return;
+                }
+                bucket.take(self.state.burst, self.state.refill);
+            }
+            if !job() {
+                self.give_back();
+            }
+        }
+
+        // Lets out the waiting jobs the bucket has tokens for, on the timer thread.
+        fn release(&self) {
+            let jobs: Vec<ThrottledJob> = {
+                let mut bucket = self.state.bucket.lock().unpoison();
+                bucket.scheduled = false;
+                bucket.refill(self.state.burst, self.state.refill);
+                let count = (bucket.tokens as usize).min(bucket.waiting.len());
+                for _ in 0..count {
+                    bucket.take(self.state.burst, self.state.refill);
+                }
+                let jobs = bucket.waiting.drain(..count).collect();
+                self.schedule(&mut bucket);
+                jobs
+            };
+            for job in jobs {
+                if !job() {
+                    self.give_back();
+                }
+            }
+        }
+
+        fn give_back(&self) {
+            let mut bucket = self.state.bucket.lock().unpoison();
+            bucket.tokens = (bucket.tokens + 1).min(self.state.burst);
+            if !bucket.waiting.is_empty() {
+                drop(bucket);
+                self.release();
+            }
+        }
+
+        fn schedule(&self, bucket: &mut Bucket) {
+            if bucket.scheduled || bucket.waiting.is_empty() {
+                return;
+            }
+            bucket.scheduled = true;
+            let throttle = self.clone();
+            self.state.timer.schedule(bucket.next_token, Job::new(move || throttle.release()));
+        }
+    }
+
+    // *********************************************************************************************
+    /// Phase of a frame run by [`TaskSystem::run_frame`], in execution order.
+    #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Copy, Clone)]
+    pub enum FramePhase {
+        /// Input handling and other work the update depends on.
+        PreUpdate,
+        /// The simulation step.
+        Update,
+        /// Rendering preparation and other work on the updated state.
+        PostUpdate,
+    }
+
+    impl FramePhase {
+        const ALL: [FramePhase; 3] = [FramePhase::PreUpdate, FramePhase::Update, FramePhase::PostUpdate];
+    }
+
+    // Queues the job of a phase task once its phase starts, unless the task was cancelled before.
+    type PhaseJob = Box<dyn FnOnce(&WorkerPool, ScopedJobGuard) + Send>;
+
+    // Tasks waiting for their phase of the next frame.
+    #[derive(Default)]
+    struct FrameScheduler {
+        phases: Mutex<[Vec<PhaseJob>; FramePhase::ALL.len()]>,
+    }
+
+    impl FrameScheduler {
+        fn push(&self, phase: FramePhase, job: PhaseJob) {
+            self.phases.lock().unpoison()[phase as usize].push(job);
+        }
+
+        fn run_phase(&self, pool: &WorkerPool, phase: FramePhase) {
+            let jobs = mem::take(&mut self.phases.lock().unpoison()[phase as usize]);
+            let tracker = Arc::new(ScopeTracker{
+                running: Mutex::new(jobs.len()),
+                finished: Condvar::new(),
+            });
+            for job in jobs {
+                job(pool, ScopedJobGuard{ tracker: tracker.clone() });
+            }
+            tracker.wait_all();
+        }
+    }
+
+    // *********************************************************************************************
+    /// Configures and creates a [`TaskSystem`].
+    pub struct TaskSystemBuilder {
+        workers: usize,
+        max_workers: Option<usize>,
+        memory_budget: Option<usize>,
+        max_queue_depth: Option<usize>,
+        saturation_policy: SaturationPolicy,
+        admission_order: AdmissionOrder,
+        threads: ThreadOptions,
+        scheduler: Option<Box<dyn Scheduler>>,
+        order: ExecutionOrder,
+        aging: Option<Duration>,
+        profiling: bool,
+        trace_capacity: Option<usize>,
+        cache_ttl: Duration,
+        watchdog: Option<(Duration, HungTaskHandler)>,
+        on_status_change: Option<StatusHook>,
+        store: Option<Arc<dyn TaskStore>>,
+        executor: Option<Arc<dyn Executor>>,
+    }
+
+    impl TaskSystemBuilder {
+        fn new() -> Self {
+            return Self{
+                workers: thread::available_parallelism().map_or(1, |n| n.get()),
+                max_workers: None,
+                memory_budget: None,
+                max_queue_depth: None,
+                saturation_policy: SaturationPolicy::default(),
+                admission_order: AdmissionOrder::default(),
+                threads: ThreadOptions{
+                    inline: cfg!(all(target_arch = "wasm32", not(target_feature = "atomics"))),
+                    ..ThreadOptions::default()
+                },
+                scheduler: None,
+                order: ExecutionOrder::default(),
+                aging: None,
+                profiling: false,
+                trace_capacity: None,
+                cache_ttl: Duration::ZERO,
+                watchdog: None,
+                on_status_change: None,
+                store: None,
+                executor: None,
+            };
+        }
+
+        /// Number of worker threads. Defaults to the available parallelism.
+        pub fn workers(mut self, n_workers: usize) -> Self {
+            self.workers = n_workers;
+            return self;
+        }
+
+        /// Upper bound for [`TaskSystem::set_worker_count`]. Defaults to the larger of the initial
+        /// worker count and 64.
+        pub fn max_workers(mut self, n_workers: usize) -> Self {
+            self.max_workers = Some(n_workers);
+            return self;
+        }
+
+        /// Names the worker threads `<name>-<index>`.
+        pub fn thread_name<S>(mut self, name: S) -> Self where S: Into<String> {
+            self.threads.name = Some(name.into());
+            return self;
+        }
+
+        /// Stack size of the worker threads in bytes. Defaults to the standard library default.
+        pub fn stack_size(mut self, bytes: usize) -> Self {
+            self.threads.stack_size = Some(bytes);
+            return self;
+        }
+
+        /// Runs `hook` on every worker thread before it picks up its first task.
+        pub fn on_thread_start<H>(mut self, hook: H) -> Self where H: Fn() + Send + Sync + 'static {
+            self.threads.on_start = Some(Arc::new(hook));
+            return self;
+        }
+
+        /// Runs `hook` with the worker index and panic message whenever a panic kills a worker
+        /// thread, before a replacement is started. Task panics only fail their task, panics of
+        /// [`TaskSystem::spawn_detached`] jobs are swallowed and the ones of
+        /// [`TaskSystem::spawn_logged`] jobs go to the panic logger, so this only catches panics
+        /// escaping the system.
+        pub fn on_worker_panic<H>(mut self, hook: H) -> Self where H: Fn(usize, &str) + Send + Sync + 'static {
+            self.threads.on_panic = Some(Arc::new(hook));
+            return self;
+        }
+
+        /// Pins worker `i` to the CPU core `cores[i % cores.len()]`, so the OS doesn't move it
+        /// between cores. Only has an effect on Linux, cores the OS refuses are ignored.
+        #[cfg(feature = "affinity")]
+        pub fn pin_workers(mut self, cores: &[usize]) -> Self {
+            self.threads.cores = cores.to_vec();
+            return self;
+        }
+
+        /// Splits the workers into groups of the given sizes in worker index order, e.g. one per NUMA
+        /// node. Tasks started with [`TaskSystem::run_on_group`] only run on the workers of their
+        /// group, and idle workers steal from their own group before the others.
+        pub fn worker_groups(mut self, sizes: &[usize]) -> Self {
+            self.threads.groups = sizes.to_vec();
+            return self;
+        }
+
+        /// Starts a worker per CPU, grouped by NUMA node with [`TaskSystemBuilder::worker_groups`]
+        /// and pinned to the cores of their node, group `i` being the `i`-th node. Leaves the builder
+        /// as is where the nodes can't be read, which is anywhere but Linux.
+        #[cfg(feature = "affinity")]
+        pub fn numa_groups(mut self) -> Self {
+            let nodes = scheduler::numa_nodes();
+            if nodes.is_empty() {
+                return self;
+            }
+            self.workers = nodes.iter().map(Vec::len).sum();
+            self.threads.groups = nodes.iter().map(Vec::len).collect();
+            self.threads.cores = nodes.concat();
+            return self;
+        }
+
+        /// Lets the threads of workers beyond [`TaskSystemBuilder::core_workers`] exit once they
+        /// idled for `idle`, so a pool sized for bursts doesn't keep every thread around between
+        /// them. Such a worker is started again as soon as work is queued while no other worker
+        /// sleeps.
+        pub fn keep_alive(mut self, idle: Duration) -> Self {
+            self.threads.keep_alive = Some(idle);
+            return self;
+        }
+
+        /// Number of workers kept alive with [`TaskSystemBuilder::keep_alive`], however long they
+        /// idle. Defaults to the available parallelism.
+        pub fn core_workers(mut self, n_workers: usize) -> Self {
+            self.threads.core_workers = Some(n_workers);
+            return self;
+        }
+
+        /// Runs tasks on the thread submitting them instead of on workers, so one API works on
+        /// targets without threads. A task then finishes before the call queueing it returns,
+        /// unless it is queued by another task, in which case it runs once that one returns or
+        /// waits for it. The
+        /// worker settings are ignored. On by default on `wasm32` without the `atomics` target
+        /// feature, where delayed and blocking tasks are unsupported as they need threads.
+        ///
+        /// ```
+        /// use task_system::{TaskBase, TaskStatus, TaskSystem};
+        ///
+        /// let system = TaskSystem::builder().run_inline(true).build();
+        /// let task = system.run(|| 6 * 7);
+        /// assert_eq!(task.status(), TaskStatus::Completed);
+        /// assert_eq!(task.join(), Ok(42));
+        /// ```
+        pub fn run_inline(mut self, enabled: bool) -> Self {
+            self.threads.inline = enabled;
+            return self;
+        }
+
+        /// Maximum number of tasks submitted with [`TaskSystem::run`] and [`TaskSystem::try_run`]
+        /// that may wait for a worker at once. Unbounded by default.
+        pub fn max_queue_depth(mut self, depth: usize) -> Self {
+            self.max_queue_depth = Some(depth);
+            return self;
+        }
+
+        /// What [`TaskSystem::run`] does once the queue is at its
+        /// [`TaskSystemBuilder::max_queue_depth`]. Blocks by default.
+        pub fn saturation_policy(mut self, policy: SaturationPolicy) -> Self {
+            self.saturation_policy = policy;
+            return self;
+        }
+
+        /// Order in which submitters blocked on a full queue are let in, see [`AdmissionOrder`].
+        /// [`AdmissionOrder::Fifo`], the default, bounds how long any submitter waits, while
+        /// [`AdmissionOrder::Lifo`] lowers the latency of fresh work at the cost of the tail
+        /// latency of submitters that have been waiting for long.
+        pub fn admission_order(mut self, order: AdmissionOrder) -> Self {
+            self.admission_order = order;
+            return self;
+        }
+
+        /// Lets `scheduler` decide which queued task runs next, instead of the default per-worker
+        /// queues with work stealing. All workers then share the scheduler's queue.
+        ///
+        /// Built with `RUSTFLAGS="--cfg loom"`, the task system runs on `loom`'s threads and locks,
+        /// so a scheduler can be model checked by running a task system using it in `loom::model`.
+        pub fn scheduler<S>(mut self, scheduler: S) -> Self where S: Scheduler {
+            self.scheduler = Some(Box::new(scheduler));
+            return self;
+        }
+
+        /// Order in which workers run the tasks they spawned. Defaults to [`ExecutionOrder::Lifo`],
+        /// [`TaskSystem::run_with_order`] overrides it per task.
+        pub fn execution_order(mut self, order: ExecutionOrder) -> Self {
+            self.order = order;
+            return self;
+        }
+
+        /// Moves every task still queued up one [`Priority`] lane each `interval`, so
+        /// [`Priority::Low`] tasks run within a bounded time even under a sustained load of higher
+        /// priority ones. Off by default. A custom [`TaskSystemBuilder::scheduler`] ages tasks by
+        /// itself, see [`ScheduledJob::submitted`].
+        pub fn priority_aging(mut self, interval: Duration) -> Self {
+            assert!(interval > Duration::ZERO, "the priority aging interval must not be zero");
+            self.aging = Some(interval);
+            return self;
+        }
+
+        /// Collects the execution times of finished tasks for [`TaskSystem::profile_report`]. Off by
+        /// default, as every finishing task then takes a shared lock.
+        pub fn profiling(mut self, enabled: bool) -> Self {
+            self.profiling = enabled;
+            return self;
+        }
+
+        /// Records when and on which worker the last `capacity` tasks ran, for
+        /// [`TaskSystem::trace`] and [`TaskSystem::dump_trace`]. Off by default.
+        pub fn record_trace(mut self, capacity: usize) -> Self {
+            assert!(capacity > 0, "a trace needs room for at least one task");
+            self.trace_capacity = Some(capacity);
+            return self;
+        }
+
+        /// Total memory that tasks submitted with [`TaskSystem::run_with_mem`] may claim at once.
+        pub fn memory_budget(mut self, total_bytes: usize) -> Self {
+            self.memory_budget = Some(total_bytes);
+            return self;
+        }
+
+        /// How long [`TaskSystem::run_cached`] hands out the output of a completed task again, rather
+        /// than only deduplicating tasks still in flight. Zero by default.
+        pub fn cache_ttl(mut self, ttl: Duration) -> Self {
+            self.cache_ttl = ttl;
+            return self;
+        }
+
+        /// Keeps the tasks submitted with [`TaskSystem::run_serialized`] in `store` until they ran,
+        /// so [`TaskSystem::resume_stored`] can queue them again after a restart. Tasks are only
+        /// kept in memory by default.
+        pub fn task_store<S>(mut self, store: S) -> Self where S: TaskStore {
+            self.store = Some(Arc::new(store));
+            return self;
+        }
+
+        /// Hands the tasks submitted with [`TaskSystem::run_remote`] to `executor`, e.g. one
+        /// sending them to worker processes. They run on this system's workers by default.
+        pub fn executor<E>(mut self, executor: E) -> Self where E: Executor {
+            self.executor = Some(Arc::new(executor));
+            return self;
+        }
+
+        /// Watches the running tasks and calls `on_hung` on the timer thread for each that went
+        /// `stall` without reporting it is alive, through [`heartbeat`] or [`Progress::set`], or
+        /// starting. A task is reported once per silence. Off by default.
+        ///
+        /// ```
+        /// use std::{sync::mpsc, time::Duration};
+        /// use task_system::TaskSystem;
+        ///
+        /// let (tx, rx) = mpsc::channel();
+        /// let system = TaskSystem::builder()
+        ///     .watchdog(Duration::from_millis(20), move |hung| tx.send(hung.name.clone()).unwrap())
+        ///     .build();
+        /// system.run_named("stuck", || std::thread::sleep(Duration::from_millis(200)));
+        /// assert_eq!(rx.recv().unwrap().as_deref(), Some("stuck"));
+        /// ```
+        pub fn watchdog<F>(mut self, stall: Duration, on_hung: F) -> Self where F: Fn(&HungTask) + Send + Sync + 'static {
+            assert!(stall > Duration::ZERO, "the watchdog period must not be zero");
+            self.watchdog = Some((stall, Arc::new(on_hung)));
+            return self;
+        }
+
+        /// Calls `hook` on every status change of every task, such as from `Queued` to `Running`,
+        /// to log or export task lifecycles. The hook runs on the thread changing the status while
+        /// the task is locked, so it should be quick and must not touch the task itself.
+        ///
+        /// ```
+        /// use std::sync::mpsc;
+        /// use task_system::{TaskStatus, TaskSystem};
+        ///
+        /// let (tx, rx) = mpsc::channel();
+        /// let system = TaskSystem::builder()
+        ///     .run_inline(true)
+        ///     .on_status_change(move |change| tx.send(change.new).unwrap())
+        ///     .build();
+        /// system.run(|| 6 * 7);
+        /// let statuses: Vec<_> = rx.try_iter().collect();
+        /// assert_eq!(statuses, vec![TaskStatus::Queued, TaskStatus::Running, TaskStatus::Completed]);
+        /// ```
+        pub fn on_status_change<H>(mut self, hook: H) -> Self where H: Fn(&StatusChange) + Send + Sync + 'static {
+            self.on_status_change = Some(Arc::new(hook));
+            return self;
+        }
+
+        /// Creates the task system without worker threads, ignoring the worker settings. See
+        /// [`TaskSystem::new_deterministic`].
+        pub fn build_deterministic(mut self) -> TaskSystem {
+            self.workers = 0;
+            self.threads.inline = false;
+            return self.start();
+        }
+
+        /// Starts the workers.
+        pub fn build(mut self) -> TaskSystem {
+            if self.threads.inline {
+                self.workers = 0;
+            } else {
+                assert!(self.workers > 0, "a task system needs at least one worker");
+            }
+            return self.start();
+        }
+
+        fn start(self) -> TaskSystem {
+            let main_tasks = Arc::new(Mutex::new(VecDeque::new()));
+            let registry = TaskRegistry::new(self.profiling, self.trace_capacity, self.on_status_change);
+            let panic_logger: Arc<Mutex<PanicLogger>> = Arc::new(Mutex::new(Arc::new(|message: &str| {
+                eprintln!("background task panicked: {}", message);
+            })));
+            let system = TaskSystem{
+                pool: WorkerPool::new(self.workers, self.max_workers.unwrap_or(self.workers.max(64)), &self.threads, self.order, self.aging, registry, panic_logger.clone(), Arc::downgrade(&main_tasks), self.scheduler),
+                memory_budget: MemoryBudget::new(self.memory_budget),
+                queue_capacity: QueueCapacity::new(self.max_queue_depth, self.saturation_policy, self.admission_order),
+                panic_logger,
+                timer: OnceLock::new(),
+                blocking: OnceLock::new(),
+                main_tasks,
+                frames: FrameScheduler::default(),
+                cache: TaskCache::new(self.cache_ttl),
+                durable: DurableTasks::new(self.store),
+                remote: RemoteTasks::new(self.executor),
+            };
+            if let Some((stall, on_hung)) = self.watchdog {
+                watchdog::watch(system.timer(), Arc::downgrade(&system.pool.registry), stall, on_hung);
+            }
+            return system;
+        }
+    }
+
+    // *********************************************************************************************
+    /// Pool of worker threads executing submitted closures.
+    pub struct TaskSystem {
+        pool: Arc<WorkerPool>,
+        memory_budget: Arc<MemoryBudget>,
+        queue_capacity: Arc<QueueCapacity>,
+        panic_logger: Arc<Mutex<PanicLogger>>,
+        // Started with the first delayed task.
+        timer: OnceLock<Arc<Timer>>,
+        // Started with the first blocking task.
+        blocking: OnceLock<Arc<BlockingPool>>,
+        // Jobs of `run_on_main`, run by `pump_main_tasks`.
+        main_tasks: Arc<Mutex<VecDeque<Job>>>,
+        frames: FrameScheduler,
+        cache: TaskCache,
+        durable: DurableTasks,
+        remote: RemoteTasks,
+    }
+
+    impl TaskSystem {
+        /// Creates a task system with `n_workers` worker threads.
+        pub fn new(n_workers: usize) -> Self {
+            return Self::builder().workers(n_workers).build();
+        }
+
+        /// Creates a task system with the default number of workers, running tasks in the order
+        /// picked by `scheduler`. See [`TaskSystemBuilder::scheduler`].
+        ///
+        /// ```
+        /// use task_system::{LifoScheduler, TaskBase, TaskSystem};
+        ///
+        /// let system = TaskSystem::with_scheduler(LifoScheduler::default());
+        /// let mut task = system.run(|| 42);
+        /// task.wait();
+        /// assert_eq!(task.value(), Ok(42));
+        /// ```
+        pub fn with_scheduler<S>(scheduler: S) -> Self where S: Scheduler {
+            return Self::builder().scheduler(scheduler).build();
+        }
+
+        /// Creates a task system without worker threads for tests. Tasks only run when the calling
+        /// code drives them with [`TaskSystem::step`] or [`TaskSystem::run_until_idle`], one at a
+        /// time on the calling thread, in priority and then submission order. Joining a subtask runs
+        /// queued tasks until the subtask is done. Delayed tasks still wait on a real clock, and
+        /// tasks left when the system shuts down are cancelled.
+        ///
+        /// ```
+        /// use task_system::{TaskBase, TaskStatus, TaskSystem};
+        ///
+        /// let system = TaskSystem::new_deterministic();
+        /// let mut first = system.run(|| 1);
+        /// let mut second = system.run(|| 2);
+        ///
+        /// assert_eq!(first.status(), TaskStatus::Queued);
+        /// assert!(system.step());
+        /// assert_eq!(first.status(), TaskStatus::Completed);
+        /// assert_eq!(second.status(), TaskStatus::Queued);
+        ///
+        /// assert_eq!(system.run_until_idle(), 1);
+        /// assert_eq!(second.value(), Ok(2));
+        /// ```
+        pub fn new_deterministic() -> Self {
+            return Self::builder().build_deterministic();
+        }
+
+        /// Runs one queued task on the calling thread, taking it from a busy worker if need be.
+        /// Returns whether there was one. Tasks pinned to a worker or a worker group are left to
+        /// the workers.
+        pub fn help_once(&self) -> bool {
+            return self.pool.help();
+        }
+
+        /// Runs the next queued task on the calling thread. Returns whether there was one. Meant
+        /// for systems created with [`TaskSystem::new_deterministic`].
+        pub fn step(&self) -> bool {
+            return self.pool.run_injected();
+        }
+
+        /// Runs queued tasks on the calling thread until none are left, including the ones they
+        /// queue in turn. Returns how many ran.
+        pub fn run_until_idle(&self) -> usize {
+            let mut steps = 0;
+            while self.step() {
+                steps += 1;
+            }
+            return steps;
+        }
+
+        /// Blocks until no task is queued or running, including the tasks those queue in the
+        /// meantime, such as their continuations, and detached jobs like the ones of
+        /// [`TaskSystem::spawn_detached`], [`TaskSystem::attach_job_source`] and pipelines. Tasks
+        /// waiting for dependencies, a delay or a frame phase only count once they are queued. A
+        /// system without workers, see [`TaskSystem::new_deterministic`], runs the queued tasks on
+        /// the calling thread instead.
+        ///
+        /// Tasks queued with [`TaskSystem::run_on_main`] count as well, so they must be pumped by
+        /// another thread, and calling it from a task never returns.
+        ///
+        /// ```
+        /// use std::sync::{Arc, atomic::{AtomicUsize, Ordering}};
+        /// use task_system::TaskSystem;
+        ///
+        /// let system = TaskSystem::new(2);
+        /// let done = Arc::new(AtomicUsize::new(0));
+        /// for _ in 0..8 {
+        ///     let done = done.clone();
+        ///     system.spawn_detached(move || done.fetch_add(1, Ordering::SeqCst));
+        /// }
+        /// system.wait_idle();
+        /// assert_eq!(done.load(Ordering::SeqCst), 8);
+        /// ```
+        pub fn wait_idle(&self) {
+            while !self.wait_idle_timeout(Duration::from_secs(3600)) {}
+        }
+
+        /// Like [`TaskSystem::wait_idle`] but gives up after `timeout`. Returns whether the system
+        /// is idle.
+        pub fn wait_idle_timeout(&self, timeout: Duration) -> bool {
+            return self.pool.wait_until(Instant::now().checked_add(timeout), || self.pool.registry.is_idle());
+        }
+
+        /// Returns a builder for a configurable task system.
+        pub fn builder() -> TaskSystemBuilder {
+            return TaskSystemBuilder::new();
+        }
+
+        /// Replaces the logger receiving panic messages of fire-and-forget tasks. Logs to stderr by
+        /// default.
+        pub fn set_panic_logger<L>(&self, logger: L) where L: Fn(&str) + Send + Sync + 'static {
+            *self.panic_logger.lock().unpoison() = Arc::new(logger);
+        }
+
+        /// Index of the worker executing the current thread, or `None` outside of worker threads.
+        pub fn current_worker_index() -> Option<usize> {
+            return scheduler::current_worker_index();
+        }
+
+        /// Starts new workers or retires the ones with an index of `n_workers` and above. A retired
+        /// worker finishes its current task first and hands its queued tasks to the others.
+        ///
+        /// Panics if `n_workers` is 0 or above [`TaskSystemBuilder::max_workers`].
+        pub fn set_worker_count(&self, n_workers: usize) {
+            self.pool.set_worker_count(n_workers);
+        }
+
+        /// Number of workers the system runs with.
+        pub fn worker_count(&self) -> usize {
+            return self.pool.worker_count();
+        }
+
+        /// Number of workers currently running a task.
+        pub fn active_count(&self) -> usize {
+            return self.pool.active_count();
+        }
+
+        /// Stops the worker at `index` from picking up new tasks. A task it is already running is
+        /// finished.
+        pub fn pause_worker(&self, index: usize) {
+            self.pool.set_paused(index, true);
+        }
+
+        /// Lets a paused worker pick up tasks again.
+        pub fn resume_worker(&self, index: usize) {
+            self.pool.set_paused(index, false);
+        }
+
+        /// Spawns a thread that submits every job received on `rx` until all senders are dropped.
+        /// With a [`TaskSystemBuilder::max_queue_depth`] set, the thread stops receiving while the
+        /// queue is full. A panic of a job is reported to the panic logger.
+        pub fn attach_job_source(&self, rx: mpsc::Receiver<Box<dyn FnOnce() + Send>>) -> thread::JoinHandle<()> {
+            let pool = self.pool.clone();
+            let queue_capacity = self.queue_capacity.clone();
+            let panic_logger = self.panic_logger.clone();
+            return thread::spawn(move || {
+                for job in rx {
+                    let slot = queue_capacity.reserve();
+                    let busy = pool.registry.busy_guard();
+                    let panic_logger = panic_logger.clone();
+                    pool.execute(Job::new(move || {
+                        let _busy = busy;
+                        drop(slot);
+                        run_logged(&panic_logger, job);
+                    }));
+                }
+            });
+        }
+    
+        /// Queues `fun` for execution and returns a handle to its output.
+        ///
+        /// With a [`TaskSystemBuilder::max_queue_depth`] set, blocks while the queue is full. Calling
+        /// it from a worker then needs other workers to make progress. Another
+        /// [`TaskSystemBuilder::saturation_policy`] rejects the task or runs it right away instead.
+        ///
+        /// ```
+        /// use task_system::{TaskBase, TaskSystem};
+        ///
+        /// let system = TaskSystem::new(1);
+        /// let mut task = system.run(|| String::from("done"));
+        /// task.wait();
+        /// assert_eq!(task.value().unwrap(), "done");
+        /// ```
+        pub fn run<F, O>(&self, fun: F) -> Task<O>
+            where F: FnOnce() -> O + Send + 'static, O: Send + 'static
+        {
+            if self.queue_capacity.max.is_none() {
+                return self.pool.submit(move |_| Ok(fun()));
+            }
+
+            let slot = match self.queue_capacity.policy {
+                SaturationPolicy::Queue => self.queue_capacity.reserve(),
+                policy => match self.queue_capacity.try_reserve() {
+                    Some(slot) => slot,
+                    None if policy == SaturationPolicy::CallerRuns => {
+                        let (task, job) = self.pool.prepare(move |_| Ok(fun()));
+                        task.shared_state.set_status(TaskStatus::Queued);
+                        job();
+                        return task;
+                    },
+                    None => {
+                        let task = Task::<O>::new(self.pool.clone(), None);
+                        task.shared_state.finish(Err(GetValueError::Rejected));
+                        return task;
+                    },
+                },
+            };
+            return self.pool.submit(move |_| {
+                drop(slot);
+                return Ok(fun());
+            });
+        }
+
+        /// Queues `fun` unless the queue already holds [`TaskSystemBuilder::max_queue_depth`]
+        /// tasks, in which case `fun` is handed back.
+        pub fn try_run<F, O>(&self, fun: F) -> Result<Task<O>, QueueFull<F>>
+            where F: FnOnce() -> O + Send + 'static, O: Send + 'static
+        {
+            let slot = match self.queue_capacity.try_reserve() {
+                Some(slot) => slot,
+                None => return Err(QueueFull(fun)),
+            };
+            return Ok(self.pool.submit(move |_| {
+                drop(slot);
+                return Ok(fun());
+            }));
+        }
+
+        /// Queues every closure of `funs`, taking the queue lock once for all of them instead of once
+        /// per task. With a [`TaskSystemBuilder::max_queue_depth`] the closures are queued one by one
+        /// like with [`TaskSystem::run`].
+        ///
+        /// ```
+        /// use task_system::{TaskIteratorExt, TaskSystem};
+        ///
+        /// let system = TaskSystem::new(2);
+        /// let tasks = system.run_batch((0..4).map(|index| move || index * 2));
+        /// assert_eq!(tasks.await_all(), Ok(vec![0, 2, 4, 6]));
+        /// ```
+        pub fn run_batch<I, F, O>(&self, funs: I) -> Vec<Task<O>>
+            where I: IntoIterator<Item = F>, F: FnOnce() -> O + Send + 'static, O: Send + 'static
+        {
+            if self.queue_capacity.max.is_some() {
+                return funs.into_iter().map(|fun| self.run(fun)).collect();
+            }
+
+            let (tasks, jobs): (Vec<_>, Vec<_>) = funs.into_iter()
+                .map(|fun| self.pool.prepare(move |_| Ok(fun())))
+                .map(|(task, job)| {
+                    task.shared_state.set_status(TaskStatus::Queued);
+                    return (task, Job::new(job));
+                })
+                .unzip();
+            self.pool.execute_batch(Priority::Normal, jobs);
+            return tasks;
+        }
+
+        /// Queues `fun` and registers `callback` to be called with its output, see
+        /// [`Task::on_complete`].
+        pub fn run_with_callback<F, C, O>(&self, fun: F, callback: C) -> Task<O>
+            where F: FnOnce() -> O + Send + 'static, C: FnOnce(&O) + Send + 'static, O: Send + 'static
+        {
+            // Registered before the task is queued, so the callback runs on the worker finishing it.
+            let (task, job) = self.pool.prepare(move |_| Ok(fun()));
+            task.on_complete(callback);
+            task.shared_state.set_status(TaskStatus::Queued);
+            self.pool.execute(Job::new(job));
+            return task;
+        }
+
+        /// Queues `fun` with a [`TaskContext`] for forking and joining subtasks.
+        ///
+        /// ```
+        /// use task_system::{TaskBase, TaskContext, TaskSystem};
+        ///
+        /// fn sum(ctx: &TaskContext, numbers: Vec<u64>) -> u64 {
+        ///     if numbers.len() <= 4 {
+        ///         return numbers.iter().sum();
+        ///     }
+        ///     let mut left = numbers;
+        ///     let right = left.split_off(left.len() / 2);
+        ///     let left = ctx.spawn(move |ctx| sum(ctx, left));
+        ///     let right = ctx.spawn(move |ctx| sum(ctx, right));
+        ///     return left.join() + right.join();
+        /// }
+        ///
+        /// let system = TaskSystem::new(1);
+        /// let mut task = system.run_with_ctx(|ctx| sum(ctx, (1..=100).collect()));
+        /// task.wait();
+        /// assert_eq!(task.value(), Ok(5050));
+        /// ```
+        pub fn run_with_ctx<F, O>(&self, fun: F) -> Task<O>
+            where F: FnOnce(&TaskContext) -> O + Send + 'static, O: Send + 'static
+        {
+            let pool = self.pool.clone();
+            return self.pool.submit(move |token| {
+                return Ok(fun(&TaskContext{ pool, token }));
+            });
+        }
+
+        /// Runs `fun` in steps until it returns [`Step::Done`]. Between steps the task is queued
+        /// again behind every task queued so far, so a long task can't monopolize a worker.
+        /// State carried from one step to the next lives in the closure. A task cancelled while it
+        /// runs stops at its next yield.
+        ///
+        /// ```
+        /// use task_system::{Step, TaskBase, TaskSystem};
+        ///
+        /// let system = TaskSystem::new(1);
+        /// let mut remaining = 1000;
+        /// let mut sum = 0;
+        /// let mut task = system.run_resumable(move |_| {
+        ///     // A hundred numbers per step.
+        ///     for _ in 0..100 {
+        ///         sum += remaining;
+        ///         remaining -= 1;
+        ///     }
+        ///     return if remaining == 0 { Step::Done(sum) } else { Step::Yield };
+        /// });
+        /// task.wait();
+        /// assert_eq!(task.value(), Ok(500500));
+        /// ```
+        pub fn run_resumable<F, O>(&self, fun: F) -> Task<O>
+            where F: FnMut(&TaskContext) -> Step<O> + Send + 'static, O: Send + 'static
+        {
+            let task = Task::<O>::new(self.pool.clone(), None);
+            task.shared_state.set_status(TaskStatus::Queued);
+
+            // The first step starts the task as any other, the later ones are queued by `resume`.
+            let pending = PendingTask::new(task.shared_state.clone());
+            let pool = self.pool.clone();
+            self.pool.execute(Job::new(move || {
+                let shared_state = pending.take();
+                if shared_state.start() {
+                    resume(pool, shared_state, fun);
+                }
+            }));
+            return task;
+        }
+
+        /// Applies `fun` to every item in parallel and returns the results in the order of `items`.
+        /// The items are split into a few chunks per worker. Blocks until every chunk is done,
+        /// helping with queued tasks when called from a worker, and propagates a panic of `fun`.
+        ///
+        /// ```
+        /// use task_system::TaskSystem;
+        ///
+        /// let system = TaskSystem::new(2);
+        /// let squares = system.map_parallel((1..=5).collect(), |n: u32| n * n);
+        /// assert_eq!(squares, vec![1, 4, 9, 16, 25]);
+        /// ```
+        pub fn map_parallel<T, F, R>(&self, items: Vec<T>, fun: F) -> Vec<R>
+            where T: Send + 'static, F: Fn(T) -> R + Send + Sync + 'static, R: Send + 'static
+        {
+            const CHUNKS_PER_WORKER: usize = 4;
+
+            if items.is_empty() {
+                return Vec::new();
+            }
+            let n_chunks = (self.pool.worker_count() * CHUNKS_PER_WORKER).min(items.len());
+            let chunk_size = items.len().div_ceil(n_chunks);
+
+            let fun = Arc::new(fun);
+            let mut items = items.into_iter();
+            let mut tasks: Vec<Task<Vec<R>>> = Vec::with_capacity(n_chunks);
+            loop {
+                let chunk: Vec<T> = items.by_ref().take(chunk_size).collect();
+                if chunk.is_empty() {
+                    break;
+                }
+                let fun = fun.clone();
+                tasks.push(self.pool.submit(move |_| Ok(chunk.into_iter().map(|item| fun(item)).collect())));
+            }
+
+            return tasks.iter_mut().flat_map(join_helping).collect();
+        }
+
+        /// Calls `fun` with every item in parallel, see [`TaskSystem::map_parallel`].
+        pub fn for_each_parallel<T, F>(&self, items: Vec<T>, fun: F)
+            where T: Send + 'static, F: Fn(T) + Send + Sync + 'static
+        {
+            self.map_parallel(items, fun);
+        }
+
+        /// Queues `fun` under a name that shows up in [`TaskSystem::tasks`].
+        pub fn run_named<S, F, O>(&self, name: S, fun: F) -> Task<O>
+            where S: Into<String>, F: FnOnce() -> O + Send + 'static, O: Send + 'static
+        {
+            return self.pool.submit_named(Priority::Normal, Some(name.into()), move |_| Ok(fun()));
+        }
+
+        /// Queues `fun` unless a task of the same key and output type is queued or running, or
+        /// completed within the builder's [`TaskSystemBuilder::cache_ttl`]. Either way the returned
+        /// task completes with a clone of the output of that one run, or with its error. Failed and
+        /// expired runs are evicted and run again.
+        ///
+        /// ```
+        /// use task_system::{TaskBase, TaskSystem};
+        ///
+        /// let system = TaskSystem::new(2);
+        /// let mut first = system.run_cached("config", || String::from("loaded"));
+        /// let mut second = system.run_cached("config", || String::from("loaded again"));
+        /// first.wait();
+        /// second.wait();
+        /// assert_eq!(first.value(), Ok(String::from("loaded")));
+        /// assert_eq!(second.value(), Ok(String::from("loaded")));
+        /// ```
+        pub fn run_cached<K, F, O>(&self, key: K, fun: F) -> Task<O>
+            where K: Hash + Eq + Send + 'static, F: FnOnce() -> O + Send + 'static, O: Clone + Send + 'static
+        {
+            let ttl = self.cache.ttl;
+            let fresh = |task: &Task<O>| {
+                let mutex = task.shared_state.lock().unpoison();
+                return match task.shared_state.status() {
+                    TaskStatus::Completed => mutex.finished_at.is_some_and(|finished| finished.elapsed() < ttl),
+                    status => !status.is_finished(),
+                };
+            };
+            // The cached task is never handed out, so its output stays for every caller to clone.
+            let source = self.cache.get_or_insert_with(key, fresh, || self.pool.submit(move |_| Ok(fun())));
+
+            let (task, pending) = source.derive();
+            let source_state = source.shared_state.clone();
+            source.on_completed(Job::new(move || {
+                let result = {
+                    let mutex = source_state.lock().unpoison();
+                    match (&mutex.output, &mutex.error) {
+                        (Some(output), _) => Ok(output.clone()),
+                        (None, Some(error)) => Err(error.clone()),
+                        (None, None) => Err(GetValueError::AlreadyTaken),
+                    }
+                };
+                let shared_state = pending.take();
+                match result {
+                    Ok(output) => shared_state.complete(output),
+                    Err(error) => shared_state.finish(Err(error)),
+                }
+            }));
+            return task;
+        }
+
+        /// Forgets every task of [`TaskSystem::run_cached`], so the next call for any key runs anew.
+        pub fn clear_cache(&self) {
+            self.cache.clear();
+        }
+
+        /// Registers `handler` to run the tasks of [`TaskSystem::run_serialized`] and
+        /// [`TaskSystem::resume_stored`] with payloads of type `P`, replacing an earlier one.
+        pub fn register_handler<P, F>(&self, handler: F) where P: TaskPayload, F: Fn(P) + Send + Sync + 'static {
+            let handler: PayloadHandler = Arc::new(move |bytes: &[u8]| {
+                match P::from_bytes(bytes) {
+                    Some(payload) => handler(payload),
+                    None => panic!("invalid {} payload", P::KIND),
+                }
+            });
+            self.durable.handlers.lock().unpoison().insert(P::KIND, handler);
+        }
+
+        /// Saves `payload` to the store of [`TaskSystemBuilder::task_store`] and queues the handler
+        /// registered for `P` with it. The task stays in the store until it ran, successfully or
+        /// not, so one that was still queued or running when the process ended runs again after
+        /// [`TaskSystem::resume_stored`]. Fails if the store does.
+        ///
+        /// Panics if no handler is registered for `P`.
+        ///
+        /// ```
+        /// use std::convert::TryInto;
+        /// use task_system::{TaskPayload, TaskSystem};
+        ///
+        /// struct Resize(u32);
+        ///
+        /// impl TaskPayload for Resize {
+        ///     const KIND: &'static str = "resize";
+        ///
+        ///     fn to_bytes(&self) -> Vec<u8> {
+        ///         return self.0.to_le_bytes().to_vec();
+        ///     }
+        ///
+        ///     fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        ///         return Some(Resize(u32::from_le_bytes(bytes.try_into().ok()?)));
+        ///     }
+        /// }
+        ///
+        /// let system = TaskSystem::new(1);
+        /// system.register_handler(|Resize(width)| println!("resizing to {}", width));
+        /// let task = system.run_serialized(Resize(640)).unwrap();
+        /// assert_eq!(task.join(), Ok(()));
+        /// ```
+        pub fn run_serialized<P>(&self, payload: P) -> io::Result<Task<()>> where P: TaskPayload {
+            let handler = self.durable.handler(P::KIND).unwrap_or_else(|| panic!("no handler registered for {} payloads", P::KIND));
+            let bytes = payload.to_bytes();
+            let id = match &self.durable.store {
+                Some(store) => Some(store.push(P::KIND, &bytes)?),
+                None => None,
+            };
+            return Ok(self.queue_stored(id, bytes, handler));
+        }
+
+        /// Queues the tasks left in the store of [`TaskSystemBuilder::task_store`] by an earlier
+        /// run of the process, for which a handler is registered by now. Tasks of other kinds stay
+        /// in the store. Fails if the store does.
+        pub fn resume_stored(&self) -> io::Result<Vec<Task<()>>> {
+            let Some(store) = &self.durable.store else { return Ok(Vec::new()); };
+            let mut tasks = Vec::new();
+            for stored in store.pending()? {
+                let Some(handler) = self.durable.handler(&stored.kind) else { continue; };
+                if self.durable.queued.lock().unpoison().contains(&stored.id) {
+                    continue;
+                }
+                tasks.push(self.queue_stored(Some(stored.id), stored.payload, handler));
+            }
+            return Ok(tasks);
+        }
+
+        fn queue_stored(&self, id: Option<u64>, bytes: Vec<u8>, handler: PayloadHandler) -> Task<()> {
+            let store = self.durable.store.clone();
+            let queued = self.durable.queued.clone();
+            if let Some(id) = id {
+                queued.lock().unpoison().insert(id);
+            }
+            return self.pool.submit(move |_| {
+                let result = panic::catch_unwind(AssertUnwindSafe(|| handler(&bytes)));
+                if let (Some(store), Some(id)) = (store, id) {
+                    // Left in the store if this fails, to run again after a restart.
+                    let _ = store.remove(id);
+                    queued.lock().unpoison().remove(&id);
+                }
+                if let Err(payload) = result {
+                    panic::resume_unwind(payload);
+                }
+                return Ok(());
+            });
+        }
+
+        /// Registers `handler` to run the tasks of [`TaskSystem::run_remote`] and
+        /// [`TaskSystem::run_descriptor`] with payloads of type `P`, replacing an earlier one.
+        pub fn register_remote_handler<P, F>(&self, handler: F) where P: RemoteTask, F: Fn(P) -> P::Output + Send + Sync + 'static {
+            self.remote.register(P::KIND, Arc::new(move |bytes: &[u8]| {
+                match P::from_bytes(bytes) {
+                    Some(payload) => return handler(payload).to_bytes(),
+                    None => panic!("invalid {} payload", P::KIND),
+                }
+            }));
+        }
+
+        /// Hands `payload` to the [`Executor`] of [`TaskSystemBuilder::executor`], or runs it with
+        /// the handler registered for `P` if there is none. Either way the payload and the output
+        /// go through their encoding, so a task behaves the same wherever it runs. The task is
+        /// queued until the executor replies, and fails if the output can't be decoded.
+        ///
+        /// ```
+        /// use std::{convert::TryInto, thread};
+        /// use task_system::{Executor, RemoteTask, Reply, TaskDescriptor, TaskPayload, TaskSystem};
+        ///
+        /// struct Square(u32);
+        ///
+        /// impl TaskPayload for Square {
+        ///     const KIND: &'static str = "square";
+        ///
+        ///     fn to_bytes(&self) -> Vec<u8> {
+        ///         return self.0.to_le_bytes().to_vec();
+        ///     }
+        ///
+        ///     fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        ///         return Some(Square(u32::from_le_bytes(bytes.try_into().ok()?)));
+        ///     }
+        /// }
+        ///
+        /// impl RemoteTask for Square {
+        ///     type Output = String;
+        /// }
+        ///
+        /// // Stands in for a transport to a worker process running its own task system.
+        /// struct Worker;
+        ///
+        /// impl Executor for Worker {
+        ///     fn execute(&self, descriptor: TaskDescriptor, reply: Reply) {
+        ///         thread::spawn(move || {
+        ///             let remote = TaskSystem::new(1);
+        ///             remote.register_remote_handler(|Square(n)| (n * n).to_string());
+        ///             reply.send(remote.run_descriptor(descriptor).join().map_err(|error| format!("{:?}", error)));
+        ///         });
+        ///     }
+        /// }
+        ///
+        /// let system = TaskSystem::builder().executor(Worker).build();
+        /// assert_eq!(system.run_remote(Square(12)).join().as_deref(), Ok("144"));
+        /// ```
+        pub fn run_remote<P>(&self, payload: P) -> Task<P::Output> where P: RemoteTask {
+            let descriptor = TaskDescriptor::new(&payload);
+            let decode = |shared_state: Arc<TaskSharedState<P::Output>>, result: Result<Vec<u8>, GetValueError>| {
+                match result.and_then(|bytes| P::Output::from_bytes(&bytes).ok_or(GetValueError::InvalidOutput)) {
+                    Ok(output) => shared_state.complete(output),
+                    Err(error) => shared_state.finish(Err(error)),
+                }
+            };
+            let Some(executor) = &self.remote.executor else {
+                let local = self.run_descriptor(descriptor);
+                let (task, pending) = local.derive();
+                let mut source = local.clone();
+                local.on_completed(Job::new(move || decode(pending.take(), source.take_input())));
+                return task;
+            };
+
+            let task = Task::<P::Output>::new(self.pool.clone(), None);
+            task.shared_state.set_status(TaskStatus::Queued);
+            let pending = PendingTask::new(task.shared_state.clone());
+            executor.execute(descriptor, Reply{ send: Box::new(move |result| {
+                decode(pending.take(), result.map_err(GetValueError::Panicked));
+            })});
+            return task;
+        }
+
+        /// Queues the handler registered for the kind of `descriptor` with its payload, completing
+        /// with the encoded output. This is how a process receiving descriptors from an
+        /// [`Executor`] runs them. The task fails if no handler is registered for the kind.
+        pub fn run_descriptor(&self, descriptor: TaskDescriptor) -> Task<Vec<u8>> {
+            let handler = self.remote.handler(&descriptor.kind);
+            return self.pool.submit(move |_| {
+                let handler = handler.unwrap_or_else(|| panic!("no handler registered for {} tasks", descriptor.kind));
+                return Ok(handler(&descriptor.payload));
+            });
+        }
+
+        /// Snapshot of every task that hasn't finished yet, ordered by [`TaskId`], i.e. by creation.
+        ///
+        /// ```
+        /// use task_system::{TaskBase, TaskStatus, TaskSystem};
+        ///
+        /// let system = TaskSystem::new(1);
+        /// let (tx, rx) = std::sync::mpsc::channel::<()>();
+        /// let mut task = system.run_named("load_level", move || rx.recv().unwrap());
+        /// while task.status() != TaskStatus::Running {}
+        ///
+        /// let tasks = system.tasks();
+        /// assert_eq!(tasks.len(), 1);
+        /// assert_eq!(tasks[0].name.as_deref(), Some("load_level"));
+        /// assert_eq!(tasks[0].status, TaskStatus::Running);
+        ///
+        /// tx.send(()).unwrap();
+        /// task.wait();
+        /// assert!(system.tasks().is_empty());
+        /// ```
+        pub fn tasks(&self) -> Vec<TaskInfo> {
+            return self.pool.registry.snapshot();
+        }
+
+        /// Snapshot of the task counters, queue length and worker utilization.
+        pub fn metrics(&self) -> Metrics {
+            return self.pool.registry.counters.snapshot(self.pool.queue_len(), self.pool.worker_busy_times());
+        }
+
+        /// Execution times of the finished tasks by name, the ones taking the most time in total
+        /// first. Empty unless [`TaskSystemBuilder::profiling`] is enabled.
+        ///
+        /// ```
+        /// use task_system::{TaskIteratorExt, TaskSystem};
+        ///
+        /// let system = TaskSystem::builder().workers(2).profiling(true).build();
+        /// let tasks: Vec<_> = (0..4).map(|_| system.run_named("physics", || {})).collect();
+        /// tasks.await_all().unwrap();
+        ///
+        /// let report = system.profile_report();
+        /// assert_eq!(report[0].name.as_deref(), Some("physics"));
+        /// assert_eq!(report[0].count, 4);
+        /// ```
+        pub fn profile_report(&self) -> Vec<TaskProfile> {
+            return self.pool.registry.profiler.as_ref().map_or_else(Vec::new, |profiler| profiler.report());
+        }
+
+        /// Runs recorded with [`TaskSystemBuilder::record_trace`], in the order the tasks finished.
+        /// Empty unless recording is enabled.
+        pub fn trace(&self) -> Vec<TraceEvent> {
+            return self.pool.registry.recorder.as_ref().map_or_else(Vec::new, |recorder| recorder.events());
+        }
+
+        /// Writes the recorded runs to `path` as JSON that `chrome://tracing` or Perfetto show as a
+        /// timeline with a row per worker, see [`TaskSystem::trace`].
+        ///
+        /// ```no_run
+        /// use task_system::{TaskIteratorExt, TaskSystem};
+        ///
+        /// let system = TaskSystem::builder().record_trace(10_000).build();
+        /// let tasks: Vec<_> = (0..100).map(|i| system.run_named("step", move || i * i)).collect();
+        /// tasks.await_all().unwrap();
+        /// system.dump_trace("trace.json").unwrap();
+        /// ```
+        pub fn dump_trace<P>(&self, path: P) -> io::Result<()> where P: AsRef<Path> {
+            let file = io::BufWriter::new(fs::File::create(path)?);
+            return timeline::write_chrome_trace(&self.trace(), self.pool.worker_count(), file);
+        }
+
+        /// Queues `fun` and runs it again whenever it returns an error, as long as `policy` allows
+        /// another attempt. The task completes with the result of the last attempt and waits in the
+        /// [`TaskStatus::Waiting`] status between attempts. A panic fails the task right away.
+        ///
+        /// ```
+        /// use std::time::Duration;
+        /// use task_system::{RetryPolicy, TaskBase, TaskSystem};
+        ///
+        /// let system = TaskSystem::new(1);
+        /// let mut attempts = 0;
+        /// let mut task = system.run_with_policy(RetryPolicy::exponential(3, Duration::from_millis(1)), move || {
+        ///     attempts += 1;
+        ///     return if attempts < 3 { Err(attempts) } else { Ok(attempts) };
+        /// });
+        /// task.wait();
+        /// assert_eq!(task.value(), Ok(Ok(3)));
+        /// ```
+        pub fn run_with_policy<F, T, E>(&self, policy: RetryPolicy, fun: F) -> Task<Result<T, E>>
+            where F: FnMut() -> Result<T, E> + Send + 'static, T: Send + 'static, E: Send + 'static
+        {
+            let task = Task::new(self.pool.clone(), None);
+            task.shared_state.set_status(TaskStatus::Queued);
+
+            RetryAttempt{
+                fun,
+                policy,
+                attempt: 1,
+                pending: PendingTask::new(task.shared_state.clone()),
+                pool: self.pool.clone(),
+                timer: self.timer().clone(),
+            }.execute();
+
+            return task;
+        }
+
+        /// Queues `fun` to run on the thread calling [`TaskSystem::pump_main_tasks`], for work tied
+        /// to one thread like graphics API calls. Waiting for such a task on the pumping thread
+        /// blocks forever.
+        ///
+        /// ```
+        /// use std::time::Duration;
+        /// use task_system::TaskSystem;
+        ///
+        /// let system = TaskSystem::new(1);
+        /// let mut task = system.run_on_main(|| std::thread::current().name().map(String::from));
+        /// assert_eq!(system.pump_main_tasks(Duration::from_millis(5)), 1);
+        /// assert_eq!(task.value(), Ok(std::thread::current().name().map(String::from)));
+        /// ```
+        pub fn run_on_main<F, O>(&self, fun: F) -> Task<O>
+            where F: FnOnce() -> O + Send + 'static, O: Send + 'static
+        {
+            let (task, job) = self.pool.prepare(move |_| Ok(fun()));
+            task.shared_state.set_status(TaskStatus::Queued);
+            self.main_tasks.lock().unpoison().push_back(Job::new(job));
+            return task;
+        }
+
+        /// Runs `fun` on a worker and hands its output to `finalize` on the thread calling
+        /// [`TaskSystem::pump_main_tasks`], for outputs that can't leave that thread like `Rc`s or
+        /// GUI handles. The returned handle must be created and used on the pumping thread; the
+        /// task fails if it is pumped anywhere else.
+        ///
+        /// ```
+        /// use std::{rc::Rc, time::Duration};
+        /// use task_system::{TaskBase, TaskSystem};
+        ///
+        /// let system = TaskSystem::new(1);
+        /// let mut task = system.run_with_local_result(|| vec![1, 2, 3], Rc::new);
+        /// while !task.completed() {
+        ///     system.pump_main_tasks(Duration::from_millis(1));
+        /// }
+        /// assert_eq!(*task.value().unwrap(), vec![1, 2, 3]);
+        /// ```
+        pub fn run_with_local_result<F, T, G, L>(&self, fun: F, finalize: G) -> LocalTask<L>
+            where F: FnOnce() -> T + Send + 'static, T: Send + 'static, G: FnOnce(T) -> L + Send + 'static, L: 'static
+        {
+            let intermediate = self.pool.submit(move |_| Ok(fun()));
+            let key = intermediate.id();
+            let owner = thread::current().id();
+            let dropped = Arc::new(AtomicBool::new(false));
+
+            let finalized = dropped.clone();
+            let mut input = intermediate.clone();
+            let (task, job) = self.pool.prepare(move |_| {
+                let input = input.take_input()?;
+                assert!(thread::current().id() == owner, "local task pumped on a thread other than the one owning it");
+                let output = finalize(input);
+                if !finalized.load(Ordering::Acquire) {
+                    LOCAL_RESULTS.with(|results| results.borrow_mut().insert(key, Box::new(output)));
+                }
+                return Ok(());
+            });
+            task.shared_state.set_status(TaskStatus::Waiting);
+
+            let shared_state = task.shared_state.clone();
+            let main_tasks = Arc::downgrade(&self.main_tasks);
+            intermediate.on_completed(Job::new(move || {
+                // Dropping the job cancels the task once the system is gone.
+                if let Some(main_tasks) = Weak::upgrade(&main_tasks) {
+                    if shared_state.transition(TaskStatus::Waiting, TaskStatus::Queued) {
+                        main_tasks.lock().unpoison().push_back(Job::new(job));
+                    }
+                }
+            }));
+            return LocalTask{ task, key, dropped, local: PhantomData };
+        }
+
+        /// Runs tasks queued with [`TaskSystem::run_on_main`] on the calling thread until none are
+        /// left or `budget` ran out. At least one task runs if any is queued, tasks queued while
+        /// pumping run as well. Returns how many ran.
+        pub fn pump_main_tasks(&self, budget: Duration) -> usize {
+            let deadline = Instant::now() + budget;
+            let mut ran = 0;
+            loop {
+                let job = self.main_tasks.lock().unpoison().pop_front();
+                match job {
+                    Some(job) => job.run(),
+                    None => return ran,
+                }
+                ran += 1;
+                if Instant::now() >= deadline {
+                    return ran;
+                }
+            }
+        }
+
+        /// Holds `fun` back until the given phase of the next [`TaskSystem::run_frame`]. The task is
+        /// [`TaskStatus::Waiting`] until then.
+        pub fn run_in_phase<F, O>(&self, phase: FramePhase, fun: F) -> Task<O>
+            where F: FnOnce() -> O + Send + 'static, O: Send + 'static
+        {
+            let (task, job) = self.pool.prepare(move |_| Ok(fun()));
+            task.shared_state.set_status(TaskStatus::Waiting);
+
+            let shared_state = task.shared_state.clone();
+            self.frames.push(phase, Box::new(move |pool, guard| {
+                if shared_state.transition(TaskStatus::Waiting, TaskStatus::Queued) {
+                    pool.execute(Job::new(move || {
+                        let _guard = guard;
+                        job();
+                    }));
+                }
+            }));
+            return task;
+        }
+
+        /// Runs one frame: the tasks of each [`FramePhase`] run in parallel, and a phase only starts
+        /// once every task of the previous one finished. Tasks added to a later phase while the
+        /// frame runs are part of it, the ones added to the current or an earlier phase wait for the
+        /// next frame. Blocks until the frame is done, so it shouldn't be called from a worker.
+        ///
+        /// ```
+        /// use std::sync::{Arc, Mutex};
+        /// use task_system::{FramePhase, TaskSystem};
+        ///
+        /// let system = TaskSystem::new(2);
+        /// let log = Arc::new(Mutex::new(Vec::new()));
+        /// for phase in [FramePhase::PostUpdate, FramePhase::Update, FramePhase::PreUpdate] {
+        ///     let log = log.clone();
+        ///     system.run_in_phase(phase, move || log.lock().unwrap().push(phase));
+        /// }
+        ///
+        /// system.run_frame();
+        /// assert_eq!(*log.lock().unwrap(), vec![FramePhase::PreUpdate, FramePhase::Update, FramePhase::PostUpdate]);
+        /// ```
+        pub fn run_frame(&self) {
+            for phase in FramePhase::ALL.iter() {
+                self.frames.run_phase(&self.pool, *phase);
+            }
+        }
+
+        /// Queues `fun`, handing it a [`StreamSender`] to pass items to the consumer while it runs.
+        ///
+        /// ```
+        /// use task_system::TaskSystem;
+        ///
+        /// let system = TaskSystem::new(1);
+        /// let hits = system.run_stream(|tx| {
+        ///     for line in ["a", "match 1", "b", "match 2"] {
+        ///         if line.starts_with("match") {
+        ///             tx.send(line);
+        ///         }
+        ///     }
+        /// });
+        /// assert_eq!(hits.collect::<Vec<_>>(), vec!["match 1", "match 2"]);
+        /// ```
+        pub fn run_stream<F, O>(&self, fun: F) -> StreamTask<O>
+            where F: FnOnce(&StreamSender<O>) + Send + 'static, O: Send + 'static
+        {
+            return StreamTask::new(|tx| self.pool.submit(move |_| {
+                fun(&tx);
+                return Ok(());
+            }));
+        }
+
+        /// Queues `fun`, handing it a [`Progress`] to report how far along it is.
+        ///
+        /// ```
+        /// use task_system::{TaskBase, TaskSystem};
+        ///
+        /// let system = TaskSystem::new(1);
+        /// let mut task = system.run_with_progress(|progress| {
+        ///     for step in 1..=4 {
+        ///         progress.set(step as f32 / 4.0);
+        ///     }
+        /// });
+        /// task.wait();
+        /// assert_eq!(task.progress(), 1.0);
+        /// ```
+        pub fn run_with_progress<F, O>(&self, fun: F) -> Task<O>
+            where F: FnOnce(Progress) -> O + Send + 'static, O: Send + 'static
+        {
+            // Filled in before the job is queued, once the task it reports for exists.
+            let progress = Arc::new(OnceLock::new());
+            let job_progress = progress.clone();
+            let (task, job) = self.pool.prepare(move |_| {
+                let progress: Progress = job_progress.get().cloned().unwrap();
+                return Ok(fun(progress));
+            });
+            let _ = progress.set(Progress{ meta: task.shared_state.meta.clone() });
+
+            task.shared_state.set_status(TaskStatus::Queued);
+            self.pool.execute(Job::new(job));
+            return task;
+        }
+
+        /// Queues `fun` and gives it `timeout` from now to finish. Past that, the task is
+        /// [`TaskStatus::TimedOut`], its [`CancellationToken`] is tripped and its output, if it still
+        /// produces one, is dropped. A [`DeadlineScheduler`] runs tasks by these deadlines.
+        ///
+        /// ```
+        /// use std::time::Duration;
+        /// use task_system::{GetValueError, TaskBase, TaskStatus, TaskSystem};
+        ///
+        /// let system = TaskSystem::new(1);
+        /// let mut task = system.run_with_deadline(Duration::from_millis(10), |token| {
+        ///     while !token.is_cancelled() {
+        ///         std::thread::yield_now();
+        ///     }
+        /// });
+        /// task.wait();
+        /// assert_eq!(task.status(), TaskStatus::TimedOut);
+        /// assert_eq!(task.value(), Err(GetValueError::TimedOut));
+        /// ```
+        pub fn run_with_deadline<F, O>(&self, timeout: Duration, fun: F) -> Task<O>
+            where F: FnOnce(CancellationToken) -> O + Send + 'static, O: Send + 'static
+        {
+            let deadline = Instant::now() + timeout;
+            let (task, job) = self.pool.prepare(move |token| Ok(fun(token)));
+            task.shared_state.set_status(TaskStatus::Queued);
+
+            let shared_state = Arc::downgrade(&task.shared_state);
+            self.timer().schedule(deadline, Job::new(move || {
+                if let Some(shared_state) = Weak::upgrade(&shared_state) {
+                    shared_state.time_out();
+                }
+            }));
+            self.pool.execute_with_deadline(deadline, Job::new(job));
+            return task;
+        }
+
+        /// Queues `fun` on behalf of `tenant`, whose share of the workers a [`WeightedFairScheduler`]
+        /// balances against the other tenants. The time the task runs adds up in
+        /// [`Metrics::tenant_busy`].
+        pub fn run_for_tenant<F, O>(&self, tenant: usize, fun: F) -> Task<O>
+            where F: FnOnce() -> O + Send + 'static, O: Send + 'static
+        {
+            let (task, job) = self.pool.prepare(move |_| Ok(fun()));
+            task.shared_state.set_status(TaskStatus::Queued);
+            self.pool.execute_for_tenant(tenant, Job::new(job));
+            return task;
+        }
+
+        /// Creates a [`Limiter`] letting at most `max_concurrent` of its tasks run at once, e.g. to
+        /// guard a resource with limited capacity.
+        ///
+        /// ```
+        /// use task_system::{TaskBase, TaskSystem};
+        ///
+        /// let system = TaskSystem::new(4);
+        /// let gpu = system.limiter(2);
+        /// let mut tasks: Vec<_> = (0..8).map(|i| system.run_limited(&gpu, move || i * 2)).collect();
+        /// for task in &mut tasks {
+        ///     task.wait();
+        /// }
+        /// assert_eq!(tasks[3].value(), Ok(6));
+        /// ```
+        pub fn limiter(&self, max_concurrent: usize) -> Limiter {
+            assert!(max_concurrent > 0, "a limiter needs to let at least one task run");
+            return Limiter{
+                state: Arc::new(LimiterState{
+                    max: max_concurrent,
+                    queue: Mutex::new(LimiterQueue{ running: 0, waiting: VecDeque::new() }),
+                }),
+            };
+        }
+
+        /// Creates a [`WorkGroup`] to run a category of tasks through, e.g. all background loading of
+        /// a level, so it can be paused or cancelled in one call.
+        ///
+        /// ```
+        /// use task_system::{GetValueError, TaskBase, TaskSystem};
+        ///
+        /// let system = TaskSystem::new(2);
+        /// let io = system.group("io");
+        /// let disk = io.group("disk");
+        /// io.pause();
+        /// let mut task = disk.run(|| 42);
+        /// io.cancel_all();
+        /// task.wait();
+        /// assert_eq!(task.value(), Err(GetValueError::Cancelled));
+        /// ```
+        pub fn group<S>(&self, name: S) -> WorkGroup where S: Into<String> {
+            let mut tree = GroupTree::default();
+            let node = tree.add(name.into(), None);
+            return WorkGroup{ tree: Arc::new(Mutex::new(tree)), node, pool: self.pool.clone() };
+        }
+
+        /// Creates a [`ChildSystem`] queueing its tasks separately and running them on the workers
+        /// of this system.
+        pub fn child(&self) -> ChildSystem {
+            return ChildSystem::new(self.pool.clone());
+        }
+
+        /// Creates a [`Throttle`] letting at most `rate` of its tasks be queued per `per`, e.g. to keep
+        /// outbound requests under a quota. Up to `rate` tasks go through at once after a quiet
+        /// period, after which one token comes back every `per / rate`.
+        ///
+        /// ```
+        /// use std::time::Duration;
+        /// use task_system::{TaskBase, TaskSystem};
+        ///
+        /// let system = TaskSystem::new(2);
+        /// let http = system.throttle(100, Duration::from_secs(1));
+        /// let mut tasks: Vec<_> = (0..3).map(|i| system.run_throttled(&http, move || i)).collect();
+        /// for task in &mut tasks {
+        ///     task.wait();
+        /// }
+        /// assert_eq!(tasks[2].value(), Ok(2));
+        /// ```
+        pub fn throttle(&self, rate: u32, per: Duration) -> Throttle {
+            assert!(rate > 0, "a throttle needs to let at least one task through");
+            let refill = per / rate;
+            assert!(refill > Duration::ZERO, "a throttle can't refill faster than once a nanosecond");
+            return Throttle{
+                state: Arc::new(ThrottleState{
+                    burst: rate,
+                    refill,
+                    timer: self.timer().clone(),
+                    bucket: Mutex::new(Bucket{
+                        tokens: rate,
+                        next_token: Instant::now() + refill,
+                        waiting: VecDeque::new(),
+                        scheduled: false,
+                    }),
+                }),
+            };
+        }
+
+        /// Queues `fun` once `throttle` has a token for it.
+        pub fn run_throttled<F, O>(&self, throttle: &Throttle, fun: F) -> Task<O>
+            where F: FnOnce() -> O + Send + 'static, O: Send + 'static
+        {
+            let (task, job) = self.pool.prepare(move |_| Ok(fun()));
+            task.shared_state.set_status(TaskStatus::Waiting);
+
+            let shared_state = task.shared_state.clone();
+            let pool = self.pool.clone();
+            throttle.acquire(Box::new(move || {
+                if !shared_state.transition(TaskStatus::Waiting, TaskStatus::Queued) {
+                    return false;
+                }
+                pool.execute(Job::new(job));
+                return true;
+            }));
+            return task;
+        }
+
+        /// Queues `fun` once fewer than [`Limiter::max_concurrent`] tasks of `limiter` are running.
+        /// Limited tasks start in submission order.
+        pub fn run_limited<F, O>(&self, limiter: &Limiter, fun: F) -> Task<O>
+            where F: FnOnce() -> O + Send + 'static, O: Send + 'static
+        {
+            let (task, job) = self.pool.prepare(move |_| Ok(fun()));
+            task.shared_state.set_status(TaskStatus::Waiting);
+
+            let shared_state = task.shared_state.clone();
+            limiter.acquire(&self.pool, Box::new(move |permit| {
+                if shared_state.transition(TaskStatus::Waiting, TaskStatus::Queued) {
+                    let pool = permit.pool.clone();
+                    pool.execute(Job::new(move || {
+                        let _permit = permit;
+                        job();
+                    }));
+                }
+            }));
+            return task;
+        }
+
+        /// Queues `fun` in the lane of the given priority.
+        pub fn run_with_priority<F, O>(&self, priority: Priority, fun: F) -> Task<O>
+            where F: FnOnce() -> O + Send + 'static, O: Send + 'static
+        {
+            return self.pool.submit_with_priority(priority, move |_| Ok(fun()));
+        }
+
+        /// Queues `fun` for the worker at `index` only, e.g. one pinned to a core with
+        /// `TaskSystemBuilder::pin_workers`. Such tasks run before the other tasks of the worker
+        /// and aren't stolen by other workers. If the worker is retired before, any worker runs it.
+        ///
+        /// Panics if `index` is not below [`TaskSystem::worker_count`].
+        ///
+        /// ```
+        /// use task_system::{TaskBase, TaskSystem};
+        ///
+        /// let system = TaskSystem::new(2);
+        /// let mut task = system.run_on_worker(1, TaskSystem::current_worker_index);
+        /// task.wait();
+        /// assert_eq!(task.value(), Ok(Some(1)));
+        /// ```
+        pub fn run_on_worker<F, O>(&self, index: usize, fun: F) -> Task<O>
+            where F: FnOnce() -> O + Send + 'static, O: Send + 'static
+        {
+            let (task, job) = self.pool.prepare(move |_| Ok(fun()));
+            task.shared_state.set_status(TaskStatus::Queued);
+            self.pool.execute_on(index, Job::new(job));
+            return task;
+        }
+
+        /// Queues `fun` for the workers of a group set up with `TaskSystemBuilder::worker_groups`.
+        /// Only they run it, unless all of them are retired before.
+        ///
+        /// Panics if there is no such group.
+        pub fn run_on_group<F, O>(&self, group: usize, fun: F) -> Task<O>
+            where F: FnOnce() -> O + Send + 'static, O: Send + 'static
+        {
+            let (task, job) = self.pool.prepare(move |_| Ok(fun()));
+            task.shared_state.set_status(TaskStatus::Queued);
+            self.pool.execute_on_group(group, Job::new(job));
+            return task;
+        }
+
+        /// Queues `fun` with the given execution order instead of the one of the builder. Only matters
+        /// when called from a worker.
+        pub fn run_with_order<F, O>(&self, order: ExecutionOrder, fun: F) -> Task<O>
+            where F: FnOnce() -> O + Send + 'static, O: Send + 'static
+        {
+            let (task, job) = self.pool.prepare(move |_| Ok(fun()));
+            task.shared_state.set_status(TaskStatus::Queued);
+            self.pool.execute_ordered(Priority::Normal, order, Job::new(job));
+            return task;
+        }
+
+        /// Queues `fun`, handing it a [`CancellationToken`] that is tripped by [`Task::cancel`].
+        pub fn run_cancellable<F, O>(&self, fun: F) -> Task<O>
+            where F: FnOnce(CancellationToken) -> O + Send + 'static, O: Send + 'static
+        {
+            return self.pool.submit(move |token| Ok(fun(token)));
+        }
+
+        /// Queues `fun` if `bytes` more fit into the memory budget. The bytes are released once `fun`
+        /// returns or panics.
+        pub fn run_with_mem<F, O>(&self, bytes: usize, fun: F) -> Result<Task<O>, SubmitError>
+            where F: FnOnce() -> O + Send + 'static, O: Send + 'static
+        {
+            let reservation = match self.memory_budget.reserve(bytes) {
+                Some(reservation) => reservation,
+                None => return Err(SubmitError::MemoryBudgetExceeded),
+            };
+
+            // The reservation is dropped when the closure returns or unwinds, before the task completes.
+            return Ok(self.pool.submit(move |_| {
+                let _reservation = reservation;
+                return Ok(fun());
+            }));
+        }
+
+        /// Runs `fun` without a handle or any shared state, the cheapest way to submit work whose
+        /// output nobody reads. A panic of `fun` is swallowed, see [`TaskSystem::spawn_logged`] to
+        /// report it.
+        pub fn spawn_detached<F, O>(&self, fun: F) where F: FnOnce() -> O + Send + 'static {
+            let busy = self.pool.registry.busy_guard();
+            self.pool.execute(Job::new(move || {
+                let _busy = busy;
+                // Nobody is interested in the panic, but it mustn't take the worker down.
+                let _ = panic::catch_unwind(AssertUnwindSafe(fun));
+            }));
+        }
+
+        /// Runs `fun` without a handle, reporting a panic to the panic logger instead of losing it.
+        pub fn spawn_logged<F, O>(&self, fun: F) where F: FnOnce() -> O + Send + 'static {
+            let panic_logger = self.panic_logger.clone();
+            let busy = self.pool.registry.busy_guard();
+            self.pool.execute(Job::new(move || {
+                let _busy = busy;
+                run_logged(&panic_logger, fun);
+            }));
+        }
+
+        /// Runs `fun` `replicas` times and completes with the result returned by a strict majority
+        /// of them.
+        pub fn run_replicated<F, O>(&self, replicas: usize, fun: F) -> Task<Result<O, Disagreement<O>>>
+            where F: Fn() -> O + Send + Sync + Clone + 'static, O: Clone + Eq + Hash + Send + 'static
+        {
+            assert!(replicas > 0, "run_replicated needs at least one replica");
+
+            let latch = DependencyLatch::new();
+            let tasks: Vec<Task<O>> = (0..replicas)
+                .map(|_| {
+                    let fun = fun.clone();
+                    let task = self.pool.submit(move |_| Ok(fun()));
+                    latch.depend_on(&task);
+                    return task;
+                })
+                .collect();
+
+            return self.pool.run_when(latch, move |_| {
+                let mut counts = HashMap::new();
+                let mut results = Vec::new();
+                for mut task in tasks {
+                    let output = task.take_input()?;
+                    let count = counts.entry(output.clone()).or_insert(0);
+                    if *count == 0 {
+                        results.push(output);
+                    }
+                    *count += 1;
+                }
+
+                match counts.into_iter().find(|(_, count)| count * 2 > replicas) {
+                    Some((output, _)) => return Ok(Ok(output)),
+                    None => return Ok(Err(Disagreement{ results })),
+                }
+            });
+        }
+
+        /// Runs `fun` with the outputs of `tasks`, in the same order, once every one of them has
+        /// completed. The outputs are moved into `fun`, so [`Task::value`] on the listed tasks
+        /// reports them as taken afterwards.
+        ///
+        /// ```
+        /// use task_system::{TaskBase, TaskSystem};
+        ///
+        /// let system = TaskSystem::new(2);
+        /// let a = system.run(|| 1);
+        /// let b = system.run(|| 2);
+        /// let mut sum = system.run_after(&[&a, &b], |inputs| inputs.iter().sum::<i32>());
+        /// sum.wait();
+        /// assert_eq!(sum.value(), Ok(3));
+        /// ```
+        pub fn run_after<I, F, O>(&self, tasks: &[&Task<I>], fun: F) -> Task<O>
+            where I: Send + 'static, F: FnOnce(Vec<I>) -> O + Send + 'static, O: Send + 'static
+        {
+            let latch = DependencyLatch::new();
+            let inputs: Vec<Arc<TaskSharedState<I>>> = tasks.iter()
+                .map(|task| {
+                    latch.depend_on(*task);
+                    return task.shared_state.clone();
+                })
+                .collect();
+
+            return self.pool.run_when(latch, move |_| {
+                let inputs = inputs.iter()
+                    .map(|input| input.take_input())
+                    .collect::<Result<Vec<I>, GetValueError>>()?;
+                return Ok(fun(inputs));
+            });
+        }
+
+        /// Runs `fun` with a [`Scope`] whose tasks may borrow data that outlives the call. Returns
+        /// only after every task spawned in the scope has finished, even if `fun` panics.
+        ///
+        /// Blocks the calling thread, so calling it from a worker needs another worker to be free.
+        ///
+        /// ```
+        /// use task_system::TaskSystem;
+        ///
+        /// let system = TaskSystem::new(2);
+        /// let numbers = vec![1, 2, 3, 4];
+        /// let (left, right) = numbers.split_at(2);
+        ///
+        /// let sums = system.scope(|s| {
+        ///     let mut left = s.run(|| left.iter().sum::<i32>());
+        ///     let mut right = s.run(|| right.iter().sum::<i32>());
+        ///     return (left.join(), right.join());
+        /// });
+        /// assert_eq!(sums, (Ok(3), Ok(7)));
+        /// ```
+        pub fn scope<'env, F, R>(&self, fun: F) -> R
+            where F: for<'scope> FnOnce(&'scope Scope<'scope, 'env>) -> R
+        {
+            let scope = Scope{
+                pool: self.pool.clone(),
+                tracker: Arc::new(ScopeTracker{
+                    running: Mutex::new(0),
+                    finished: Condvar::new(),
+                }),
+                scope: PhantomData,
+                env: PhantomData,
+            };
+
+            let result = panic::catch_unwind(AssertUnwindSafe(|| fun(&scope)));
+            scope.tracker.wait_all();
+            match result {
+                Ok(result) => return result,
+                Err(payload) => panic::resume_unwind(payload),
+            }
+        }
+
+        /// Queues `fun` once `delay` has passed. The task is [`TaskStatus::Waiting`] until then.
+        pub fn run_after_delay<F, O>(&self, delay: Duration, fun: F) -> Task<O>
+            where F: FnOnce() -> O + Send + 'static, O: Send + 'static
+        {
+            return self.run_at(Instant::now() + delay, fun);
+        }
+
+        /// Runs `fun` every `interval` at a [`Cadence::FixedRate`], starting one interval from now,
+        /// until the returned handle is stopped.
+        ///
+        /// ```
+        /// use std::{sync::{Arc, atomic::{AtomicUsize, Ordering}}, thread, time::Duration};
+        /// use task_system::TaskSystem;
+        ///
+        /// let system = TaskSystem::new(1);
+        /// let ticks = Arc::new(AtomicUsize::new(0));
+        /// let counter = ticks.clone();
+        /// let heartbeat = system.run_periodic(Duration::from_millis(10), move || {
+        ///     counter.fetch_add(1, Ordering::SeqCst);
+        /// });
+        ///
+        /// thread::sleep(Duration::from_millis(100));
+        /// heartbeat.stop();
+        /// assert!(ticks.load(Ordering::SeqCst) > 0);
+        /// ```
+        pub fn run_periodic<F>(&self, interval: Duration, fun: F) -> PeriodicTask
+            where F: FnMut() + Send + 'static
+        {
+            return self.run_periodic_with(Cadence::FixedRate, interval, fun);
+        }
+
+        /// Runs `fun` every `interval` with the given cadence until the returned handle is stopped.
+        /// Panics of `fun` are reported to the panic logger and don't stop the task.
+        pub fn run_periodic_with<F>(&self, cadence: Cadence, interval: Duration, fun: F) -> PeriodicTask
+            where F: FnMut() + Send + 'static
+        {
+            let stopped = Arc::new(AtomicBool::new(false));
+            PeriodicRun{
+                fun,
+                at: Instant::now() + interval,
+                interval,
+                cadence,
+                stopped: stopped.clone(),
+                pool: self.pool.clone(),
+                timer: self.timer().clone(),
+                panic_logger: self.panic_logger.clone(),
+            }.schedule();
+
+            return PeriodicTask{ stopped };
+        }
+
+        /// Queues `fun` at `at`, or right away if it already passed. The task is
+        /// [`TaskStatus::Waiting`] until then. Tasks still waiting when the system is dropped never
+        /// run.
+        ///
+        /// ```
+        /// use std::time::{Duration, Instant};
+        /// use task_system::{TaskBase, TaskSystem};
+        ///
+        /// let system = TaskSystem::new(1);
+        /// let start = Instant::now();
+        /// let mut task = system.run_at(start + Duration::from_millis(20), move || start.elapsed());
+        /// task.wait();
+        /// assert!(task.value().unwrap() >= Duration::from_millis(20));
+        /// ```
+        pub fn run_at<F, O>(&self, at: Instant, fun: F) -> Task<O>
+            where F: FnOnce() -> O + Send + 'static, O: Send + 'static
+        {
+            return self.pool.run_at(self.timer(), at, move |_| Ok(fun()));
+        }
+
+        fn timer(&self) -> &Arc<Timer> {
+            return self.timer.get_or_init(Timer::new);
+        }
+
+        /// Runs `fun` on a separate pool meant for work that blocks, like file or network I/O, so
+        /// it doesn't hold up the worker threads. The pool starts a thread whenever no idle one is
+        /// around and lets threads exit after idling for a while.
+        ///
+        /// ```
+        /// use task_system::{TaskBase, TaskSystem};
+        ///
+        /// let system = TaskSystem::new(1);
+        /// let mut task = system.run_blocking(|| std::fs::metadata(".").is_ok());
+        /// task.wait();
+        /// assert_eq!(task.value(), Ok(true));
+        /// ```
+        pub fn run_blocking<F, O>(&self, fun: F) -> Task<O>
+            where F: FnOnce() -> O + Send + 'static, O: Send + 'static
+        {
+            let (task, job) = self.pool.prepare(move |_| Ok(fun()));
+            task.shared_state.set_status(TaskStatus::Queued);
+            self.blocking.get_or_init(BlockingPool::new).execute(Job::new(job));
+            return task;
+        }
+
+        /// Stops the system once every queued task ran and joins the workers. Tasks waiting for a
+        /// delay are cancelled, as is anything submitted after the workers stopped.
+        ///
+        /// Dropping the system shuts it down the same way.
+        pub fn shutdown(mut self) {
+            self.shut_down(false);
+        }
+
+        /// Stops the system as soon as the running tasks return and joins the workers. Every task
+        /// that didn't start yet is cancelled.
+        pub fn shutdown_now(mut self) {
+            self.shut_down(true);
+        }
+
+        fn shut_down(&mut self, now: bool) {
+            if let Some(timer) = self.timer.get() {
+                timer.close();
+            }
+            if let Some(blocking) = self.blocking.get() {
+                blocking.shutdown(now);
+            }
+            // Nobody pumps them anymore.
+            let main_tasks = mem::take(&mut *self.main_tasks.lock().unpoison());
+            drop(main_tasks);
+            self.pool.shutdown(now);
+        }
+
+        run_after_fn!(run_after2, a: A, b: B);
+        run_after_fn!(run_after3, a: A, b: B, c: C);
+        run_after_fn!(run_after4, a: A, b: B, c: C, d: D);
+        run_after_fn!(run_after5, a: A, b: B, c: C, d: D, e: E);
+        run_after_fn!(run_after6, a: A, b: B, c: C, d: D, e: E, f: G);
+        run_after_fn!(run_after7, a: A, b: B, c: C, d: D, e: E, f: G, g: H);
+        run_after_fn!(run_after8, a: A, b: B, c: C, d: D, e: E, f: G, g: H, h: I);
+    }
+
+    /// Creates a task system with one worker per available core.
+    impl Default for TaskSystem {
+        fn default() -> Self {
+            return Self::builder().build();
+        }
+    }
+
+    impl Drop for TaskSystem {
+        fn drop(&mut self) {
+            self.shut_down(false);
+        }
+    }
+
+    // *********************************************************************************************
+    static GLOBAL: OnceLock<TaskSystem> = OnceLock::new();
+
+    /// Configures the process wide task system used by [`spawn`] and [`spawn_blocking`]. Returns
+    /// false if it is running already, started by an earlier call or the first use.
+    pub fn init(builder: TaskSystemBuilder) -> bool {
+        let mut builder = Some(builder);
+        GLOBAL.get_or_init(|| builder.take().unwrap().build());
+        return builder.is_none();
+    }
+
+    /// Process wide task system, started with one worker per CPU on first use unless configured
+    /// by [`init`] before. It is never shut down, so its tasks may be cut short when the process
+    /// exits.
+    pub fn global() -> &'static TaskSystem {
+        return GLOBAL.get_or_init(|| TaskSystem::builder().build());
+    }
+
+    /// Queues `fun` on the [`global`] task system.
+    ///
+    /// ```
+    /// use task_system::TaskBase;
+    ///
+    /// let mut task = task_system::spawn(|| 6 * 7);
+    /// task.wait();
+    /// assert_eq!(task.value(), Ok(42));
+    /// ```
+    pub fn spawn<F, O>(fun: F) -> Task<O>
+        where F: FnOnce() -> O + Send + 'static, O: Send + 'static
+    {
+        return global().run(fun);
+    }
+
+    /// Runs `fun` on the blocking pool of the [`global`] task system, see
+    /// [`TaskSystem::run_blocking`].
+    pub fn spawn_blocking<F, O>(fun: F) -> Task<O>
+        where F: FnOnce() -> O + Send + 'static, O: Send + 'static
+    {
+        return global().run_blocking(fun);
+    }
+
+    // *********************************************************************************************
+    #[cfg(all(test, not(loom)))]
+    mod tests {
+        use std::{
+            thread,
+            time,
+            sync::{Barrier},
+        };
+        use super::*;
+    
+        #[test]
+        fn run_single_task() {
+            let system = TaskSystem::new(1);
+
+            let mut task = system.run(move|| {
+                return 1;
+            });
+
+            task.wait();
+            assert_eq!(task.status(), TaskStatus::Completed);
+            assert_eq!(task.value().unwrap(), 1);
+            assert_eq!(task.value(), Err(GetValueError::AlreadyTaken));
+        }
+
+        #[test]
+        fn run_single_blocking_task() {
+            let system = TaskSystem::new(1);
+
+            let barrier = Arc::new(Barrier::new(2));
+            let barrier_clone = barrier.clone();
+            let mut task = system.run(move|| {
+                barrier_clone.wait();
+            });
+
+            thread::sleep(time::Duration::from_secs(1));
+            assert_eq!(task.status(), TaskStatus::Running);
+            barrier.wait();
+            task.wait();
+            assert_eq!(task.status(), TaskStatus::Completed);
+            assert_eq!(task.value(), Ok(()));
+            assert_eq!(task.value(), Err(GetValueError::AlreadyTaken));
+        }
+
+        #[test]
+        fn wait_timeout_gives_up_on_blocked_task() {
+            let system = TaskSystem::new(1);
+
+            let barrier = Arc::new(Barrier::new(2));
+            let barrier_clone = barrier.clone();
+            let mut task = system.run(move|| {
+                barrier_clone.wait();
+                return 1;
+            });
+
+            let start = time::Instant::now();
+            assert!(!task.wait_timeout(time::Duration::from_millis(100)));
+            assert!(start.elapsed() >= time::Duration::from_millis(100));
+            assert!(!task.wait_deadline(time::Instant::now()));
+
+            barrier.wait();
+            assert!(task.wait_timeout(time::Duration::from_secs(5)));
+            assert_eq!(task.value(), Ok(1));
+            assert!(task.wait_deadline(time::Instant::now()));
+        }
+
+        #[test]
+        fn await_all_returns_ordered_results() {
+            let system = TaskSystem::new(4);
+
+            let tasks: Vec<Task<i32>> = (0..8)
+                .map(|i| system.run(move|| {
+                    thread::sleep(time::Duration::from_millis(((8 - i) * 10) as u64));
+                    return i;
+                }))
+                .collect();
+
+            assert_eq!(tasks.await_all(), Ok((0..8).collect()));
+        }
+
+        #[test]
+        fn await_as_completed_yields_in_completion_order() {
+            let system = TaskSystem::new(2);
+
+            let barrier = Arc::new(Barrier::new(2));
+            let barrier_clone = barrier.clone();
+            let slow = system.run(move|| {
+                barrier_clone.wait();
+                return 1;
+            });
+            let fast = system.run(move|| {
+                return 2;
+            });
+
+            let mut completed = vec![slow, fast].await_as_completed();
+            assert_eq!(completed.next(), Some(Ok(2)));
+            barrier.wait();
+            assert_eq!(completed.next(), Some(Ok(1)));
+            assert_eq!(completed.next(), None);
+        }
+
+        #[test]
+        fn task_group_joins_in_submission_order() {
+            let system = TaskSystem::new(4);
+
+            let mut group = TaskGroup::new();
+            for i in 0..200 {
+                group.push(system.run(move|| {
+                    thread::sleep(time::Duration::from_micros(((200 - i) * 10) as u64));
+                    return i;
+                }));
+            }
+
+            let (index, first) = group.next_completed().unwrap();
+            assert_eq!(first, Ok(index as i32));
+            let expected: Vec<i32> = (0..200).filter(|&i| i != index as i32).collect();
+            assert_eq!(group.join(), Ok(expected));
+
+            let tasks: Vec<Task<i32>> = (0..8).map(|i| system.run(move|| i)).collect();
+            assert_eq!(join_all(tasks), Ok((0..8).collect()));
+        }
+
+        #[test]
+        fn result_tasks_short_circuit_on_errors() {
+            let system = TaskSystem::new(1);
+
+            let failing = system.run(|| -> Result<i32, String> { Err(String::from("bad input")) });
+            let spinning: Vec<Task<Result<i32, String>>> = (0..2).map(|_| system.run_cancellable(|token| {
+                while !token.is_cancelled() {
+                    thread::sleep(time::Duration::from_millis(1));
+                }
+                return Ok(0);
+            })).collect();
+            let mut tasks = vec![failing];
+            tasks.extend(spinning.iter().cloned());
+            assert_eq!(join_all_ok(tasks), Err(TaskError::Err(String::from("bad input"))));
+            for mut task in spinning {
+                task.wait();
+                assert_eq!(task.status(), TaskStatus::Cancelled);
+            }
+
+            let tasks: Vec<Task<Result<i32, String>>> = (0..4).map(|i| system.run(move|| Ok(i)).map_ok(|i| i * 2)).collect();
+            assert_eq!(join_all_ok(tasks), Ok(vec![0, 2, 4, 6]));
+
+            let panicking = system.run(|| -> Result<i32, String> { panic!("no result") }).then_ok(|i| Ok(i + 1));
+            assert_eq!(panicking.try_join(), Err(TaskError::Task(GetValueError::Panicked(String::from("no result")))));
+        }
+
+        #[test]
+        fn wait_any_returns_first_finished_task() {
+            let system = TaskSystem::new(2);
+
+            let barrier = Arc::new(Barrier::new(2));
+            let barrier_clone = barrier.clone();
+            let mut slow = system.run(move|| {
+                barrier_clone.wait();
+                return String::from("slow");
+            });
+            let mut fast = system.run(move|| -> i32 {
+                panic!("fast failure");
+            });
+
+            assert_eq!(wait_any(&mut [slow.as_base(), fast.as_base()]), 1);
+            assert_eq!(slow.status(), TaskStatus::Running);
+            barrier.wait();
+            slow.wait();
+            assert_eq!(wait_any(&mut [slow.as_base(), fast.as_base()]), 0);
+        }
+
+        #[test]
+        fn scoped_tasks_borrow_local_data() {
+            let system = TaskSystem::new(2);
+
+            let words = [String::from("scoped"), String::from("tasks")];
+            let mut lengths = vec![0; 2];
+            let finished = AtomicUsize::new(0);
+
+            system.scope(|s| {
+                for (word, length) in words.iter().zip(lengths.iter_mut()) {
+                    let finished = &finished;
+                    s.run(move|| {
+                        thread::sleep(time::Duration::from_millis(50));
+                        *length = word.len();
+                        finished.fetch_add(1, Ordering::SeqCst);
+                    });
+                }
+            });
+
+            assert_eq!(finished.load(Ordering::SeqCst), 2);
+            assert_eq!(lengths, vec![6, 5]);
+        }
+
+        #[test]
+        fn joining_subtasks_helps_instead_of_blocking() {
+            fn fib(ctx: &TaskContext, n: u64) -> u64 {
+                if n < 2 {
+                    return n;
+                }
+                let a = ctx.spawn(move|ctx| fib(ctx, n - 1));
+                let b = ctx.spawn(move|ctx| fib(ctx, n - 2));
+                return a.join() + b.join();
+            }
+
+            let system = TaskSystem::new(1);
+            let mut task = system.run_with_ctx(|ctx| fib(ctx, 15));
+            assert!(task.wait_timeout(time::Duration::from_secs(30)));
+            assert_eq!(task.value(), Ok(610));
+
+            let mut failing = system.run_with_ctx(|ctx| {
+                let subtask = ctx.spawn(|_| -> i32 {
+                    panic!("leaf failed");
+                });
+                return subtask.join();
+            });
+            failing.wait();
+            assert_eq!(failing.value(), Err(GetValueError::Panicked(String::from("subtask panicked: leaf failed"))));
+        }
+
+        #[test]
+        fn cancelling_a_task_cancels_its_subtasks() {
+            let system = TaskSystem::new(3);
+
+            let (started_tx, started_rx) = mpsc::channel();
+            let (stopped_tx, stopped_rx) = mpsc::channel();
+            let mut root = system.run_with_ctx(move|ctx| {
+                let _child = ctx.spawn(move|ctx| {
+                    let _grandchild = ctx.spawn(move|ctx| {
+                        started_tx.send(()).unwrap();
+                        while !ctx.is_cancelled() {
+                            thread::yield_now();
+                        }
+                        stopped_tx.send(()).unwrap();
+                    });
+                    while !ctx.is_cancelled() {
+                        thread::yield_now();
+                    }
+                });
+                while !ctx.is_cancelled() {
+                    thread::yield_now();
+                }
+            });
+
+            started_rx.recv().unwrap();
+            root.cancel();
+            stopped_rx.recv().unwrap();
+            root.wait();
+            assert_eq!(root.value(), Err(GetValueError::Cancelled));
+        }
+
+        #[test]
+        fn resumable_tasks_let_queued_tasks_run_between_steps() {
+            let system = TaskSystem::new(1);
+            let log = Arc::new(Mutex::new(Vec::new()));
+            let (tx, rx) = mpsc::channel::<()>();
+            let mut blocker = system.run(move|| rx.recv().unwrap());
+
+            let resumable_log = log.clone();
+            let mut steps = 0;
+            let mut resumable = system.run_resumable(move |_| {
+                steps += 1;
+                resumable_log.lock().unwrap().push(format!("step {}", steps));
+                return if steps == 3 { Step::Done(steps) } else { Step::Yield };
+            });
+            let others: Vec<_> = (0..2).map(|index| {
+                let log = log.clone();
+                return system.run(move|| log.lock().unwrap().push(format!("task {}", index)));
+            }).collect();
+
+            tx.send(()).unwrap();
+            blocker.wait();
+            resumable.wait();
+            assert_eq!(others.await_all(), Ok(vec![(), ()]));
+            assert_eq!(resumable.value(), Ok(3));
+            assert_eq!(*log.lock().unwrap(), vec!["step 1", "task 0", "task 1", "step 2", "step 3"]);
+
+            let mut cancelled = system.run_resumable(|ctx| {
+                while !ctx.is_cancelled() {
+                    thread::yield_now();
+                }
+                return Step::<()>::Yield;
+            });
+            while cancelled.status() != TaskStatus::Running {}
+            cancelled.cancel();
+            cancelled.wait();
+            assert_eq!(cancelled.value(), Err(GetValueError::Cancelled));
+        }
+
+        #[test]
+        fn task_locals_stay_with_their_task() {
+            crate::task_local! {
+                static REQUEST_ID: u32;
+            }
+
+            let system = TaskSystem::new(1);
+            let mut task = system.run_with_ctx(|ctx| {
+                REQUEST_ID.set(1);
+                // Runs on the same worker while the parent waits for it.
+                let subtask = ctx.spawn(|_| {
+                    let inherited = REQUEST_ID.try_with(|id| *id);
+                    REQUEST_ID.set(2);
+                    return inherited;
+                });
+                return (subtask.join(), REQUEST_ID.with(|id| *id));
+            });
+
+            task.wait();
+            assert_eq!(task.value(), Ok((None, 1)));
+            assert_eq!(REQUEST_ID.try_with(|id| *id), None);
+        }
+
+        #[test]
+        fn progress_is_reported_to_listeners() {
+            let system = TaskSystem::new(1);
+
+            let (tx, rx) = mpsc::channel();
+            let (step_tx, step_rx) = mpsc::channel::<()>();
+            let mut task = system.run_with_progress(move|progress| {
+                for step in 1..=2 {
+                    step_rx.recv().unwrap();
+                    progress.set(step as f32 * 0.25);
+                }
+                progress.set(7.0);
+            });
+            assert_eq!(task.progress(), 0.0);
+            task.on_progress(move|value| tx.send(value).unwrap());
+
+            step_tx.send(()).unwrap();
+            assert_eq!(rx.recv().unwrap(), 0.25);
+            assert_eq!(task.progress(), 0.25);
+            assert_eq!(system.tasks()[0].progress, 0.25);
+
+            step_tx.send(()).unwrap();
+            assert_eq!(rx.iter().take(2).collect::<Vec<f32>>(), vec![0.5, 1.0]);
+            task.wait();
+            assert_eq!(task.progress(), 1.0);
+        }
+
+        #[test]
+        fn streamed_items_arrive_before_the_task_finishes() {
+            let system = TaskSystem::new(1);
+
+            let (tx, rx) = mpsc::channel::<()>();
+            let mut stream = system.run_stream(move|items| {
+                items.send(1);
+                rx.recv().unwrap();
+                items.send(2);
+                panic!("producer failed");
+            });
+
+            assert_eq!(stream.recv(), Some(1));
+            assert!(!stream.status().is_finished());
+            assert_eq!(stream.try_recv(), None);
+            tx.send(()).unwrap();
+            assert_eq!(stream.by_ref().collect::<Vec<i32>>(), vec![2]);
+            assert_eq!(stream.value(), Err(GetValueError::Panicked(String::from("producer failed"))));
+        }
+
+        #[test]
+        fn deterministic_system_runs_tasks_when_stepped() {
+            let system = TaskSystem::new_deterministic();
+
+            let order = Arc::new(Mutex::new(Vec::new()));
+            let order_clone = order.clone();
+            let mut low = system.run_with_priority(Priority::Low, move|| order_clone.lock().unwrap().push("low"));
+            let order_clone = order.clone();
+            let mut high = system.run_with_priority(Priority::High, move|| order_clone.lock().unwrap().push("high"));
+            let mut forked = system.run_with_ctx(|ctx| {
+                let a = ctx.spawn(|_| 20);
+                let b = ctx.spawn(|_| 22);
+                return a.join() + b.join();
+            });
+            thread::sleep(time::Duration::from_millis(10));
+            assert_eq!(low.status(), TaskStatus::Queued);
+
+            // The subtasks run while the forking task joins them.
+            assert_eq!(system.run_until_idle(), 3);
+            assert_eq!(*order.lock().unwrap(), vec!["high", "low"]);
+            assert_eq!(high.value(), Ok(()));
+            assert_eq!(low.value(), Ok(()));
+            assert_eq!(forked.value(), Ok(42));
+            assert!(!system.step());
+        }
+
+        #[test]
+        fn wait_idle_waits_for_spawned_work_and_continuations() {
+            let system = Arc::new(TaskSystem::new(2));
+            let done = Arc::new(AtomicUsize::new(0));
+
+            for _ in 0..16 {
+                let (inner_system, done) = (system.clone(), done.clone());
+                system.spawn_detached(move|| {
+                    thread::sleep(time::Duration::from_millis(1));
+                    let done = done.clone();
+                    inner_system.run(|| thread::sleep(time::Duration::from_millis(1)))
+                        .then(move|_| done.fetch_add(1, Ordering::SeqCst));
+                });
+            }
+            system.wait_idle();
+            assert_eq!(done.load(Ordering::SeqCst), 16);
+
+            let (tx, rx) = mpsc::channel::<()>();
+            let blocked = system.run(move|| rx.recv().unwrap());
+            assert!(!system.wait_idle_timeout(time::Duration::from_millis(20)));
+            tx.send(()).unwrap();
+            assert!(system.wait_idle_timeout(time::Duration::from_secs(10)));
+            assert!(blocked.completed());
+
+            let pipeline = Pipeline::new(&system)
+                .stage(|item: usize| {
+                    thread::sleep(time::Duration::from_millis(5));
+                    return item * 2;
+                }, 1)
+                .build();
+            for item in 0..4 {
+                pipeline.push(item);
+            }
+            system.wait_idle();
+            let outputs: Vec<usize> = std::iter::from_fn(|| pipeline.try_pull()).collect();
+            assert_eq!(outputs, vec![0, 2, 4, 6]);
+
+            // Without workers, the waiting thread runs the tasks, including ones queued while it
+            // waits.
+            let system = Arc::new(TaskSystem::new_deterministic());
+            let mut task = system.run(|| 42);
+            system.wait_idle();
+            assert_eq!(task.value(), Ok(42));
+
+            let (tx, rx) = mpsc::channel::<()>();
+            let blocked = system.run(move|| rx.recv().unwrap());
+            let runner_system = system.clone();
+            let runner = thread::spawn(move|| runner_system.step());
+            while !blocked.running() {}
+            let waiter_system = system.clone();
+            let waiter = thread::spawn(move|| waiter_system.wait_idle());
+            thread::sleep(time::Duration::from_millis(20));
+            let queued = system.run(|| 7);
+            while !queued.completed() {}
+            tx.send(()).unwrap();
+            assert!(runner.join().unwrap());
+            waiter.join().unwrap();
+            assert!(blocked.completed());
+            assert_eq!(queued.join(), Ok(7));
+        }
+
+        #[test]
+        fn child_systems_wait_for_their_own_tasks_only() {
+            let system = TaskSystem::new(2);
+            let (tx, rx) = mpsc::channel::<()>();
+            let mut blocker = system.run(move|| rx.recv().unwrap());
+            while !blocker.running() {}
+
+            let child = system.child();
+            let done = Arc::new(AtomicUsize::new(0));
+            let tasks: Vec<Task<usize>> = (0..8).map(|_| {
+                let done = done.clone();
+                return child.run(move|| done.fetch_add(1, Ordering::SeqCst));
+            }).collect();
+            drop(child);
+            assert_eq!(done.load(Ordering::SeqCst), 8);
+            assert!(tasks.iter().all(|task| task.completed()));
+            assert_eq!(blocker.status(), TaskStatus::Running);
+            tx.send(()).unwrap();
+            blocker.wait();
+
+            let system = TaskSystem::new_deterministic();
+            let child = system.child();
+            let order = Arc::new(Mutex::new(Vec::new()));
+            for priority in [Priority::Low, Priority::Normal, Priority::High] {
+                let order = order.clone();
+                child.run_with_priority(priority, move|| order.lock().unwrap().push(priority));
+            }
+            assert_eq!(child.pending(), 3);
+            child.wait_idle();
+            assert_eq!(*order.lock().unwrap(), vec![Priority::High, Priority::Normal, Priority::Low]);
+        }
+
+        #[test]
+        fn jobs_run_or_drop_their_closures_once() {
+            struct Counted(Arc<AtomicUsize>);
+
+            impl Drop for Counted {
+                fn drop(&mut self) {
+                    self.0.fetch_add(1, Ordering::SeqCst);
+                }
+            }
+
+            let ran = Arc::new(AtomicUsize::new(0));
+            let dropped = Arc::new(AtomicUsize::new(0));
+            // A closure kept inline in the job and one too large for it.
+            let small = || {
+                let (ran, counted) = (ran.clone(), Counted(dropped.clone()));
+                return Job::new(move || {
+                    let _counted = counted;
+                    ran.fetch_add(1, Ordering::SeqCst);
+                });
+            };
+            let large = || {
+                let (ran, counted, padding) = (ran.clone(), Counted(dropped.clone()), [1u64; 8]);
+                return Job::new(move || {
+                    let _counted = counted;
+                    ran.fetch_add(padding.len(), Ordering::SeqCst);
+                });
+            };
+
+            small().run();
+            large().run();
+            assert_eq!(ran.load(Ordering::SeqCst), 9);
+            drop(small());
+            drop(large());
+            assert_eq!(ran.load(Ordering::SeqCst), 9);
+            assert_eq!(dropped.load(Ordering::SeqCst), 4);
+        }
+
+        #[test]
+        fn mpmc_queue_hands_out_every_value_once() {
+            let queue = Arc::new(mpmc::MpmcQueue::new(16));
+            let producers: Vec<_> = (0..4).map(|producer| {
+                let queue = queue.clone();
+                return thread::spawn(move|| {
+                    for value in producer * 1000..(producer + 1) * 1000 {
+                        let mut value = value;
+                        while let Err(rejected) = queue.push(value) {
+                            value = rejected;
+                            thread::yield_now();
+                        }
+                    }
+                });
+            }).collect();
+            let taken = Arc::new(AtomicUsize::new(0));
+            let consumers: Vec<_> = (0..2).map(|_| {
+                let (queue, taken) = (queue.clone(), taken.clone());
+                return thread::spawn(move|| {
+                    let mut values = Vec::new();
+                    while taken.load(Ordering::SeqCst) < 4000 {
+                        match queue.pop() {
+                            Some(value) => {
+                                values.push(value);
+                                taken.fetch_add(1, Ordering::SeqCst);
+                            },
+                            None => thread::yield_now(),
+                        }
+                    }
+                    return values;
+                });
+            }).collect();
+
+            for producer in producers {
+                producer.join().unwrap();
+            }
+            let mut values: Vec<usize> = consumers.into_iter().flat_map(|consumer| consumer.join().unwrap()).collect();
+            values.sort();
+            assert_eq!(values, (0..4000).collect::<Vec<_>>());
+            assert_eq!(queue.pop(), None);
+        }
+
+        #[test]
+        fn inline_systems_run_tasks_on_the_submitting_thread() {
+            let system = Arc::new(TaskSystem::builder().workers(4).run_inline(true).build());
+            assert_eq!(system.worker_count(), 0);
+
+            let caller = thread::current().id();
+            let order = Arc::new(Mutex::new(Vec::new()));
+            let (system_clone, order_clone) = (system.clone(), order.clone());
+            let mut outer = system.run(move|| {
+                assert_eq!(thread::current().id(), caller);
+                let order = order_clone.clone();
+                let inner = system_clone.run(move|| order.lock().unwrap().push("inner"));
+                // Tasks queued by a task run once it returns.
+                assert_eq!(inner.status(), TaskStatus::Queued);
+                order_clone.lock().unwrap().push("outer");
+                return inner;
+            });
+            assert_eq!(outer.status(), TaskStatus::Completed);
+            let inner = outer.value().unwrap();
+            assert_eq!(inner.status(), TaskStatus::Completed);
+            assert_eq!(*order.lock().unwrap(), vec!["outer", "inner"]);
+
+            let forked = system.run_with_ctx(|ctx| {
+                let a = ctx.spawn(|_| 20);
+                let b = ctx.spawn(|_| 22);
+                return a.join() + b.join();
+            });
+            let dependent = system.run_after(&[&forked], |sums| sums[0] * 2);
+            assert_eq!(dependent.join(), Ok(84));
+        }
+
+        #[test]
+        fn waiting_threads_help_with_queued_tasks() {
+            let system = Arc::new(TaskSystem::new(1));
+            let (task_tx, task_rx) = mpsc::channel();
+            let (release_tx, release_rx) = mpsc::channel::<()>();
+            let system_clone = system.clone();
+            let blocker = system.run(move|| {
+                // Queued on the deque of the worker, which then blocks.
+                task_tx.send(system_clone.run(|| thread::current().id())).unwrap();
+                release_rx.recv().unwrap();
+            });
+
+            let mut stolen = task_rx.recv().unwrap();
+            stolen.wait_and_help();
+            assert_eq!(stolen.join(), Ok(thread::current().id()));
+
+            let group: TaskGroup<_> = (0..3).map(|index| system.run(move|| index)).collect();
+            assert_eq!(group.join_and_help(), Ok(vec![0, 1, 2]));
+            assert!(!system.help_once());
+
+            release_tx.send(()).unwrap();
+            assert_eq!(blocker.join(), Ok(()));
+        }
+
+        #[test]
+        fn weighted_fair_scheduler_shares_workers_by_weight() {
+            let system = TaskSystem::builder().workers(1).scheduler(WeightedFairScheduler::new(&[3, 1])).build();
+            let (tx, rx) = mpsc::channel::<()>();
+            let blocker = system.run_for_tenant(2, move|| rx.recv().unwrap());
+            while !blocker.running() {}
+
+            let order = Arc::new(Mutex::new(Vec::new()));
+            let tasks: Vec<_> = (0..40)
+                .map(|index| {
+                    let tenant = (index % 4 == 0) as usize;
+                    let order = order.clone();
+                    return system.run_for_tenant(tenant, move|| {
+                        thread::sleep(time::Duration::from_millis(2));
+                        order.lock().unwrap().push(tenant);
+                    });
+                })
+                .collect();
+            tx.send(()).unwrap();
+            tasks.await_all().unwrap();
+
+            // Tenant 0 gets three of every four runs while both have work.
+            let order = order.lock().unwrap();
+            let first = order[..20].iter().filter(|&&tenant| tenant == 0).count();
+            assert!((13..=17).contains(&first), "tenant 0 ran {} of the first 20 tasks", first);
+
+            let busy = system.metrics().tenant_busy;
+            assert_eq!(busy.len(), 3);
+            assert!(busy[0] > busy[1]);
+        }
+
+        #[test]
+        fn custom_schedulers_pick_the_next_task() {
+            fn run_order<S>(scheduler: S) -> Vec<usize> where S: Scheduler {
+                let system = TaskSystem::builder().workers(1).scheduler(scheduler).build();
+                let (tx, rx) = mpsc::channel::<()>();
+                let blocker = system.run_with_priority(Priority::Normal, move|| rx.recv().unwrap());
+                while !blocker.running() {}
+
+                let order = Arc::new(Mutex::new(Vec::new()));
+                let priorities = [Priority::Low, Priority::High, Priority::Normal, Priority::High];
+                let mut tasks: Vec<_> = priorities.iter().enumerate()
+                    .map(|(index, &priority)| {
+                        let order = order.clone();
+                        return system.run_with_priority(priority, move|| order.lock().unwrap().push(index));
+                    })
+                    .collect();
+                tx.send(()).unwrap();
+                for task in &mut tasks {
+                    task.wait();
+                }
+
+                let order = order.lock().unwrap().clone();
+                return order;
+            }
+
+            assert_eq!(run_order(FifoScheduler::default()), vec![0, 1, 2, 3]);
+            assert_eq!(run_order(LifoScheduler::default()), vec![3, 2, 1, 0]);
+            assert_eq!(run_order(PriorityScheduler::default()), vec![1, 3, 2, 0]);
+            assert_eq!(run_order(DeadlineScheduler::default()), vec![0, 1, 2, 3]);
+        }
+
+        #[test]
+        fn main_thread_tasks_run_when_pumped() {
+            let system = TaskSystem::new(1);
+
+            let main_thread = thread::current().id();
+            let mut tasks: Vec<_> = (0..3)
+                .map(|_| system.run_on_main(move|| thread::current().id() == main_thread))
+                .collect();
+            thread::sleep(time::Duration::from_millis(10));
+            assert_eq!(tasks[0].status(), TaskStatus::Queued);
+
+            assert_eq!(system.pump_main_tasks(time::Duration::ZERO), 1);
+            assert_eq!(system.pump_main_tasks(time::Duration::from_secs(1)), 2);
+            assert_eq!(system.pump_main_tasks(time::Duration::from_secs(1)), 0);
+            for task in &mut tasks {
+                assert_eq!(task.value(), Ok(true));
+            }
+
+            let mut abandoned = system.run_on_main(|| ());
+            system.shutdown();
+            assert_eq!(abandoned.value(), Err(GetValueError::Cancelled));
+        }
+
+        #[test]
+        fn continuations_run_on_their_target() {
+            let system = TaskSystem::new(4);
+
+            for worker in 0..4 {
+                let mut task = system.run_on_worker(worker, || ())
+                    .then_on(Target::SameWorker, |_| TaskSystem::current_worker_index());
+                task.wait();
+                assert_eq!(task.value(), Ok(Some(worker)));
+            }
+
+            let main_thread = thread::current().id();
+            let mut task = system.run(|| 1).then_on(Target::MainThread, move|n| (n, thread::current().id() == main_thread));
+            while !task.completed() {
+                system.pump_main_tasks(time::Duration::from_millis(1));
+            }
+            assert_eq!(task.value(), Ok((1, true)));
+
+            let mut source = system.run(|| 1);
+            source.wait();
+            system.shutdown();
+            let mut orphan = source.then_on(Target::MainThread, |n| n);
+            assert_eq!(orphan.value(), Err(GetValueError::Cancelled));
+        }
+
+        #[test]
+        fn local_results_are_finalized_on_the_pumping_thread() {
+            let system = TaskSystem::new(1);
+            let mut local = system.run_with_local_result(|| 21, |half| std::rc::Rc::new(half * 2));
+            let mut failed = system.run_with_local_result(|| -> i32 { panic!("no input") }, std::rc::Rc::new);
+            while !(local.completed() && failed.status().is_finished()) {
+                system.pump_main_tasks(time::Duration::from_millis(1));
+            }
+            assert_eq!(*local.value().unwrap(), 42);
+            assert_eq!(local.value().err(), Some(GetValueError::AlreadyTaken));
+            assert_eq!(failed.value().err(), Some(GetValueError::Panicked(String::from("no input"))));
+
+            // Pumped by another thread, the output would end up out of the owner's reach.
+            let system = Arc::new(system);
+            let mut stray = system.run_with_local_result(|| 1, std::rc::Rc::new);
+            let pumping_system = system.clone();
+            thread::spawn(move|| {
+                while pumping_system.pump_main_tasks(time::Duration::from_millis(1)) == 0 {}
+            }).join().unwrap();
+            stray.wait();
+            assert!(matches!(stray.value(), Err(GetValueError::Panicked(_))));
+        }
+
+        #[test]
+        fn frame_phases_are_separated_by_barriers() {
+            let system = Arc::new(TaskSystem::new(3));
+
+            let updated = Arc::new(AtomicUsize::new(0));
+            let mut updates: Vec<_> = (0..3)
+                .map(|_| {
+                    let updated = updated.clone();
+                    return system.run_in_phase(FramePhase::Update, move|| {
+                        thread::sleep(time::Duration::from_millis(10));
+                        updated.fetch_add(1, Ordering::SeqCst);
+                    });
+                })
+                .collect();
+            let updated_clone = updated.clone();
+            let system_clone = system.clone();
+            let mut pre_update = system.run_in_phase(FramePhase::PreUpdate, move|| {
+                // Queued while the frame runs, so it is part of it.
+                return system_clone.run_in_phase(FramePhase::PostUpdate, move|| updated_clone.load(Ordering::SeqCst));
+            });
+            thread::sleep(time::Duration::from_millis(10));
+            assert_eq!(pre_update.status(), TaskStatus::Waiting);
+
+            system.run_frame();
+            for update in &mut updates {
+                assert!(update.completed());
+            }
+            let mut post_update = pre_update.value().unwrap();
+            assert_eq!(post_update.value(), Ok(3));
+        }
+
+        #[test]
+        fn tasks_past_their_deadline_time_out() {
+            let system = TaskSystem::new(1);
+
+            let (tx, rx) = mpsc::channel::<()>();
+            let mut slow = system.run_with_deadline(time::Duration::from_millis(10), move|_| rx.recv().unwrap());
+            let mut quick = system.run_with_deadline(time::Duration::from_secs(60), |_| 1);
+            slow.wait();
+            assert_eq!(slow.status(), TaskStatus::TimedOut);
+            assert_eq!(slow.value(), Err(GetValueError::TimedOut));
+            assert_eq!(system.metrics().failed, 1);
+
+            tx.send(()).unwrap();
+            quick.wait();
+            assert_eq!(quick.value(), Ok(1));
+            assert_eq!(slow.status(), TaskStatus::TimedOut);
+        }
+
+        #[test]
+        fn limited_tasks_respect_the_concurrency_cap() {
+            let system = TaskSystem::new(4);
+            let limiter = system.limiter(2);
+
+            let running = Arc::new(AtomicUsize::new(0));
+            let peak = Arc::new(AtomicUsize::new(0));
+            let mut tasks: Vec<_> = (0..8)
+                .map(|_| {
+                    let running = running.clone();
+                    let peak = peak.clone();
+                    return system.run_limited(&limiter, move|| {
+                        let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                        peak.fetch_max(now, Ordering::SeqCst);
+                        thread::sleep(time::Duration::from_millis(5));
+                        running.fetch_sub(1, Ordering::SeqCst);
+                    });
+                })
+                .collect();
+            assert_eq!(tasks[7].status(), TaskStatus::Waiting);
+            tasks[7].cancel();
+
+            let mut unlimited = system.run_with_priority(Priority::Normal, || 1);
+            unlimited.wait();
+            assert_eq!(unlimited.value(), Ok(1));
+            for task in &mut tasks {
+                task.wait();
+            }
+            assert_eq!(peak.load(Ordering::SeqCst), 2);
+            assert_eq!(tasks[7].value(), Err(GetValueError::Cancelled));
+        }
+
+        #[test]
+        fn work_groups_pause_resume_and_cancel_their_subgroups() {
+            let system = TaskSystem::new(2);
+            let io = system.group("io");
+            let disk = io.group("disk");
+            let audio = system.group("audio");
+            assert_eq!(disk.name(), "disk");
+
+            io.pause();
+            assert!(disk.is_paused());
+            let mut held = disk.run(|| 1);
+            let mut other = audio.run(|| 2);
+            other.wait();
+            assert_eq!(other.value(), Ok(2));
+            assert!(!held.wait_timeout(time::Duration::from_millis(20)));
+
+            // Resuming a subgroup doesn't lift the pause of its parent.
+            disk.resume();
+            assert!(!held.wait_timeout(time::Duration::from_millis(20)));
+            io.resume();
+            held.wait();
+            assert_eq!(held.value(), Ok(1));
+
+            io.pause();
+            let tasks: Vec<_> = (0..4).map(|index| disk.run(move|| index)).collect();
+            io.cancel_all();
+            io.resume();
+            assert!(tasks.into_iter().all(|mut task| { task.wait(); task.value() == Err(GetValueError::Cancelled) }));
+            let mut later = disk.run(|| 3);
+            later.wait();
+            assert_eq!(later.value(), Ok(3));
+        }
+
+        #[test]
+        fn cached_tasks_share_one_run_per_key() {
+            let system = TaskSystem::builder().workers(2).cache_ttl(time::Duration::from_millis(50)).build();
+            let runs = Arc::new(AtomicUsize::new(0));
+            let load = |key: &'static str| {
+                let runs = runs.clone();
+                return system.run_cached(key, move|| {
+                    runs.fetch_add(1, Ordering::SeqCst);
+                    thread::sleep(time::Duration::from_millis(5));
+                    return key.len();
+                });
+            };
+
+            let tasks = vec![load("a"), load("a"), load("bb")];
+            assert_eq!(tasks.await_all(), Ok(vec![1, 1, 2]));
+            assert_eq!(runs.load(Ordering::SeqCst), 2);
+
+            // Completed runs are handed out until they expire.
+            assert_eq!(vec![load("a")].await_all(), Ok(vec![1]));
+            assert_eq!(runs.load(Ordering::SeqCst), 2);
+            thread::sleep(time::Duration::from_millis(60));
+            assert_eq!(vec![load("a")].await_all(), Ok(vec![1]));
+            assert_eq!(runs.load(Ordering::SeqCst), 3);
+            system.clear_cache();
+            assert_eq!(vec![load("a")].await_all(), Ok(vec![1]));
+            assert_eq!(runs.load(Ordering::SeqCst), 4);
+        }
+
+        #[test]
+        fn cached_tasks_nest_on_inline_systems() {
+            let system = Arc::new(TaskSystem::builder().run_inline(true).cache_ttl(time::Duration::from_secs(60)).build());
+            let inner = system.clone();
+            let outer = system.run_cached("outer", move|| inner.run_cached("inner", move|| 1).join().unwrap() + 1);
+            assert_eq!(outer.join(), Ok(2));
+            assert_eq!(system.run_cached("inner", move|| 0).join(), Ok(1));
+        }
+
+        #[test]
+        fn throttled_tasks_are_queued_at_the_given_rate() {
+            let system = TaskSystem::new(2);
+            let throttle = system.throttle(4, time::Duration::from_millis(80));
+            let started = time::Instant::now();
+            let tasks: Vec<_> = (0..8).map(|_| system.run_throttled(&throttle, move|| started.elapsed())).collect();
+            let waits = tasks.await_all().unwrap();
+            // A burst of four, then one every 20ms.
+            assert!(waits[..4].iter().all(|wait| *wait < time::Duration::from_millis(20)));
+            assert!(waits[7] >= time::Duration::from_millis(80));
+
+            // Subgroups share the throttle of their group, and cancelled tasks hand their token back.
+            let http = system.group("http");
+            http.set_throttle(Some(system.throttle(1, time::Duration::from_millis(50))));
+            let api = http.group("api");
+            let mut first = api.run(|| 1);
+            let waiting = api.run(|| 2);
+            let mut last = api.run(|| 3);
+            assert_eq!(waiting.status(), TaskStatus::Waiting);
+            waiting.cancel();
+            first.wait();
+            last.wait();
+            assert_eq!(last.value(), Ok(3));
+        }
+
+        #[test]
+        fn combinators_derive_tasks_without_blocking() {
+            let system = TaskSystem::new(1);
+
+            let (tx, rx) = mpsc::channel::<()>();
+            let blocked = system.run(move|| rx.recv().unwrap());
+            let mut mapped = blocked.map(|()| "done");
+            assert_eq!(mapped.status(), TaskStatus::Waiting);
+
+            let inner = system.run(|| 42);
+            let mut flattened = system.run(|| ()).map(move|()| inner).flatten();
+            let failing = system.run(|| -> i32 { panic!("lhs failed") });
+            let mut zipped = Task::zip(failing, system.run(|| 1));
+
+            tx.send(()).unwrap();
+            mapped.wait();
+            assert_eq!(mapped.value(), Ok("done"));
+            flattened.wait();
+            assert_eq!(flattened.value(), Ok(42));
+            zipped.wait();
+            assert_eq!(zipped.value(), Err(GetValueError::Panicked(String::from("lhs failed"))));
+        }
+
+        #[test]
+        fn global_system_is_configured_once() {
+            assert!(init(TaskSystem::builder().workers(2).thread_name("global")));
+            assert!(!init(TaskSystem::builder().workers(3)));
+            assert_eq!(global().worker_count(), 2);
+
+            let mut task = spawn(|| thread::current().name().map(String::from));
+            let mut blocking = spawn_blocking(|| 1);
+            task.wait();
+            blocking.wait();
+            assert!(task.value().unwrap().unwrap().starts_with("global-"));
+            assert_eq!(blocking.value(), Ok(1));
+        }
+
+        #[test]
+        fn tasks_can_be_submitted_from_many_threads() {
+            fn assert_send_sync<T: Send + Sync>() {}
+            assert_send_sync::<TaskSystem>();
+
+            let system = TaskSystem::builder().workers(2).max_queue_depth(4).build();
+            let total = AtomicUsize::new(0);
+            thread::scope(|scope| {
+                for _ in 0..4 {
+                    scope.spawn(|| {
+                        let mut tasks: Vec<_> = (0..100).map(|n| system.run(move|| n)).collect();
+                        for task in &mut tasks {
+                            task.wait();
+                            total.fetch_add(task.value().unwrap(), Ordering::SeqCst);
+                        }
+                    });
+                }
+            });
+            assert_eq!(total.load(Ordering::SeqCst), 4 * 4950);
+        }
+
+        #[test]
+        fn map_parallel_keeps_item_order() {
+            let system = TaskSystem::new(3);
+
+            let words: Vec<String> = (0..100).map(|i| i.to_string()).collect();
+            let lengths = system.map_parallel(words.clone(), |word| word.len());
+            assert_eq!(lengths, words.iter().map(|word| word.len()).collect::<Vec<usize>>());
+            assert!(system.map_parallel(Vec::<i32>::new(), |n| n).is_empty());
+
+            let sum = Arc::new(AtomicUsize::new(0));
+            let sum_clone = sum.clone();
+            system.for_each_parallel((1..=10).collect(), move|n| {
+                sum_clone.fetch_add(n, Ordering::SeqCst);
+            });
+            assert_eq!(sum.load(Ordering::SeqCst), 55);
+        }
+
+        #[test]
+        fn paused_worker_does_not_pick_up_tasks() {
+            let system = TaskSystem::new(2);
+            system.pause_worker(0);
+
+            let workers: Vec<Task<Option<usize>>> = (0..4)
+                .map(|_| system.run(move|| {
+                    thread::sleep(time::Duration::from_millis(10));
+                    return TaskSystem::current_worker_index();
+                }))
+                .collect();
+            assert_eq!(workers.await_all(), Ok(vec![Some(1); 4]));
+
+            let barrier = Arc::new(Barrier::new(2));
+            let barrier_clone = barrier.clone();
+            let mut blocking = system.run(move|| {
+                barrier_clone.wait();
+                return TaskSystem::current_worker_index();
+            });
+            while blocking.status() != TaskStatus::Running {}
+
+            let mut task = system.run(move|| {
+                return TaskSystem::current_worker_index();
+            });
+            thread::sleep(time::Duration::from_millis(100));
+            assert_eq!(task.status(), TaskStatus::Queued);
+
+            system.resume_worker(0);
+            task.wait();
+            assert_eq!(task.value(), Ok(Some(0)));
+
+            barrier.wait();
+            blocking.wait();
+            assert_eq!(blocking.value(), Ok(Some(1)));
+        }
+
+        #[test]
+        fn run_after3_combines_typed_outputs() {
+            let system = TaskSystem::new(2);
+
+            let barrier = Arc::new(Barrier::new(2));
+            let barrier_clone = barrier.clone();
+            let number = system.run(move|| {
+                return 1;
+            });
+            let text = system.run(move|| {
+                return String::from("two");
+            });
+            let flag = system.run(move|| {
+                barrier_clone.wait();
+                return true;
+            });
+
+            let mut combined = system.run_after3(number, text, flag, |(number, text, flag)| {
+                return format!("{} {} {}", number, text, flag);
+            });
+
+            thread::sleep(time::Duration::from_millis(100));
+            assert_eq!(combined.status(), TaskStatus::Waiting);
+            barrier.wait();
+            combined.wait();
+            assert_eq!(combined.value(), Ok(String::from("1 two true")));
+        }
+
+        #[test]
+        fn run_after_waits_for_every_dependency() {
+            let system = TaskSystem::new(2);
+
+            let barrier = Arc::new(Barrier::new(2));
+            let barrier_clone = barrier.clone();
+            let root = system.run(move|| {
+                barrier_clone.wait();
+                return 1;
+            });
+            let mut left_input = root.then(|value| value + 1);
+            let left = system.run_after(&[&left_input], |inputs| inputs[0] * 10);
+            let right = system.run(move|| {
+                return 100;
+            });
+
+            let mut sink = system.run_after(&[&left, &right], |inputs| inputs);
+
+            thread::sleep(time::Duration::from_millis(100));
+            assert_eq!(sink.status(), TaskStatus::Waiting);
+            barrier.wait();
+            sink.wait();
+            assert_eq!(sink.value(), Ok(vec![20, 100]));
+            assert_eq!(left_input.value(), Err(GetValueError::AlreadyTaken));
+        }
+
+        #[test]
+        fn delayed_tasks_wait_for_their_time() {
+            let system = TaskSystem::new(1);
+
+            let start = time::Instant::now();
+            let order = Arc::new(Mutex::new(Vec::new()));
+            let late_order = order.clone();
+            let mut late = system.run_after_delay(time::Duration::from_millis(200), move|| {
+                late_order.lock().unwrap().push("late");
+            });
+            let early_order = order.clone();
+            let mut early = system.run_at(start + time::Duration::from_millis(100), move|| {
+                early_order.lock().unwrap().push("early");
+            });
+
+            assert_eq!(late.status(), TaskStatus::Waiting);
+            assert_eq!(early.status(), TaskStatus::Waiting);
+            late.wait();
+            early.wait();
+            assert!(start.elapsed() >= time::Duration::from_millis(200));
+            assert_eq!(*order.lock().unwrap(), vec!["early", "late"]);
+
+            let mut cancelled = system.run_after_delay(time::Duration::from_millis(50), move|| {});
+            cancelled.cancel();
+            cancelled.wait();
+            assert_eq!(cancelled.value(), Err(GetValueError::Cancelled));
+        }
+
+        #[test]
+        fn periodic_task_runs_until_stopped() {
+            let system = TaskSystem::new(2);
+
+            let (tick_tx, tick_rx) = mpsc::channel();
+            let tick_tx = Mutex::new(tick_tx);
+            let periodic = system.run_periodic_with(Cadence::FixedDelay, time::Duration::from_millis(10), move|| {
+                tick_tx.lock().unwrap().send(time::Instant::now()).unwrap();
+                thread::sleep(time::Duration::from_millis(20));
+            });
+
+            let ticks: Vec<time::Instant> = (0..3)
+                .map(|_| tick_rx.recv_timeout(time::Duration::from_secs(5)).unwrap())
+                .collect();
+            periodic.stop();
+            assert!(periodic.is_stopped());
+            for pair in ticks.windows(2) {
+                assert!(pair[1] - pair[0] >= time::Duration::from_millis(30));
+            }
+
+            // At most the run in progress when stopping still reports.
+            thread::sleep(time::Duration::from_millis(100));
+            let late_ticks = tick_rx.try_iter().count();
+            assert!(late_ticks <= 1, "{} runs after stop", late_ticks);
+        }
+
+        #[test]
+        fn shutdown_runs_queued_tasks() {
+            let system = TaskSystem::new(1);
+
+            let ran = Arc::new(AtomicUsize::new(0));
+            let tasks: Vec<Task<()>> = (0..10)
+                .map(|_| {
+                    let ran = ran.clone();
+                    return system.run(move|| {
+                        thread::sleep(time::Duration::from_millis(5));
+                        ran.fetch_add(1, Ordering::SeqCst);
+                    });
+                })
+                .collect();
+            let mut delayed = system.run_after_delay(time::Duration::from_secs(60), move|| {});
+
+            system.shutdown();
+            assert_eq!(ran.load(Ordering::SeqCst), 10);
+            assert!(tasks.iter().all(|task| task.completed()));
+            assert_eq!(delayed.value(), Err(GetValueError::Cancelled));
+        }
+
+        #[test]
+        fn shutdown_now_cancels_queued_tasks() {
+            let system = TaskSystem::new(1);
+
+            let barrier = Arc::new(Barrier::new(2));
+            let barrier_clone = barrier.clone();
+            let mut running = system.run(move|| {
+                barrier_clone.wait();
+                return 1;
+            });
+            while running.status() != TaskStatus::Running {}
+
+            let mut queued = system.run(move|| {
+                return 2;
+            });
+            let mut dependent = system.run_after(&[&queued], |inputs| inputs[0] * 2);
+
+            let releaser = thread::spawn(move|| {
+                thread::sleep(time::Duration::from_millis(100));
+                barrier.wait();
+            });
+            system.shutdown_now();
+            releaser.join().unwrap();
+            assert_eq!(running.value(), Ok(1));
+            assert_eq!(queued.value(), Err(GetValueError::Cancelled));
+            assert_eq!(dependent.value(), Err(GetValueError::Cancelled));
+        }
+
+        #[test]
+        fn expect_ref_borrows_completed_value() {
+            let system = TaskSystem::new(1);
+
+            let barrier = Arc::new(Barrier::new(2));
+            let barrier_clone = barrier.clone();
+            let mut task = system.run(move|| {
+                barrier_clone.wait();
+                return vec![1, 2, 3];
+            });
+            while task.status() != TaskStatus::Running {}
+
+            let error = panic::catch_unwind(AssertUnwindSafe(|| {
+                task.expect_ref();
+            })).unwrap_err();
+            let message = error.downcast_ref::<String>().unwrap();
+            assert!(message.contains("Running"), "unexpected panic message: {}", message);
+
+            barrier.wait();
+            task.wait();
+            assert_eq!(task.expect_ref().len(), 3);
+            assert_eq!(*task.expect_ref(), vec![1, 2, 3]);
+            assert_eq!(task.value(), Ok(vec![1, 2, 3]));
+        }
+
+        #[test]
+        fn cloned_tasks_share_the_result() {
+            let system = TaskSystem::new(1);
+
+            let barrier = Arc::new(Barrier::new(2));
+            let barrier_clone = barrier.clone();
+            let mut task = system.run(move|| {
+                barrier_clone.wait();
+                return String::from("shared");
+            });
+            let mut observer = task.clone();
+
+            assert!(observer.peek().is_none());
+            assert_eq!(observer.try_value(), None);
+            let waiter = thread::spawn(move|| {
+                observer.wait();
+                return observer.peek().map(|output| output.clone());
+            });
+
+            barrier.wait();
+            assert_eq!(waiter.join().unwrap(), Some(String::from("shared")));
+            assert_eq!(&*task.peek().unwrap(), "shared");
+            assert_eq!(task.try_value(), Some(String::from("shared")));
+            assert_eq!(task.clone().try_value(), None);
+            assert_eq!(task.value(), Err(GetValueError::AlreadyTaken));
+        }
+
+        #[test]
+        fn status_is_readable_while_output_is_borrowed() {
+            let system = TaskSystem::new(1);
+
+            let mut task = system.run(move|| {
+                return 5;
+            });
+            task.wait();
+
+            let output = task.peek().unwrap();
+            assert_eq!(task.status(), TaskStatus::Completed);
+            assert!(task.completed());
+            assert_eq!(*output, 5);
+        }
+
+        #[test]
+        fn completion_callbacks_fire_once_completed() {
+            let system = TaskSystem::new(1);
+
+            let (event_tx, event_rx) = mpsc::channel();
+            let worker_tx = Mutex::new(event_tx.clone());
+            let mut task = system.run_with_callback(move|| 7, move|output| {
+                let worker = TaskSystem::current_worker_index();
+                worker_tx.lock().unwrap().send((*output, worker)).unwrap();
+            });
+            assert_eq!(event_rx.recv_timeout(time::Duration::from_secs(5)), Ok((7, Some(0))));
+
+            let late_tx = Mutex::new(event_tx);
+            task.on_complete(move|output| {
+                late_tx.lock().unwrap().send((*output * 2, TaskSystem::current_worker_index())).unwrap();
+            });
+            assert_eq!(event_rx.try_recv(), Ok((14, None)));
+
+            let called = Arc::new(AtomicBool::new(false));
+            let called_clone = called.clone();
+            let mut failed = system.run(move|| -> i32 {
+                panic!("boom");
+            });
+            failed.on_complete(move|_| called_clone.store(true, Ordering::SeqCst));
+            failed.wait();
+            assert!(!called.load(Ordering::SeqCst));
+            assert_eq!(task.value(), Ok(7));
+        }
+
+        #[test]
+        fn finished_tasks_are_reported_through_channels() {
+            let system = TaskSystem::new(2);
+            let (tx, rx) = mpsc::channel();
+            let (release_tx, release_rx) = mpsc::channel::<()>();
+            let blocked = system.run(move|| release_rx.recv().unwrap());
+            let failed = system.run(|| -> () { panic!("boom") });
+            blocked.notify_finished(tx.clone());
+            failed.notify_finished(tx);
+
+            assert_eq!(rx.recv_timeout(time::Duration::from_secs(5)), Ok(failed.id()));
+            assert!(rx.try_recv().is_err());
+            release_tx.send(()).unwrap();
+            assert_eq!(rx.recv_timeout(time::Duration::from_secs(5)), Ok(blocked.id()));
+            // Every sender is gone once all tasks reported.
+            assert!(rx.recv().is_err());
+
+            // Tasks finished already report right away.
+            assert_eq!(failed.completion_receiver().try_recv(), Ok(failed.id()));
+        }
+
+        #[test]
+        fn pipelines_limit_stage_workers_and_queue_sizes() {
+            let system = TaskSystem::new(4);
+            let running = Arc::new(AtomicUsize::new(0));
+            let most_running = Arc::new(AtomicUsize::new(0));
+            let (running_clone, most_running_clone) = (running.clone(), most_running.clone());
+            let pipeline = Pipeline::new(&system)
+                .capacity(2)
+                .stage(|value: u32| value + 1, 4)
+                .stage(move|value| {
+                    most_running_clone.fetch_max(running_clone.fetch_add(1, Ordering::SeqCst) + 1, Ordering::SeqCst);
+                    thread::sleep(time::Duration::from_millis(1));
+                    running_clone.fetch_sub(1, Ordering::SeqCst);
+                    if value == 5 {
+                        panic!("dropped");
+                    }
+                    return value.to_string();
+                }, 2)
+                .build();
+
+            // Two items per queue fill the pipeline, as nothing is pulled yet.
+            for value in 0..6 {
+                pipeline.push(value);
+            }
+            thread::sleep(time::Duration::from_millis(50));
+            assert_eq!(pipeline.try_push(6), Err(6));
+
+            pipeline.close();
+            let mut outputs: Vec<String> = pipeline.collect();
+            outputs.sort_by_key(|output| output.parse::<u32>().unwrap());
+            assert_eq!(outputs, vec!["1", "2", "3", "4", "6"]);
+            assert!(most_running.load(Ordering::SeqCst) <= 2);
+        }
+
+        #[test]
+        fn watchdog_reports_tasks_without_heartbeat() {
+            let (tx, rx) = mpsc::channel();
+            let system = TaskSystem::builder()
+                .workers(2)
+                .watchdog(time::Duration::from_millis(30), move|hung| tx.send(hung.clone()).unwrap())
+                .build();
+
+            let (stop_tx, stop_rx) = mpsc::channel::<()>();
+            let beating = system.run_named("beating", move|| {
+                while stop_rx.recv_timeout(time::Duration::from_millis(5)).is_err() {
+                    heartbeat();
+                }
+            });
+            let silent = system.run_named("silent", || thread::sleep(time::Duration::from_millis(150)));
+
+            let hung = rx.recv_timeout(time::Duration::from_secs(5)).unwrap();
+            assert_eq!(hung.id, silent.id());
+            assert_eq!(hung.name.as_deref(), Some("silent"));
+            assert!(hung.worker.is_some());
+            assert!(hung.silent >= time::Duration::from_millis(30));
+            assert!(hung.backtrace.is_none());
+
+            // Reported once per silence.
+            assert_eq!(silent.join(), Ok(()));
+            assert!(rx.try_recv().is_err());
+            stop_tx.send(()).unwrap();
+            assert_eq!(beating.join(), Ok(()));
+        }
+
+        #[test]
+        fn stored_tasks_resume_after_a_restart() {
+            use std::{convert::TryInto, io::Write};
+
+            struct Note(u32);
+
+            impl TaskPayload for Note {
+                const KIND: &'static str = "note";
+
+                fn to_bytes(&self) -> Vec<u8> {
+                    return self.0.to_le_bytes().to_vec();
+                }
+
+                fn from_bytes(bytes: &[u8]) -> Option<Self> {
+                    return Some(Note(u32::from_le_bytes(bytes.try_into().ok()?)));
+                }
+            }
+
+            let path = std::env::temp_dir().join(format!("task_system_store_{}.log", std::process::id()));
+            let _ = std::fs::remove_file(&path);
+            {
+                // An earlier run that ended before its tasks ran, the last record cut short.
+                let store = FileTaskStore::open(&path).unwrap();
+                store.push("note", &1u32.to_le_bytes()).unwrap();
+                store.push("other", b"kept").unwrap();
+                store.push("note", &2u32.to_le_bytes()).unwrap();
+            }
+            std::fs::OpenOptions::new().append(true).open(&path).unwrap().write_all(&[1, 7, 0]).unwrap();
+
+            let notes = Arc::new(Mutex::new(Vec::new()));
+            let notes_clone = notes.clone();
+            let system = TaskSystem::builder().workers(1).task_store(FileTaskStore::open(&path).unwrap()).build();
+            system.register_handler(move|Note(note)| notes_clone.lock().unwrap().push(note));
+
+            let resumed = system.resume_stored().unwrap();
+            assert_eq!(resumed.len(), 2);
+            assert!(system.resume_stored().unwrap().is_empty());
+            let added = system.run_serialized(Note(3)).unwrap();
+            resumed.await_all().unwrap();
+            assert_eq!(added.join(), Ok(()));
+            assert_eq!(*notes.lock().unwrap(), vec![1, 2, 3]);
+            drop(system);
+
+            let pending = FileTaskStore::open(&path).unwrap().pending().unwrap();
+            assert_eq!(pending.iter().map(|task| task.kind.as_str()).collect::<Vec<_>>(), vec!["other"]);
+            std::fs::remove_file(&path).unwrap();
+        }
+
+        #[test]
+        fn remote_tasks_run_through_the_executor() {
+            use std::convert::TryInto;
+
+            struct Double(u32);
+
+            impl TaskPayload for Double {
+                const KIND: &'static str = "double";
+
+                fn to_bytes(&self) -> Vec<u8> {
+                    return self.0.to_le_bytes().to_vec();
+                }
+
+                fn from_bytes(bytes: &[u8]) -> Option<Self> {
+                    return Some(Double(u32::from_le_bytes(bytes.try_into().ok()?)));
+                }
+            }
+
+            impl RemoteTask for Double {
+                type Output = Vec<u8>;
+            }
+
+            struct Name;
+
+            impl TaskPayload for Name {
+                const KIND: &'static str = "name";
+
+                fn to_bytes(&self) -> Vec<u8> {
+                    return Vec::new();
+                }
+
+                fn from_bytes(_: &[u8]) -> Option<Self> {
+                    return Some(Name);
+                }
+            }
+
+            impl RemoteTask for Name {
+                type Output = String;
+            }
+
+            // Replies from another thread, standing in for a worker process.
+            struct Channel(mpsc::Sender<(TaskDescriptor, Reply)>);
+
+            impl Executor for Channel {
+                fn execute(&self, descriptor: TaskDescriptor, reply: Reply) {
+                    self.0.send((descriptor, reply)).unwrap();
+                }
+            }
+
+            let local = TaskSystem::new(1);
+            local.register_remote_handler(|Double(n)| (n * 2).to_le_bytes().to_vec());
+            assert_eq!(local.run_remote(Double(21)).join(), Ok(42u32.to_le_bytes().to_vec()));
+            let unknown = TaskDescriptor{ kind: String::from("unknown"), payload: Vec::new() };
+            assert!(matches!(local.run_descriptor(unknown).join(), Err(GetValueError::Panicked(_))));
+
+            let (tx, rx) = mpsc::channel();
+            let system = TaskSystem::builder().workers(1).executor(Channel(tx)).build();
+            let worker = thread::spawn(move|| {
+                for (descriptor, reply) in rx {
+                    match descriptor.decode::<Double>() {
+                        None => reply.send(Ok(vec![0xff])),
+                        Some(Double(0)) => drop(reply),
+                        Some(Double(1)) => reply.send(Err(String::from("worker lost"))),
+                        _ => reply.send(local.run_descriptor(descriptor).join().map_err(|error| format!("{:?}", error))),
+                    }
+                }
+            });
+            assert_eq!(system.run_remote(Double(4)).join(), Ok(8u32.to_le_bytes().to_vec()));
+            assert_eq!(system.run_remote(Double(0)).join(), Err(GetValueError::Cancelled));
+            assert_eq!(system.run_remote(Double(1)).join(), Err(GetValueError::Panicked(String::from("worker lost"))));
+            assert_eq!(system.run_remote(Name).join(), Err(GetValueError::InvalidOutput));
+            drop(system);
+            worker.join().unwrap();
+        }
+
+        #[cfg(feature = "serde")]
+        #[test]
+        fn serde_types_are_remote_payloads_and_outputs() {
+            #[derive(serde::Serialize, serde::Deserialize)]
+            struct Split {
+                text: String,
+            }
+
+            impl SerdePayload for Split {
+                const KIND: &'static str = "split";
+            }
+
+            #[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+            struct Words(Vec<String>);
+
+            impl SerdeOutput for Words {}
+
+            impl RemoteTask for Split {
+                type Output = Words;
+            }
+
+            let system = TaskSystem::new(1);
+            system.register_remote_handler(|Split{ text }| Words(text.split(' ').map(String::from).collect()));
+            let words = system.run_remote(Split{ text: String::from("a b") }).join();
+            assert_eq!(words, Ok(Words(vec![String::from("a"), String::from("b")])));
+
+            let descriptor = TaskDescriptor::new(&Split{ text: String::from("c") });
+            let json = serde_json::to_string(&descriptor).unwrap();
+            let descriptor: TaskDescriptor = serde_json::from_str(&json).unwrap();
+            assert_eq!(descriptor.decode::<Split>().map(|split| split.text), Some(String::from("c")));
+            let output = system.run_descriptor(descriptor).join().unwrap();
+            assert_eq!(Words::from_bytes(&output), Some(Words(vec![String::from("c")])));
+            // Byte outputs keep their own encoding.
+            assert_eq!(String::from("c").to_bytes(), b"c");
+        }
+
+        #[test]
+        fn replayed_schedules_run_tasks_in_the_recorded_order() {
+            fn run(system: TaskSystem) -> Vec<u64> {
+                let order = Arc::new(Mutex::new(Vec::new()));
+                let tasks: Vec<_> = (0..8).map(|i| {
+                    let order = order.clone();
+                    return system.run(move|| order.lock().unwrap().push(i));
+                }).collect();
+                system.run_until_idle();
+                tasks.await_all().unwrap();
+                return order.lock().unwrap().clone();
+            }
+
+            let scheduler = RecordingScheduler::new(LifoScheduler::default());
+            let recording = scheduler.recording();
+            run(TaskSystem::builder().workers(3).scheduler(scheduler).build());
+            let schedule = recording.schedule();
+            assert_eq!(schedule.entries.len(), 8);
+
+            let mut saved = Vec::new();
+            schedule.save(&mut saved).unwrap();
+            let loaded = Schedule::load(&saved[..]).unwrap();
+            assert_eq!(loaded, schedule);
+            assert!(Schedule::load(&b"1 x\n"[..]).is_err());
+
+            let first = schedule.entries.iter().map(|entry| entry.sequence).min().unwrap();
+            let recorded: Vec<u64> = schedule.entries.iter().map(|entry| entry.sequence - first).collect();
+            for _ in 0..2 {
+                let system = TaskSystem::builder().scheduler(ReplayScheduler::new(loaded.clone())).build_deterministic();
+                assert_eq!(run(system), recorded);
+            }
+        }
+
+        #[test]
+        fn registry_lists_unfinished_tasks() {
+            let system = TaskSystem::new(1);
+
+            let barrier = Arc::new(Barrier::new(2));
+            let barrier_clone = barrier.clone();
+            let mut blocking = system.run_named("blocking", move|| {
+                barrier_clone.wait();
+            });
+            while blocking.status() != TaskStatus::Running {}
+            let mut queued = system.run(move|| {});
+            let mut waiting = system.run_after(&[&queued], |_| {});
+
+            let tasks = system.tasks();
+            let ids: Vec<TaskId> = tasks.iter().map(|task| task.id).collect();
+            assert_eq!(ids, vec![blocking.id(), queued.id(), waiting.id()]);
+            let statuses: Vec<TaskStatus> = tasks.iter().map(|task| task.status).collect();
+            assert_eq!(statuses, vec![TaskStatus::Running, TaskStatus::Queued, TaskStatus::Waiting]);
+            assert_eq!(tasks[0].name.as_deref(), Some("blocking"));
+            assert_eq!(blocking.name(), Some("blocking"));
+            assert_eq!(queued.name(), None);
+            assert!(tasks[0].age >= tasks[1].age);
+
+            barrier.wait();
+            blocking.wait();
+            queued.wait();
+            waiting.wait();
+            assert!(system.tasks().is_empty());
+        }
+
+        #[test]
+        fn status_changes_are_reported_in_order() {
+            let (tx, rx) = mpsc::channel();
+            let system = TaskSystem::builder()
+                .on_status_change(move|change| tx.send(*change).unwrap())
+                .build_deterministic();
+            let ok = system.run(|| {});
+            let failed = system.run(|| panic!("expected"));
+            system.run_until_idle();
+
+            let changes: Vec<StatusChange> = rx.try_iter().collect();
+            let transitions = |id| changes.iter().filter(|change| change.id == id).map(|change| (change.old, change.new)).collect::<Vec<_>>();
+            assert_eq!(transitions(ok.id()), vec![
+                (TaskStatus::None, TaskStatus::Queued),
+                (TaskStatus::Queued, TaskStatus::Running),
+                (TaskStatus::Running, TaskStatus::Completed),
+            ]);
+            assert_eq!(transitions(failed.id()).last(), Some(&(TaskStatus::Running, TaskStatus::Failed)));
+            for pair in changes.windows(2).filter(|pair| pair[0].id == pair[1].id) {
+                assert!(pair[0].at <= pair[1].at);
+                assert!(pair[0].submitted <= pair[0].at);
+            }
+        }
+
+        #[test]
+        fn metrics_count_tasks_by_outcome() {
+            let system = TaskSystem::new(1);
+
+            let barrier = Arc::new(Barrier::new(2));
+            let barrier_clone = barrier.clone();
+            let mut blocking = system.run(move|| {
+                barrier_clone.wait();
+                thread::sleep(time::Duration::from_millis(20));
+            });
+            while blocking.status() != TaskStatus::Running {}
+            let mut failing = system.run(move|| -> i32 {
+                panic!("boom");
+            });
+            let cancelled = system.run(move|| {});
+            cancelled.cancel();
+
+            let metrics = system.metrics();
+            assert_eq!(metrics.submitted, 3);
+            assert_eq!(metrics.cancelled, 1);
+            assert_eq!(metrics.queued, 2);
+
+            barrier.wait();
+            blocking.wait();
+            failing.wait();
+            let metrics = system.metrics();
+            assert_eq!((metrics.completed, metrics.failed, metrics.cancelled), (1, 1, 1));
+            assert!(metrics.worker_busy[0] >= time::Duration::from_millis(20));
+            assert!(metrics.average_wait > time::Duration::ZERO);
+        }
+
+        #[test]
+        fn timings_are_profiled_by_name() {
+            let system = TaskSystem::builder().workers(1).profiling(true).build();
+
+            let mut slow = system.run_named("slow", move|| thread::sleep(time::Duration::from_millis(20)));
+            assert_eq!(slow.timings().running, None);
+            let mut queued = system.run(move|| {});
+            let fast: Vec<_> = (0..3).map(|_| system.run_named("fast", move|| {})).collect();
+
+            slow.wait();
+            queued.wait();
+            fast.await_all().unwrap();
+            assert!(slow.timings().running.unwrap() >= time::Duration::from_millis(20));
+            assert!(queued.timings().queued.unwrap() >= time::Duration::from_millis(15));
+
+            let report = system.profile_report();
+            let names: Vec<_> = report.iter().map(|profile| profile.name.as_deref()).collect();
+            assert_eq!(names[0], Some("slow"));
+            assert!(names.contains(&None));
+            let fast = report.iter().find(|profile| profile.name.as_deref() == Some("fast")).unwrap();
+            assert_eq!(fast.count, 3);
+            assert!(report[0].p95 >= time::Duration::from_millis(20) && report[0].mean == report[0].total);
+
+            assert!(TaskSystem::new(1).profile_report().is_empty());
+        }
+
+        #[test]
+        fn recorded_trace_is_dumped_as_chrome_trace() {
+            let system = TaskSystem::builder().workers(2).record_trace(3).build();
+            let tasks: Vec<_> = (0..4).map(|index| system.run_named(format!("step \"{}\"", index), move|| index)).collect();
+            tasks.await_all().unwrap();
+
+            let trace = system.trace();
+            assert_eq!(trace.len(), 3);
+            assert!(trace.iter().all(|event| event.worker.is_some_and(|worker| worker < 2)));
+
+            let path = std::env::temp_dir().join(format!("task_system_trace_{}.json", std::process::id()));
+            system.dump_trace(&path).unwrap();
+            let json = std::fs::read_to_string(&path).unwrap();
+            std::fs::remove_file(&path).unwrap();
+            assert!(json.starts_with("{\"traceEvents\":[") && json.ends_with("}"));
+            assert_eq!(json.matches("\"ph\":\"X\"").count(), 3);
+            assert!(json.contains(&format!("\"name\":\"{}\"", trace[0].name.as_ref().unwrap().replace('"', "\\\""))));
+
+            assert!(TaskSystem::new(1).trace().is_empty());
+        }
+
+        #[cfg(feature = "tracing")]
+        #[test]
+        fn task_spans_are_children_of_the_submitting_span() {
+            use std::{cell::RefCell, fmt::Debug, sync::atomic::AtomicU64};
+            use tracing::{Event, Id, Metadata, Subscriber, field::{Field, Visit}, span};
+
+            thread_local! {
+                static ENTERED: RefCell<Vec<Id>> = const { RefCell::new(Vec::new()) };
+            }
+
+            #[derive(Default)]
+            struct Recorder {
+                next_id: AtomicU64,
+                spans: Mutex<HashMap<u64, (&'static Metadata<'static>, Option<u64>)>>,
+                statuses: Mutex<Vec<(Option<u64>, String)>>,
+            }
+
+            struct StatusVisitor(Option<String>);
+
+            impl Visit for StatusVisitor {
+                fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+                    if field.name() == "status" {
+                        self.0 = Some(format!("{:?}", value));
+                    }
+                }
+            }
+
+            impl Subscriber for Recorder {
+                fn enabled(&self, _: &Metadata<'_>) -> bool {
+                    return true;
+                }
+
+                fn new_span(&self, attributes: &span::Attributes<'_>) -> Id {
+                    let id = self.next_id.fetch_add(1, Ordering::SeqCst) + 1;
+                    let parent = match attributes.parent() {
+                        Some(parent) => Some(parent.into_u64()),
+                        None if attributes.is_contextual() => ENTERED.with(|entered| entered.borrow().last().map(Id::into_u64)),
+                        None => None,
+                    };
+                    self.spans.lock().unwrap().insert(id, (attributes.metadata(), parent));
+                    return Id::from_u64(id);
+                }
+
+                fn record(&self, _: &Id, _: &span::Record<'_>) {}
+
+                fn record_follows_from(&self, _: &Id, _: &Id) {}
+
+                fn event(&self, event: &Event<'_>) {
+                    let mut visitor = StatusVisitor(None);
+                    event.record(&mut visitor);
+                    if let Some(status) = visitor.0 {
+                        let current = ENTERED.with(|entered| entered.borrow().last().map(Id::into_u64));
+                        self.statuses.lock().unwrap().push((current, status));
+                    }
+                }
+
+                fn enter(&self, id: &Id) {
+                    ENTERED.with(|entered| entered.borrow_mut().push(id.clone()));
+                }
+
+                fn exit(&self, _: &Id) {
+                    ENTERED.with(|entered| entered.borrow_mut().pop());
+                }
+
+                fn current_span(&self) -> tracing_core::span::Current {
+                    let current = ENTERED.with(|entered| entered.borrow().last().cloned());
+                    match current {
+                        Some(id) => {
+                            let metadata = self.spans.lock().unwrap()[&id.into_u64()].0;
+                            return tracing_core::span::Current::new(id, metadata);
+                        },
+                        None => return tracing_core::span::Current::none(),
+                    }
+                }
+            }
+
+            let recorder = Arc::new(Recorder::default());
+            tracing::subscriber::set_global_default(recorder.clone()).unwrap();
+
+            let system = TaskSystem::new(1);
+            let submitter = tracing::info_span!("submitter");
+            let mut task = submitter.in_scope(|| system.run_named("traced", move|| 1));
+            task.wait();
+
+            let submitter = submitter.id().unwrap().into_u64();
+            let task_spans: Vec<u64> = recorder.spans.lock().unwrap().iter()
+                .filter(|(_, (metadata, parent))| metadata.name() == "task" && *parent == Some(submitter))
+                .map(|(&id, _)| id)
+                .collect();
+            assert_eq!(task_spans.len(), 1);
+
+            let statuses = recorder.statuses.lock().unwrap().clone();
+            let task_span = Some(task_spans[0]);
+            assert!(statuses.contains(&(Some(submitter), String::from("Queued"))));
+            assert!(statuses.contains(&(task_span, String::from("Running"))));
+            assert!(statuses.contains(&(task_span, String::from("Completed"))));
+        }
+
+        #[test]
+        fn retry_policy_backs_off_between_attempts() {
+            let system = TaskSystem::new(1);
+
+            let attempts = Arc::new(Mutex::new(Vec::new()));
+            let attempts_clone = attempts.clone();
+            let mut task = system.run_with_policy(RetryPolicy::exponential(3, time::Duration::from_millis(20)), move|| {
+                let mut attempts = attempts_clone.lock().unwrap();
+                attempts.push(time::Instant::now());
+                return Err::<(), usize>(attempts.len());
+            });
+
+            task.wait();
+            assert_eq!(task.value(), Ok(Err(3)));
+            let attempts = attempts.lock().unwrap();
+            assert_eq!(attempts.len(), 3);
+            assert!(attempts[1] - attempts[0] >= time::Duration::from_millis(20));
+            assert!(attempts[2] - attempts[1] >= time::Duration::from_millis(40));
+
+            let mut cancelled = system.run_with_policy(RetryPolicy::fixed(100, time::Duration::from_secs(60)), move|| {
+                return Err::<(), ()>(());
+            });
+            while cancelled.status() != TaskStatus::Waiting {}
+            cancelled.cancel();
+            assert_eq!(cancelled.value(), Err(GetValueError::Cancelled));
+        }
+
+        #[test]
+        fn blocking_tasks_leave_workers_free() {
+            let system = TaskSystem::new(1);
+
+            let barrier = Arc::new(Barrier::new(3));
+            let mut blocking: Vec<_> = (0..2)
+                .map(|_| {
+                    let barrier = barrier.clone();
+                    return system.run_blocking(move|| { barrier.wait(); });
+                })
+                .collect();
+            let mut compute = system.run(move|| { barrier.wait(); });
+
+            compute.wait();
+            assert_eq!(compute.value(), Ok(()));
+            for task in &mut blocking {
+                task.wait();
+                assert_eq!(task.value(), Ok(()));
+            }
+        }
+
+        #[test]
+        fn worker_count_changes_at_runtime() {
+            let system = TaskSystem::new(1);
+            assert_eq!(system.worker_count(), 1);
+
+            system.set_worker_count(3);
+            assert_eq!(system.worker_count(), 3);
+            let barrier = Arc::new(Barrier::new(3));
+            let mut tasks: Vec<_> = (0..3)
+                .map(|_| {
+                    let barrier = barrier.clone();
+                    return system.run(move|| {
+                        barrier.wait();
+                        return TaskSystem::current_worker_index().unwrap();
+                    });
+                })
+                .collect();
+            let mut workers: Vec<_> = tasks.iter_mut().map(|task| { task.wait(); return task.value().unwrap(); }).collect();
+            workers.sort_unstable();
+            assert_eq!(workers, vec![0, 1, 2]);
+
+            system.set_worker_count(1);
+            assert_eq!(system.worker_count(), 1);
+            let mut active = system.run(|| TaskSystem::current_worker_index().unwrap());
+            active.wait();
+            assert_eq!(active.value(), Ok(0));
+        }
+
+        #[test]
+        fn surplus_workers_exit_when_idle_and_return_with_work() {
+            let starts = Arc::new(AtomicUsize::new(0));
+            let counter = starts.clone();
+            let system = TaskSystem::builder()
+                .workers(3)
+                .core_workers(1)
+                .keep_alive(time::Duration::from_millis(10))
+                .on_thread_start(move|| { counter.fetch_add(1, Ordering::SeqCst); })
+                .build();
+            thread::sleep(time::Duration::from_millis(100));
+            assert_eq!(starts.load(Ordering::SeqCst), 3);
+
+            // Three tasks meeting at a barrier need all three workers back.
+            let barrier = Arc::new(Barrier::new(3));
+            let tasks: Vec<_> = (0..3).map(|_| {
+                let barrier = barrier.clone();
+                return system.run(move|| { barrier.wait(); });
+            }).collect();
+            assert_eq!(tasks.await_all(), Ok(vec![(), (), ()]));
+            assert_eq!(starts.load(Ordering::SeqCst), 5);
+            assert_eq!(system.worker_count(), 3);
+        }
+
+        #[test]
+        fn panicking_workers_are_replaced() {
+            let (tx, rx) = mpsc::channel();
+            let tx = Mutex::new(tx);
+            let system = TaskSystem::builder()
+                .workers(1)
+                .on_worker_panic(move |index, message| {
+                    tx.lock().unwrap().send((index, message.to_string())).unwrap();
+                })
+                .build();
+
+            system.pool.execute(Job::new(|| panic!("job failed")));
+            assert_eq!(rx.recv().unwrap(), (0, "job failed".to_string()));
+
+            let mut replacement = system.run(TaskSystem::current_worker_index);
+            replacement.wait();
+            assert_eq!(replacement.value(), Ok(Some(0)));
+            assert_eq!(system.metrics().worker_panics, 1);
+        }
+
+        #[test]
+        fn panics_of_helped_jobs_are_reported() {
+            let system = TaskSystem::new_deterministic();
+            let (log_tx, log_rx) = mpsc::channel();
+            let log_tx = Mutex::new(log_tx);
+            system.set_panic_logger(move |message| {
+                log_tx.lock().unwrap().send(message.to_string()).unwrap();
+            });
+
+            system.pool.execute(Job::new(|| panic!("helped job failed")));
+            assert_eq!(system.run_until_idle(), 1);
+            assert_eq!(log_rx.try_recv(), Ok("helped job failed".to_string()));
+            assert_eq!(system.metrics().worker_panics, 1);
+        }
+
+        #[test]
+        fn attached_job_source_is_drained() {
+            let system = TaskSystem::builder().workers(2).max_queue_depth(2).build();
+            let (log_tx, log_rx) = mpsc::channel();
+            let log_tx = Mutex::new(log_tx);
+            system.set_panic_logger(move |message| {
+                log_tx.lock().unwrap().send(message.to_string()).unwrap();
+            });
+
+            let (tx, rx) = mpsc::channel::<Box<dyn FnOnce() + Send>>();
+            let (done_tx, done_rx) = mpsc::channel();
+            let pump = system.attach_job_source(rx);
+
+            tx.send(Box::new(|| panic!("bad job"))).unwrap();
+            for i in 0..20 {
+                let done_tx = done_tx.clone();
+                tx.send(Box::new(move|| done_tx.send(i).unwrap())).unwrap();
+            }
+            drop(tx);
+            pump.join().unwrap();
+            system.wait_idle();
+
+            let mut done: Vec<i32> = done_rx.try_iter().collect();
+            done.sort();
+            assert_eq!(done, (0..20).collect::<Vec<i32>>());
+            assert_eq!(log_rx.try_recv(), Ok("bad job".to_string()));
+            assert_eq!(system.metrics().worker_panics, 0);
+        }
+
+        #[test]
+        fn detached_tasks_run() {
+            let worker_panics = Arc::new(AtomicUsize::new(0));
+            let worker_panics_clone = worker_panics.clone();
+            let system = TaskSystem::builder()
+                .workers(1)
+                .on_worker_panic(move|_, _| {
+                    worker_panics_clone.fetch_add(1, Ordering::SeqCst);
+                })
+                .build();
+
+            let (done_tx, done_rx) = mpsc::channel();
+            system.spawn_detached(move|| -> i32 {
+                panic!("ignored");
+            });
+            for i in 0..4 {
+                let done_tx = done_tx.clone();
+                system.spawn_detached(move|| done_tx.send(i).unwrap());
+            }
+
+            let mut done: Vec<i32> = (0..4).map(|_| done_rx.recv_timeout(time::Duration::from_secs(5)).unwrap()).collect();
+            done.sort();
+            assert_eq!(done, vec![0, 1, 2, 3]);
+            // A killed worker would have been reported before its replacement ran this.
+            assert_eq!(system.run(move|| ()).join(), Ok(()));
+            assert_eq!(system.metrics().worker_panics, 0);
+            assert_eq!(worker_panics.load(Ordering::SeqCst), 0);
+        }
+
+        #[test]
+        fn spawn_logged_reports_panics() {
+            let system = TaskSystem::new(1);
+
+            let (log_tx, log_rx) = mpsc::channel();
+            let log_tx = Mutex::new(log_tx);
+            system.set_panic_logger(move |message| {
+                log_tx.lock().unwrap().send(message.to_string()).unwrap();
+            });
+
+            system.spawn_logged(move|| {
+                panic!("lost telemetry batch {}", 7);
+            });
+
+            let message = log_rx.recv_timeout(time::Duration::from_secs(5)).unwrap();
+            assert_eq!(message, "lost telemetry batch 7");
+        }
+
+        #[test]
+        fn builder_configures_worker_threads() {
+            let started = Arc::new(AtomicUsize::new(0));
+            let started_clone = started.clone();
+            let system = TaskSystem::builder()
+                .workers(2)
+                .thread_name("ts-worker")
+                .stack_size(4 * 1024 * 1024)
+                .on_thread_start(move|| {
+                    started_clone.fetch_add(1, Ordering::SeqCst);
+                })
+                .build();
+
+            let mut task = system.run(move|| {
+                // Fails with a stack overflow on the default 2 MiB stack of spawned threads.
+                let buffer = [1u8; 3 * 1024 * 1024];
+                let sum = buffer.iter().map(|&byte| byte as usize).sum::<usize>();
+                return (thread::current().name().map(String::from), sum);
+            });
+            task.wait();
+            let (name, sum) = task.value().unwrap();
+            assert!(name.unwrap().starts_with("ts-worker-"));
+            assert_eq!(sum, 3 * 1024 * 1024);
+
+            system.shutdown();
+            assert_eq!(started.load(Ordering::SeqCst), 2);
+        }
+
+        #[test]
+        fn bounded_queue_applies_backpressure() {
+            let system = TaskSystem::builder()
+                .workers(1)
+                .max_queue_depth(2)
+                .build();
+
+            let barrier = Arc::new(Barrier::new(2));
+            let barrier_clone = barrier.clone();
+            let blocking = system.run(move|| {
+                barrier_clone.wait();
+            });
+            while blocking.status() != TaskStatus::Running {}
+
+            let mut first = system.try_run(move|| 1).unwrap();
+            let mut second = system.try_run(move|| 2).unwrap();
+            let QueueFull(rejected) = system.try_run(move|| 3).err().unwrap();
+            assert_eq!(rejected(), 3);
+
+            let releaser = thread::spawn(move|| {
+                thread::sleep(time::Duration::from_millis(100));
+                barrier.wait();
+            });
+            let start = time::Instant::now();
+            let mut third = system.run(move|| 3);
+            assert!(start.elapsed() >= time::Duration::from_millis(50));
+            releaser.join().unwrap();
+
+            let outputs: Vec<i32> = [&mut first, &mut second, &mut third].iter_mut()
+                .map(|task| {
+                    task.wait();
+                    return task.value().unwrap();
+                })
+                .collect();
+            assert_eq!(outputs, vec![1, 2, 3]);
+        }
+
+        #[test]
+        fn saturation_policy_rejects_or_runs_inline() {
+            let saturated = |policy: SaturationPolicy| {
+                let system = TaskSystem::builder().workers(1).max_queue_depth(1).saturation_policy(policy).build();
+                let (tx, rx) = mpsc::channel::<()>();
+                let blocking = system.run(move|| rx.recv().unwrap());
+                while blocking.status() != TaskStatus::Running {}
+                let queued = system.run(TaskSystem::current_worker_index);
+                let overflow = system.run(TaskSystem::current_worker_index);
+                let status = overflow.status();
+                tx.send(()).unwrap();
+                assert_eq!(queued.join(), Ok(Some(0)));
+                return (status, overflow.join());
+            };
+            assert_eq!(saturated(SaturationPolicy::CallerRuns), (TaskStatus::Completed, Ok(None)));
+            assert_eq!(saturated(SaturationPolicy::RejectNewest), (TaskStatus::Failed, Err(GetValueError::Rejected)));
+        }
+
+        #[test]
+        fn blocked_submitters_are_admitted_in_order() {
+            let admitted = |order: AdmissionOrder| {
+                let system = TaskSystem::builder().workers(1).max_queue_depth(1).admission_order(order).build();
+                let (tx, rx) = mpsc::channel::<()>();
+                let blocking = system.run(move|| rx.recv().unwrap());
+                while blocking.status() != TaskStatus::Running {}
+                let queued = system.run(move|| ());
+
+                let admitted = Arc::new(Mutex::new(Vec::new()));
+                thread::scope(|scope| {
+                    for submitter in 0..3 {
+                        let (system, admitted) = (&system, admitted.clone());
+                        scope.spawn(move|| system.run(move|| admitted.lock().unwrap().push(submitter)).join().unwrap());
+                        while system.queue_capacity.state.lock().unwrap().waiting.len() <= submitter {
+                            thread::yield_now();
+                        }
+                    }
+                    tx.send(()).unwrap();
+                });
+                queued.join().unwrap();
+                return admitted.lock().unwrap().clone();
+            };
+            assert_eq!(admitted(AdmissionOrder::Fifo), vec![0, 1, 2]);
+            assert_eq!(admitted(AdmissionOrder::Lifo), vec![2, 1, 0]);
+        }
+
+        #[test]
+        fn deadline_scope_skips_late_steps() {
+            let system = TaskSystem::new(2);
+            let third_ran = Arc::new(AtomicBool::new(false));
+            let third_ran_clone = third_ran.clone();
+
+            let scope = DeadlineScope::new(time::Duration::from_millis(150));
+            let mut task = system.run(move|| {
+                thread::sleep(time::Duration::from_millis(100));
+                return 1;
+            })
+                .then(move|n| {
+                    thread::sleep(time::Duration::from_millis(100));
+                    return n + 1;
+                })
+                .then(move|n| {
+                    third_ran_clone.store(true, Ordering::SeqCst);
+                    return n + 1;
+                });
+            drop(scope);
+
+            task.wait();
+            assert_eq!(task.status(), TaskStatus::TimedOut);
+            assert_eq!(task.value(), Err(GetValueError::TimedOut));
+            assert!(!third_ran.load(Ordering::SeqCst));
+        }
+
+        #[test]
+        fn ready_continuations_run_on_the_caller() {
+            let system = TaskSystem::new(2);
+            let caller = thread::current().id();
+            let and_then_thread = |source: Task<()>| {
+                let inner = system.run(move|| ());
+                let (tx, rx) = mpsc::channel();
+                let chained = source.and_then(move|_| {
+                    tx.send(thread::current().id()).unwrap();
+                    return inner;
+                });
+                chained.join().unwrap();
+                return rx.recv().unwrap();
+            };
+
+            let mut done = system.run(move|| ());
+            done.wait();
+            assert_eq!(done.map(move|_| thread::current().id()).join(), Ok(caller));
+            let mut done = system.run(move|| ());
+            done.wait();
+            assert_eq!(and_then_thread(done), caller);
+
+            let (release, blocked) = mpsc::channel::<()>();
+            let running = system.run(move|| blocked.recv().unwrap());
+            let mapped = running.map(move|_| thread::current().id());
+            release.send(()).unwrap();
+            assert_ne!(mapped.join().unwrap(), caller);
+            let (release, blocked) = mpsc::channel::<()>();
+            let running = system.run(move|| blocked.recv().unwrap());
+            let releaser = thread::spawn(move|| {
+                thread::sleep(time::Duration::from_millis(20));
+                release.send(()).unwrap();
+            });
+            assert_ne!(and_then_thread(running), caller);
+            releaser.join().unwrap();
+        }
+
+        #[test]
+        fn memory_budget_rejects_tasks_over_budget() {
+            const MB: usize = 1024 * 1024;
+            let system = TaskSystem::builder()
+                .workers(2)
+                .memory_budget(100 * MB)
+                .build();
+
+            let barrier = Arc::new(Barrier::new(3));
+            let first_barrier = barrier.clone();
+            let mut first = system.run_with_mem(40 * MB, move|| {
+                first_barrier.wait();
+            }).unwrap();
+            let second_barrier = barrier.clone();
+            let mut second = system.run_with_mem(40 * MB, move|| {
+                second_barrier.wait();
+            }).unwrap();
+
+            let rejected = system.run_with_mem(40 * MB, move|| {});
+            assert_eq!(rejected.err(), Some(SubmitError::MemoryBudgetExceeded));
+
+            barrier.wait();
+            first.wait();
+            second.wait();
+
+            let mut third = system.run_with_mem(40 * MB, move|| {
+                return 3;
+            }).unwrap();
+            third.wait();
+            assert_eq!(third.value(), Ok(3));
+        }
+
+        #[test]
+        fn run_replicated_returns_majority_result() {
+            let system = TaskSystem::new(3);
+
+            let counter = Arc::new(AtomicUsize::new(0));
+            let mut task = system.run_replicated(3, move|| {
+                match counter.fetch_add(1, Ordering::SeqCst) {
+                    0 => return 7,
+                    _ => return 42,
+                }
+            });
+
+            task.wait();
+            assert_eq!(task.value(), Ok(Ok(42)));
+        }
+
+        #[test]
+        fn run_replicated_reports_disagreement() {
+            let system = TaskSystem::new(3);
+
+            let counter = Arc::new(AtomicUsize::new(0));
+            let mut task = system.run_replicated(3, move|| {
+                return counter.fetch_add(1, Ordering::SeqCst);
+            });
+
+            task.wait();
+            let mut disagreement = task.value().unwrap().unwrap_err();
+            disagreement.results.sort();
+            assert_eq!(disagreement.results, vec![0, 1, 2]);
+        }
+
+        #[test]
+        fn continuations_run_after_completion() {
+            let system = TaskSystem::new(2);
+
+            let barrier = Arc::new(Barrier::new(2));
+            let barrier_clone = barrier.clone();
+            let source = system.run(move|| {
+                barrier_clone.wait();
+                return 20;
+            });
+            let other = system.run(move|| {
+                return 1;
+            });
+
+            let mut task = source
+                .then(|value| value * 2)
+                .and_then(move|value| other.then(move|other| value + other));
+
+            thread::sleep(time::Duration::from_millis(100));
+            assert_eq!(task.status(), TaskStatus::Waiting);
+            barrier.wait();
+            task.wait();
+            assert_eq!(task.value(), Ok(41));
+        }
+
+        #[test]
+        fn cancelled_queued_task_never_runs() {
+            let system = TaskSystem::new(1);
+
+            let barrier = Arc::new(Barrier::new(2));
+            let barrier_clone = barrier.clone();
+            let mut blocking = system.run(move|| {
+                barrier_clone.wait();
+            });
+
+            let ran = Arc::new(AtomicBool::new(false));
+            let ran_clone = ran.clone();
+            let mut task = system.run(move|| {
+                ran_clone.store(true, Ordering::SeqCst);
+            });
+            let mut last = system.run(move|| {});
+
+            task.cancel();
+            assert_eq!(task.status(), TaskStatus::Cancelled);
+            task.wait();
+            assert_eq!(task.value(), Err(GetValueError::Cancelled));
+
+            let mut dependent = task.then(|_| 1);
+
+            barrier.wait();
+            blocking.wait();
+            last.wait();
+            assert!(!ran.load(Ordering::SeqCst));
+
+            dependent.wait();
+            assert_eq!(dependent.value(), Err(GetValueError::Cancelled));
+        }
+
+        #[test]
+        fn aborted_queued_task_never_runs() {
+            let system = TaskSystem::new(1);
+
+            let barrier = Arc::new(Barrier::new(2));
+            let barrier_clone = barrier.clone();
+            let mut blocking = system.run(move|| {
+                barrier_clone.wait();
+            });
+            while blocking.status() != TaskStatus::Running {}
+
+            let ran = Arc::new(AtomicBool::new(false));
+            let ran_clone = ran.clone();
+            let mut task = system.run(move|| {
+                ran_clone.store(true, Ordering::SeqCst);
+            });
+            let mut dependent = task.clone().then(|_| 1);
+            let mut last = system.run(move|| {});
+
+            assert!(task.abort());
+            assert_eq!(task.status(), TaskStatus::Aborted);
+            assert!(!task.abort());
+            assert!(!blocking.abort());
+
+            barrier.wait();
+            blocking.wait();
+            last.wait();
+            dependent.wait();
+            assert!(!ran.load(Ordering::SeqCst));
+            assert_eq!(task.value(), Err(GetValueError::Aborted));
+            assert_eq!(blocking.value(), Ok(()));
+            assert_eq!(dependent.value(), Err(GetValueError::Aborted));
+            assert_eq!(system.metrics().aborted, 2);
+        }
+
+        #[test]
+        fn poisoned_task_reports_instead_of_panicking() {
+            let system = TaskSystem::new(1);
+            let mut task = system.run(move|| 1);
+            task.wait();
+
+            let callback = panic::catch_unwind(AssertUnwindSafe(|| {
+                task.on_complete(|_| panic!("callback panicked"));
+            }));
+            assert!(callback.is_err());
+
+            task.wait();
             assert_eq!(task.status(), TaskStatus::Completed);
-            assert_eq!(task.value(), Ok(()));
-            assert_eq!(task.value(), Err(GetValueError::AlreadyTaken));
+            assert!(task.peek().is_some());
+            assert_eq!(task.value(), Err(GetValueError::Poisoned));
+
+            let mut dependent = task.then(|value| value + 1);
+            dependent.wait();
+            assert_eq!(dependent.status(), TaskStatus::Failed);
+            assert_eq!(dependent.value(), Err(GetValueError::Poisoned));
+
+            let mut next = system.run(move|| 2);
+            next.wait();
+            assert_eq!(next.value(), Ok(2));
+        }
+
+        #[test]
+        fn cancellation_token_stops_running_task() {
+            let system = TaskSystem::new(1);
+
+            let mut task = system.run_cancellable(move|token| {
+                while !token.is_cancelled() {
+                    thread::sleep(time::Duration::from_millis(1));
+                }
+                return 1;
+            });
+            while task.status() != TaskStatus::Running {}
+
+            task.cancel();
+            task.wait();
+            assert_eq!(task.status(), TaskStatus::Cancelled);
+            assert_eq!(task.value(), Err(GetValueError::Cancelled));
+        }
+
+        #[test]
+        fn panicking_task_fails() {
+            let system = TaskSystem::new(1);
+
+            let mut task = system.run(move|| -> i32 {
+                panic!("boom");
+            });
+
+            task.wait();
+            assert_eq!(task.status(), TaskStatus::Failed);
+            assert_eq!(task.value(), Err(GetValueError::Panicked(String::from("boom"))));
+
+            let other = system.run(move|| {
+                return 2;
+            });
+            let mut dependent = system.run_after2(task, other, |(a, b)| a + b);
+            dependent.wait();
+            assert_eq!(dependent.value(), Err(GetValueError::Panicked(String::from("boom"))));
+        }
+
+        #[test]
+        fn join_waits_and_takes_the_result() {
+            let system = TaskSystem::new(1);
+            let (tx, rx) = mpsc::channel::<()>();
+            let blocker = system.run(move|| rx.recv().unwrap());
+            let queued = system.run(|| 1);
+            queued.cancel();
+
+            let joiner = thread::spawn(move|| blocker.join());
+            tx.send(()).unwrap();
+            assert_eq!(joiner.join().unwrap(), Ok(()));
+            assert_eq!(queued.join(), Err(GetValueError::Cancelled));
+        }
+
+        #[test]
+        fn higher_priority_tasks_run_first() {
+            let system = TaskSystem::new(1);
+
+            let barrier = Arc::new(Barrier::new(2));
+            let barrier_clone = barrier.clone();
+            let mut blocking = system.run(move|| {
+                barrier_clone.wait();
+            });
+            while blocking.status() != TaskStatus::Running {}
+
+            let order = Arc::new(Mutex::new(Vec::new()));
+            let tasks: Vec<Task<()>> = [Priority::Low, Priority::Normal, Priority::High, Priority::Low, Priority::High]
+                .iter()
+                .map(|&priority| {
+                    let order = order.clone();
+                    return system.run_with_priority(priority, move|| order.lock().unwrap().push(priority));
+                })
+                .collect();
+
+            barrier.wait();
+            blocking.wait();
+            tasks.await_all().unwrap();
+            assert_eq!(*order.lock().unwrap(), vec![
+                Priority::High, Priority::High, Priority::Normal, Priority::Low, Priority::Low,
+            ]);
+        }
+
+        #[test]
+        fn aging_bounds_the_wait_of_low_priority_tasks() {
+            // Keeps a few high priority tasks queued until the low priority one ran or `limit` passed.
+            // The worker is kept busy until they are, or it would take the low priority task at once.
+            let low_priority_wait = |builder: TaskSystemBuilder, limit: time::Duration| {
+                let system = builder.workers(1).build();
+                let barrier = Arc::new(Barrier::new(2));
+                let barrier_clone = barrier.clone();
+                system.run_with_priority(Priority::High, move|| barrier_clone.wait());
+                let start = time::Instant::now();
+                let mut low = system.run_with_priority(Priority::Low, move|| start.elapsed());
+                for _ in 0..4 {
+                    system.run_with_priority(Priority::High, move|| thread::sleep(time::Duration::from_millis(1)));
+                }
+                barrier.wait();
+                while !low.status().is_finished() && start.elapsed() < limit {
+                    if system.metrics().queued < 4 {
+                        system.run_with_priority(Priority::High, move|| thread::sleep(time::Duration::from_millis(1)));
+                    } else {
+                        thread::sleep(time::Duration::from_micros(100));
+                    }
+                }
+                return low.try_value();
+            };
+
+            let limit = time::Duration::from_millis(500);
+            assert_eq!(low_priority_wait(TaskSystem::builder(), limit), None);
+            let wait = low_priority_wait(TaskSystem::builder().priority_aging(time::Duration::from_millis(10)), limit);
+            assert!(wait.unwrap() < limit);
+        }
+
+        struct ThreadWaker(thread::Thread);
+
+        impl std::task::Wake for ThreadWaker {
+            fn wake(self: Arc<Self>) {
+                self.0.unpark();
+            }
+        }
+
+        fn block_on<F: Future>(future: F) -> F::Output {
+            let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+            let mut context = Context::from_waker(&waker);
+            let mut future = Box::pin(future);
+            loop {
+                match future.as_mut().poll(&mut context) {
+                    Poll::Ready(output) => return output,
+                    Poll::Pending => thread::park(),
+                }
+            }
+        }
+
+        #[test]
+        fn task_can_be_awaited() {
+            let system = TaskSystem::new(1);
+
+            let barrier = Arc::new(Barrier::new(2));
+            let barrier_clone = barrier.clone();
+            let task = system.run(move|| {
+                barrier_clone.wait();
+                return 42;
+            });
+
+            let releaser = thread::spawn(move|| {
+                thread::sleep(time::Duration::from_millis(100));
+                barrier.wait();
+            });
+            let value = block_on(async move {
+                return task.await;
+            });
+            releaser.join().unwrap();
+            assert_eq!(value, Ok(42));
+
+            let failed = system.run(move|| -> i32 {
+                panic!("boom");
+            });
+            assert_eq!(block_on(failed), Err(GetValueError::Panicked(String::from("boom"))));
+        }
+
+        #[test]
+        fn idle_worker_steals_locally_spawned_task() {
+            let system = Arc::new(TaskSystem::new(2));
+
+            let inner_system = system.clone();
+            let mut outer = system.run_with_priority(Priority::Normal, move|| {
+                // The inner task lands in this worker's own deque, so it only finishes if the other
+                // worker steals it while this one is blocked.
+                let mut inner = inner_system.run_with_priority(Priority::Normal, move|| {
+                    return TaskSystem::current_worker_index();
+                });
+                inner.wait();
+                return (TaskSystem::current_worker_index(), inner.value().unwrap());
+            });
+
+            outer.wait();
+            let (outer_worker, inner_worker) = outer.value().unwrap();
+            assert!(outer_worker.is_some());
+            assert_ne!(outer_worker, inner_worker);
+        }
+
+        #[test]
+        fn batches_run_from_outside_inside_and_with_a_scheduler() {
+            let system = Arc::new(TaskSystem::new(3));
+            assert_eq!(system.run_batch((0..100).map(|index| move|| index)).await_all(), Ok((0..100).collect()));
+
+            let inner_system = system.clone();
+            let mut outer = system.run(move|| inner_system.run_batch((0..10).map(|index| move|| index)));
+            outer.wait();
+            assert_eq!(outer.value().unwrap().await_all(), Ok((0..10).collect()));
+
+            let system = TaskSystem::builder().workers(2).scheduler(FifoScheduler::default()).build();
+            assert_eq!(system.run_batch((0..10).map(|index| move|| index)).await_all(), Ok((0..10).collect()));
+            assert!(system.run_batch(Vec::<fn()>::new()).is_empty());
+        }
+
+        #[test]
+        fn tasks_run_on_the_worker_they_are_routed_to() {
+            let system = TaskSystem::new(3);
+            let tasks: Vec<_> = (0..30).map(|index| system.run_on_worker(index % 3, TaskSystem::current_worker_index)).collect();
+            assert_eq!(tasks.await_all(), Ok((0..30).map(|index| Some(index % 3)).collect()));
+
+            // A paused worker keeps its routed tasks, and hands them on once it is retired.
+            system.pause_worker(2);
+            let mut routed = system.run_on_worker(2, TaskSystem::current_worker_index);
+            assert!(!routed.wait_timeout(time::Duration::from_millis(20)));
+            system.set_worker_count(2);
+            routed.wait();
+            assert!(matches!(routed.value(), Ok(Some(index)) if index < 2));
+        }
+
+        #[test]
+        fn grouped_tasks_stay_in_their_group() {
+            let system = TaskSystem::builder().workers(4).worker_groups(&[2, 2]).build();
+            let tasks: Vec<_> = (0..40).map(|_| system.run_on_group(1, TaskSystem::current_worker_index)).collect();
+            assert!(tasks.await_all().unwrap().iter().all(|worker| matches!(worker, Some(2) | Some(3))));
+
+            // Once no worker of the group is left, the others take its tasks.
+            system.set_worker_count(2);
+            let mut orphaned = system.run_on_group(1, TaskSystem::current_worker_index);
+            orphaned.wait();
+            assert!(matches!(orphaned.value(), Ok(Some(index)) if index < 2));
+        }
+
+        #[cfg(feature = "capi")]
+        #[test]
+        fn c_interface_runs_tasks() {
+            use std::ffi::c_void;
+            use crate::capi::*;
+
+            unsafe extern "C" fn increment(user_data: *mut c_void) {
+                (*(user_data as *const AtomicUsize)).fetch_add(1, Ordering::SeqCst);
+            }
+
+            let counter = AtomicUsize::new(0);
+            unsafe {
+                let system = ts_create(2);
+                let task = ts_run(system, Some(increment), &counter as *const AtomicUsize as *mut c_void);
+                assert_eq!(ts_task_wait(task), TS_STATUS_COMPLETED);
+                assert_eq!(ts_task_status(task), TS_STATUS_COMPLETED);
+                assert!(ts_run(system, None, std::ptr::null_mut()).is_null());
+                ts_task_release(task);
+                ts_destroy(system);
+            }
+            assert_eq!(counter.load(Ordering::SeqCst), 1);
+        }
+
+        #[cfg(all(feature = "affinity", target_os = "linux"))]
+        #[test]
+        fn cpu_lists_are_parsed() {
+            assert_eq!(scheduler::parse_cpu_list("0-3,8,10-11\n"), Some(vec![0, 1, 2, 3, 8, 10, 11]));
+            assert_eq!(scheduler::parse_cpu_list(""), Some(vec![]));
+            assert_eq!(scheduler::parse_cpu_list("0-x"), None);
+        }
+
+        #[cfg(all(feature = "affinity", target_os = "linux"))]
+        #[test]
+        fn pinned_workers_stay_on_their_core() {
+            let system = TaskSystem::builder().workers(2).pin_workers(&[0]).build();
+            let tasks: Vec<_> = (0..20).map(|index| system.run_on_worker(index % 2, || unsafe { libc::sched_getcpu() })).collect();
+            assert_eq!(tasks.await_all(), Ok(vec![0; 20]));
+        }
+
+        #[test]
+        fn spawned_tasks_follow_execution_order() {
+            let spawn_three = |default: ExecutionOrder, order: Option<ExecutionOrder>| {
+                let system = Arc::new(TaskSystem::builder().workers(1).execution_order(default).build());
+                let started = Arc::new(Mutex::new(Vec::new()));
+
+                let inner_system = system.clone();
+                let inner_started = started.clone();
+                let mut outer = system.run(move|| {
+                    // The only worker is busy here, so all three wait in its deque until this returns.
+                    return (0..3).map(|index| {
+                        let started = inner_started.clone();
+                        let fun = move|| started.lock().unwrap().push(index);
+                        return match order {
+                            Some(order) => inner_system.run_with_order(order, fun),
+                            None => inner_system.run(fun),
+                        };
+                    }).collect::<Vec<_>>();
+                });
+
+                outer.wait();
+                for mut task in outer.value().unwrap() {
+                    task.wait();
+                }
+                let mutex = started.lock().unwrap();
+                return mutex.clone();
+            };
+
+            assert_eq!(spawn_three(ExecutionOrder::Lifo, None), vec![2, 1, 0]);
+            assert_eq!(spawn_three(ExecutionOrder::Fifo, None), vec![0, 1, 2]);
+            assert_eq!(spawn_three(ExecutionOrder::Lifo, Some(ExecutionOrder::Fifo)), vec![0, 1, 2]);
+            assert_eq!(spawn_three(ExecutionOrder::Fifo, Some(ExecutionOrder::Lifo)), vec![2, 1, 0]);
+        }
+    }
+
+    // *********************************************************************************************
+    // Models of the task system's synchronization, checked by loom in every interleaving of the
+    // threads. Run alone in a build on loom's primitives, with
+    // `RUSTFLAGS="--cfg loom" cargo test --release --lib loom`.
+    #[cfg(all(test, loom))]
+    mod loom_tests {
+        use super::*;
+
+        // Bounds the preemptions of each explored interleaving, which still covers the ordering
+        // bugs found in practice and keeps whole task systems within reach.
+        fn model<F>(fun: F) where F: Fn() + Send + Sync + 'static {
+            let mut builder = loom::model::Builder::new();
+            builder.preemption_bound = Some(2);
+            builder.check(fun);
+        }
+
+        #[test]
+        fn loom_mpmc_queue_hands_out_every_value_once() {
+            model(|| {
+                let queue = Arc::new(mpmc::MpmcQueue::new(2));
+                let producer = {
+                    let queue = queue.clone();
+                    loom::thread::spawn(move|| {
+                        queue.push(1).unwrap();
+                        queue.push(2).unwrap();
+                    })
+                };
+                let first = queue.pop();
+                producer.join().unwrap();
+                let mut values: Vec<i32> = first.into_iter().chain(queue.pop()).chain(queue.pop()).collect();
+                values.sort();
+                assert_eq!(values, [1, 2]);
+            });
+        }
+
+        #[test]
+        fn loom_task_status_transitions() {
+            model(|| {
+                let statuses = Arc::new(std::sync::Mutex::new(Vec::new()));
+                let recorded = statuses.clone();
+                let system = Arc::new(TaskSystem::builder()
+                    .on_status_change(move |change| recorded.lock().unwrap().push(change.new))
+                    .build_deterministic());
+                let task = system.run(|| 1);
+                let runner = {
+                    let system = system.clone();
+                    loom::thread::spawn(move|| system.step())
+                };
+                task.cancel();
+                let result = task.join();
+                runner.join().unwrap();
+
+                let statuses = statuses.lock().unwrap().clone();
+                match result {
+                    Ok(1) => assert_eq!(statuses, [TaskStatus::Queued, TaskStatus::Running, TaskStatus::Completed]),
+                    Err(GetValueError::Cancelled) => assert!(
+                        statuses == [TaskStatus::Queued, TaskStatus::Cancelled]
+                            || statuses == [TaskStatus::Queued, TaskStatus::Running, TaskStatus::Cancelled],
+                        "{:?}", statuses,
+                    ),
+                    result => panic!("unexpected result {:?}", result),
+                }
+            });
+        }
+
+        #[test]
+        fn loom_workers_wake_for_submitted_work() {
+            model(|| {
+                let system = TaskSystem::new(1);
+                assert_eq!(system.run(|| 1).join(), Ok(1));
+                system.spawn_detached(|| ());
+                system.wait_idle();
+            });
+        }
+
+        #[test]
+        fn loom_helpers_wake_for_finished_tasks() {
+            model(|| {
+                let system = TaskSystem::new(1);
+                // Keeps the pool from going idle, whose notification would wake the helper too.
+                let busy = system.pool.registry.busy_guard();
+                let mut task = system.run(|| 1);
+                task.wait_and_help();
+                assert_eq!(task.join(), Ok(1));
+                drop(busy);
+            });
         }
     }
 }
@@ -0,0 +1,119 @@
+use std::{
+    sync::{Arc, Mutex, Condvar},
+    collections::VecDeque,
+    time::Duration,
+    cell::Cell,
+    thread,
+    mem,
+};
+
+use super::{Job, Unpoison};
+
+// Threads that waited this long without a job exit.
+const KEEP_ALIVE: Duration = Duration::from_secs(10);
+const MAX_THREADS: usize = 512;
+
+thread_local! {
+    static IS_BLOCKING_THREAD: Cell<bool> = const { Cell::new(false) };
+}
+
+// *************************************************************************************************
+struct BlockingState {
+    jobs: VecDeque<Job>,
+    threads: usize,
+    idle: usize,
+    closing: bool,
+}
+
+// Runs jobs that block on I/O or locks, away from the compute workers. A thread is started whenever
+// a job arrives with no idle thread around, and idle threads exit after a while.
+pub(super) struct BlockingPool {
+    state: Mutex<BlockingState>,
+    condvar: Condvar,
+    exited: Condvar,
+}
+
+impl BlockingPool {
+    pub(super) fn new() -> Arc<Self> {
+        return Arc::new(Self{
+            state: Mutex::new(BlockingState{
+                jobs: VecDeque::new(),
+                threads: 0,
+                idle: 0,
+                closing: false,
+            }),
+            condvar: Condvar::new(),
+            exited: Condvar::new(),
+        });
+    }
+
+    pub(super) fn execute(self: &Arc<Self>, job: Job) {
+        let mut state = self.state.lock().unpoison();
+        if state.closing {
+            drop(state);
+            // Dropping the job cancels its task.
+            drop(job);
+            return;
+        }
+
+        state.jobs.push_back(job);
+        self.condvar.notify_one();
+        // Idle threads that weren't woken yet each take one of the queued jobs.
+        if state.idle < state.jobs.len() && state.threads < MAX_THREADS {
+            state.threads += 1;
+            let pool = self.clone();
+            thread::Builder::new()
+                .name("task-system-blocking".to_string())
+                .spawn(move || pool.thread_loop())
+                .expect("failed to spawn a blocking thread");
+        }
+    }
+
+    // Waits for the threads to exit. They finish the queued jobs first, unless `now` is set, in
+    // which case the queued jobs are dropped.
+    pub(super) fn shutdown(&self, now: bool) {
+        let jobs = {
+            let mut state = self.state.lock().unpoison();
+            state.closing = true;
+            self.condvar.notify_all();
+            if now { mem::take(&mut state.jobs) } else { VecDeque::new() }
+        };
+        drop(jobs);
+
+        // A blocking job shutting the system down can't wait for its own thread.
+        let own = if IS_BLOCKING_THREAD.with(|flag| flag.get()) { 1 } else { 0 };
+        let mut state = self.state.lock().unpoison();
+        while state.threads > own {
+            state = self.exited.wait(state).unpoison();
+        }
+    }
+
+    fn thread_loop(&self) {
+        IS_BLOCKING_THREAD.with(|flag| flag.set(true));
+
+        let mut state = self.state.lock().unpoison();
+        loop {
+            if let Some(job) = state.jobs.pop_front() {
+                drop(state);
+                job.run();
+                state = self.state.lock().unpoison();
+                continue;
+            }
+
+            if state.closing {
+                break;
+            }
+
+            state.idle += 1;
+            let (guard, timeout) = self.condvar.wait_timeout(state, KEEP_ALIVE).unpoison();
+            state = guard;
+            state.idle -= 1;
+            if timeout.timed_out() && state.jobs.is_empty() && !state.closing {
+                break;
+            }
+        }
+
+        state.threads -= 1;
+        self.exited.notify_all();
+    }
+}
@@ -0,0 +1,62 @@
+use std::{
+    sync::Mutex,
+    any::{Any, TypeId},
+    collections::HashMap,
+    hash::Hash,
+    mem,
+    time::Duration,
+};
+
+use super::Unpoison;
+
+// *************************************************************************************************
+// Tasks of `TaskSystem::run_cached`, in one map per key and value type.
+pub(super) struct TaskCache {
+    // How long completed tasks are handed out again.
+    pub(super) ttl: Duration,
+    maps: Mutex<HashMap<TypeId, Box<dyn Any + Send>>>,
+}
+
+impl TaskCache {
+    pub(super) fn new(ttl: Duration) -> Self {
+        return Self{
+            ttl,
+            maps: Mutex::new(HashMap::new()),
+        };
+    }
+
+    // Returns the value under `key` if `fresh` keeps it, or caches and returns the one `make`
+    // creates. Values of the same types that `fresh` rejects are evicted on the way. `make` runs
+    // without the lock, as it may run a task using the cache right away; if another value was
+    // cached meanwhile, that one is kept and returned instead.
+    pub(super) fn get_or_insert_with<K, V, F, M>(&self, key: K, fresh: F, make: M) -> V
+        where K: Hash + Eq + Send + 'static, V: Clone + Send + 'static, F: Fn(&V) -> bool, M: FnOnce() -> V
+    {
+        if let Some(value) = self.with_map(|map: &mut HashMap<K, V>| {
+            map.retain(|_, value| fresh(value));
+            return map.get(&key).cloned();
+        }) {
+            return value;
+        }
+
+        let made = make();
+        return self.with_map(|map: &mut HashMap<K, V>| {
+            map.retain(|_, value| fresh(value));
+            return map.entry(key).or_insert(made).clone();
+        });
+    }
+
+    fn with_map<K, V, R>(&self, fun: impl FnOnce(&mut HashMap<K, V>) -> R) -> R where K: Send + 'static, V: Send + 'static {
+        let mut maps = self.maps.lock().unpoison();
+        let map = maps.entry(TypeId::of::<HashMap<K, V>>())
+            .or_insert_with(|| Box::new(HashMap::<K, V>::new()))
+            .downcast_mut::<HashMap<K, V>>()
+            .unwrap();
+        return fun(map);
+    }
+
+    pub(super) fn clear(&self) {
+        let maps = mem::take(&mut *self.maps.lock().unpoison());
+        drop(maps);
+    }
+}
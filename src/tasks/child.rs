@@ -0,0 +1,133 @@
+use std::{
+    sync::{Arc, Mutex, atomic::{AtomicUsize, Ordering}},
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+use super::{Dependency, Job, Priority, Task, TaskStatus, Unpoison, registry::TaskRegistry, scheduler::WorkerPool};
+
+// *************************************************************************************************
+/// View of a task system with a queue of its own, created by
+/// [`TaskSystem::child`](super::TaskSystem::child) for a subsystem that wants to order and wait
+/// for its own tasks only. Its tasks run on the workers of the parent, each taking a turn in the
+/// parent's queue at its priority and then running the highest priority task of the child.
+///
+/// Dropping the child waits for its tasks like [`ChildSystem::wait_idle`], so it must not be
+/// dropped by one of them.
+///
+/// ```
+/// use std::sync::{Arc, atomic::{AtomicUsize, Ordering}};
+/// use task_system::TaskSystem;
+///
+/// let system = TaskSystem::new(2);
+/// let physics = system.child();
+/// let steps = Arc::new(AtomicUsize::new(0));
+/// for _ in 0..4 {
+///     let steps = steps.clone();
+///     physics.run(move || steps.fetch_add(1, Ordering::SeqCst));
+/// }
+/// physics.wait_idle();
+/// assert_eq!(steps.load(Ordering::SeqCst), 4);
+/// ```
+pub struct ChildSystem {
+    pool: Arc<WorkerPool>,
+    state: Arc<ChildState>,
+}
+
+struct ChildState {
+    lanes: Mutex<[VecDeque<Job>; Priority::COUNT]>,
+    // Unfinished tasks of the child. Threads waiting for them are notified through the registry
+    // once there are none.
+    busy: AtomicUsize,
+    registry: Arc<TaskRegistry>,
+}
+
+impl ChildSystem {
+    pub(super) fn new(pool: Arc<WorkerPool>) -> Self {
+        let state = Arc::new(ChildState{
+            lanes: Mutex::new(Default::default()),
+            busy: AtomicUsize::new(0),
+            registry: pool.registry.clone(),
+        });
+        return Self{ pool, state };
+    }
+
+    /// Queues `fun` in the child's queue.
+    pub fn run<F, O>(&self, fun: F) -> Task<O>
+        where F: FnOnce() -> O + Send + 'static, O: Send + 'static
+    {
+        return self.run_with_priority(Priority::Normal, fun);
+    }
+
+    /// Queues `fun` in the lane of the given priority of the child's queue.
+    pub fn run_with_priority<F, O>(&self, priority: Priority, fun: F) -> Task<O>
+        where F: FnOnce() -> O + Send + 'static, O: Send + 'static
+    {
+        let (task, job) = self.pool.prepare(move |_| Ok(fun()));
+        self.state.busy.fetch_add(1, Ordering::SeqCst);
+        let state = self.state.clone();
+        task.on_completed(Job::new(move || state.leave()));
+
+        task.shared_state.set_status(TaskStatus::Queued);
+        self.state.lanes.lock().unpoison()[priority.lane()].push_back(Job::new(job));
+        let turn = Turn(Some(self.state.clone()));
+        self.pool.execute_with_priority(priority, Job::new(move || turn.run()));
+        return task;
+    }
+
+    /// Number of tasks of the child that didn't finish yet.
+    pub fn pending(&self) -> usize {
+        return self.state.busy.load(Ordering::SeqCst);
+    }
+
+    /// Blocks until every task of the child finished, including the ones queued in the meantime.
+    /// Tasks of the parent and of other children aren't waited for.
+    pub fn wait_idle(&self) {
+        while !self.wait_idle_timeout(Duration::from_secs(3600)) {}
+    }
+
+    /// Like [`ChildSystem::wait_idle`] but gives up after `timeout`. Returns whether the child is
+    /// idle.
+    pub fn wait_idle_timeout(&self, timeout: Duration) -> bool {
+        return self.pool.wait_until(Instant::now().checked_add(timeout), || self.pending() == 0);
+    }
+}
+
+impl Drop for ChildSystem {
+    fn drop(&mut self) {
+        self.wait_idle();
+    }
+}
+
+impl ChildState {
+    fn leave(&self) {
+        if self.busy.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.registry.notify_waiters();
+        }
+    }
+
+    fn pop(&self) -> Option<Job> {
+        return self.lanes.lock().unpoison().iter_mut().find_map(VecDeque::pop_front);
+    }
+}
+
+// Turn of a child in the parent's queue, running the child's next task. A turn dropped by the
+// parent without running drops a task of the child as well, which cancels it.
+struct Turn(Option<Arc<ChildState>>);
+
+impl Turn {
+    fn run(mut self) {
+        let job = self.0.take().and_then(|state| state.pop());
+        if let Some(job) = job {
+            job.run();
+        }
+    }
+}
+
+impl Drop for Turn {
+    fn drop(&mut self) {
+        if let Some(state) = self.0.take() {
+            drop(state.pop());
+        }
+    }
+}
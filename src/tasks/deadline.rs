@@ -0,0 +1,62 @@
+use std::{
+    cell::Cell,
+    marker::PhantomData,
+    time::{Duration, Instant},
+};
+
+thread_local! {
+    // Deadline of the innermost scope alive on this thread.
+    static DEADLINE: Cell<Option<Instant>> = const { Cell::new(None) };
+}
+
+// *************************************************************************************************
+/// Shared deadline of every step chained with [`Task::then`](super::Task::then),
+/// [`Task::and_then`](super::Task::and_then) or [`Task::map`](super::Task::map) on this thread while
+/// the scope is alive. A step whose turn comes after the deadline is skipped and finishes as
+/// [`TaskStatus::TimedOut`](super::TaskStatus::TimedOut), which the steps after it pass on. A nested
+/// scope can only shorten the deadline.
+///
+/// ```
+/// use std::{thread, time::Duration};
+/// use task_system::{DeadlineScope, GetValueError, TaskSystem};
+///
+/// let system = TaskSystem::new(1);
+/// let scope = DeadlineScope::new(Duration::from_millis(20));
+/// let task = system.run(|| thread::sleep(Duration::from_millis(50))).map(|_| 42);
+/// drop(scope);
+/// assert_eq!(task.join(), Err(GetValueError::TimedOut));
+/// ```
+pub struct DeadlineScope {
+    previous: Option<Instant>,
+    // Restores the deadline of the thread it was created on.
+    thread: PhantomData<*const ()>,
+}
+
+impl DeadlineScope {
+    /// Starts a scope whose steps have to be scheduled within `budget` from now.
+    pub fn new(budget: Duration) -> Self {
+        return Self::until(Instant::now() + budget);
+    }
+
+    /// Starts a scope whose steps have to be scheduled before `deadline`.
+    pub fn until(deadline: Instant) -> Self {
+        let previous = DEADLINE.with(|current| current.replace(Some(current.get().map_or(deadline, |outer| outer.min(deadline)))));
+        return Self{ previous, thread: PhantomData };
+    }
+
+    /// Deadline of the innermost scope on the calling thread.
+    pub fn current() -> Option<Instant> {
+        return DEADLINE.with(Cell::get);
+    }
+}
+
+impl Drop for DeadlineScope {
+    fn drop(&mut self) {
+        DEADLINE.with(|current| current.set(self.previous));
+    }
+}
+
+// Whether a step built under `deadline` is too late to be scheduled.
+pub(super) fn expired(deadline: Option<Instant>) -> bool {
+    return deadline.is_some_and(|deadline| Instant::now() >= deadline);
+}
@@ -0,0 +1,60 @@
+use std::{
+    mem::{self, ManuallyDrop, MaybeUninit},
+    ptr,
+};
+
+// *************************************************************************************************
+// Closure queued to run once. Closures of up to `Job::WORDS` words, which covers most tasks and any
+// boxed closure, are kept inline in the queue slot rather than in an allocation of their own.
+pub struct Job {
+    storage: MaybeUninit<[usize; Job::WORDS]>,
+    // Moves the closure out of `storage` and calls it, or only drops it when `run` is false.
+    call: unsafe fn(storage: *mut u8, run: bool),
+}
+
+// SAFETY: jobs are only built from `Send` closures.
+unsafe impl Send for Job {}
+
+impl Job {
+    const WORDS: usize = 3;
+
+    pub(super) fn new<F>(fun: F) -> Self where F: FnOnce() + Send + 'static {
+        if Self::fits::<F>() {
+            return Self::inline(fun);
+        }
+        return Self::inline(Box::new(fun));
+    }
+
+    fn fits<F>() -> bool {
+        return mem::size_of::<F>() <= mem::size_of::<[usize; Self::WORDS]>() && mem::align_of::<F>() <= mem::align_of::<usize>();
+    }
+
+    fn inline<F>(fun: F) -> Self where F: FnOnce() + Send + 'static {
+        assert!(Self::fits::<F>());
+        let mut storage = MaybeUninit::<[usize; Self::WORDS]>::uninit();
+        // SAFETY: the storage is large and aligned enough for `F`, as checked above.
+        unsafe { ptr::write(storage.as_mut_ptr() as *mut F, fun) };
+        return Self{ storage, call: Self::call::<F> };
+    }
+
+    // SAFETY: `storage` must hold an `F` that isn't used afterwards.
+    unsafe fn call<F>(storage: *mut u8, run: bool) where F: FnOnce() {
+        let fun = ptr::read(storage as *mut F);
+        if run {
+            fun();
+        }
+    }
+
+    pub(super) fn run(self) {
+        let mut job = ManuallyDrop::new(self);
+        // SAFETY: the closure is moved out once, `job` isn't dropped afterwards.
+        unsafe { (job.call)(job.storage.as_mut_ptr() as *mut u8, true) };
+    }
+}
+
+impl Drop for Job {
+    fn drop(&mut self) {
+        // SAFETY: a job still holds its closure until it is run or dropped.
+        unsafe { (self.call)(self.storage.as_mut_ptr() as *mut u8, false) };
+    }
+}
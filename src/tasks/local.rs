@@ -0,0 +1,106 @@
+use std::{
+    any::Any,
+    cell::RefCell,
+    marker::PhantomData,
+    rc::Rc,
+    mem,
+};
+
+thread_local! {
+    // Values of the task running on this thread, keyed by the address of their `TaskLocal`.
+    static LOCALS: RefCell<Vec<(usize, Rc<dyn Any>)>> = const { RefCell::new(Vec::new()) };
+}
+
+// *************************************************************************************************
+/// Declares task-local values of type [`TaskLocal`].
+///
+/// ```
+/// use task_system::{TaskBase, TaskSystem, task_local};
+///
+/// task_local! {
+///     static REQUEST_ID: u64;
+/// }
+///
+/// fn log(message: &str) -> String {
+///     return REQUEST_ID.with(|id| format!("[{}] {}", id, message));
+/// }
+///
+/// let mut system = TaskSystem::new(1);
+/// let mut task = system.run(|| {
+///     REQUEST_ID.set(7);
+///     return log("loaded");
+/// });
+/// task.wait();
+/// assert_eq!(task.value(), Ok(String::from("[7] loaded")));
+/// ```
+#[macro_export]
+macro_rules! task_local {
+    ($($(#[$attr:meta])* $vis:vis static $name:ident: $t:ty;)+) => {
+        $($(#[$attr])* $vis static $name: $crate::TaskLocal<$t> = $crate::TaskLocal::new();)+
+    };
+}
+
+// *************************************************************************************************
+/// Value visible to the task that set it, until that task finishes. Unlike a thread local it
+/// doesn't leak into other tasks the same worker runs, including tasks run while waiting on a
+/// subtask. Declared with [`task_local!`](crate::task_local).
+pub struct TaskLocal<T: 'static> {
+    // Gives every static a distinct address to key the values with.
+    _key: u8,
+    _value: PhantomData<fn() -> T>,
+}
+
+impl<T: 'static> TaskLocal<T> {
+    #[doc(hidden)]
+    pub const fn new() -> Self {
+        return Self{ _key: 0, _value: PhantomData };
+    }
+
+    /// Sets the value for the rest of the current task.
+    pub fn set(&'static self, value: T) {
+        let key = self.key();
+        LOCALS.with(|locals| {
+            let mut locals = locals.borrow_mut();
+            locals.retain(|(local, _)| *local != key);
+            locals.push((key, Rc::new(value)));
+        });
+    }
+
+    /// Calls `fun` with the value of the current task. Panics if the task didn't set one.
+    pub fn with<F, R>(&'static self, fun: F) -> R where F: FnOnce(&T) -> R {
+        return self.try_with(fun).expect("task local value not set");
+    }
+
+    /// Calls `fun` with the value of the current task, or returns `None` if the task didn't set
+    /// one.
+    pub fn try_with<F, R>(&'static self, fun: F) -> Option<R> where F: FnOnce(&T) -> R {
+        let key = self.key();
+        // Cloned out so `fun` may set other values.
+        let value = LOCALS.with(|locals| {
+            return locals.borrow().iter().find(|(local, _)| *local == key).map(|(_, value)| value.clone());
+        })?;
+        return Some(fun(value.downcast_ref::<T>().unwrap()));
+    }
+
+    fn key(&'static self) -> usize {
+        return self as *const Self as usize;
+    }
+}
+
+// Runs a task's closure with no task-local values set, restoring those of the task it interrupted
+// afterwards.
+pub(super) fn isolated<F, R>(fun: F) -> R where F: FnOnce() -> R {
+    struct Restore(Vec<(usize, Rc<dyn Any>)>);
+
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            let outer = mem::take(&mut self.0);
+            let inner = LOCALS.with(|locals| mem::replace(&mut *locals.borrow_mut(), outer));
+            // Dropped outside of the borrow, as the values' destructors may use task locals.
+            drop(inner);
+        }
+    }
+
+    let _restore = Restore(LOCALS.with(|locals| mem::take(&mut *locals.borrow_mut())));
+    return fun();
+}
@@ -0,0 +1,145 @@
+use std::{
+    sync::{Arc, atomic::{AtomicIsize, AtomicUsize, Ordering}},
+    alloc::{GlobalAlloc, Layout, System},
+    cell::Cell,
+    ptr,
+};
+
+use super::registry::TaskMeta;
+
+thread_local! {
+    // Counters the allocations of this thread are charged to: the running task's and its group's.
+    static CHARGED: Cell<(*const AtomicIsize, *const GroupMemory)> = const { Cell::new((ptr::null(), ptr::null())) };
+}
+
+// *************************************************************************************************
+/// Global allocator charging allocations to the task running on the allocating thread, which
+/// [`Task::memory_used`](super::Task::memory_used) and the memory budgets of
+/// [`WorkGroup::set_memory_budget`](super::WorkGroup::set_memory_budget) rely on. Without it
+/// installed they see no allocations at all.
+///
+/// A task is charged the bytes allocated minus the bytes freed while it runs, on its own thread.
+/// Memory it hands out, like its output, stays charged to it even once freed elsewhere.
+///
+/// ```
+/// use std::alloc::System;
+/// use task_system::{TaskBase, TaskSystem, TrackingAllocator};
+///
+/// #[global_allocator]
+/// static ALLOCATOR: TrackingAllocator = TrackingAllocator::new(System);
+///
+/// let system = TaskSystem::new(1);
+/// let mut task = system.run(|| vec![0u8; 4096]);
+/// task.wait();
+/// assert!(task.memory_used() >= 4096);
+/// ```
+pub struct TrackingAllocator<A = System> {
+    inner: A,
+}
+
+impl<A> TrackingAllocator<A> {
+    /// Tracks the allocations made through `inner`.
+    pub const fn new(inner: A) -> Self {
+        return Self{ inner };
+    }
+}
+
+// SAFETY: every call is forwarded to the inner allocator unchanged, charging only touches atomics
+// and a thread local without destructor, neither of which allocates.
+unsafe impl<A> GlobalAlloc for TrackingAllocator<A> where A: GlobalAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let pointer = self.inner.alloc(layout);
+        if !pointer.is_null() {
+            charge(layout.size() as isize);
+        }
+        return pointer;
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let pointer = self.inner.alloc_zeroed(layout);
+        if !pointer.is_null() {
+            charge(layout.size() as isize);
+        }
+        return pointer;
+    }
+
+    unsafe fn dealloc(&self, pointer: *mut u8, layout: Layout) {
+        self.inner.dealloc(pointer, layout);
+        charge(-(layout.size() as isize));
+    }
+
+    unsafe fn realloc(&self, pointer: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_pointer = self.inner.realloc(pointer, layout, new_size);
+        if !new_pointer.is_null() {
+            charge(new_size as isize - layout.size() as isize);
+        }
+        return new_pointer;
+    }
+}
+
+fn charge(bytes: isize) {
+    // Fails while the thread shuts down, when nothing runs anymore.
+    let _ = CHARGED.try_with(|charged| {
+        let (task, mut group) = charged.get();
+        // SAFETY: the counters are kept alive by the task running on this thread, see `charged`.
+        unsafe {
+            if let Some(task) = task.as_ref() {
+                task.fetch_add(bytes, Ordering::Relaxed);
+            }
+            while let Some(current) = group.as_ref() {
+                current.used.fetch_add(bytes, Ordering::Relaxed);
+                group = current.parent.as_deref().map_or(ptr::null(), |parent| parent as *const GroupMemory);
+            }
+        }
+    });
+}
+
+// Runs `fun` as the task of `meta`, charging what it allocates to it and its group.
+pub(super) fn charged<F, R>(meta: &TaskMeta, fun: F) -> R where F: FnOnce() -> R {
+    struct Restore((*const AtomicIsize, *const GroupMemory));
+
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            let _ = CHARGED.try_with(|charged| charged.set(self.0));
+        }
+    }
+
+    let group = meta.group_memory.get().map_or(ptr::null(), Arc::as_ptr);
+    let _restore = Restore(CHARGED.with(|charged| charged.replace((&meta.memory, group))));
+    return fun();
+}
+
+// *************************************************************************************************
+// Memory charged to the tasks of a work group and its subgroups.
+pub(super) struct GroupMemory {
+    used: AtomicIsize,
+    // `usize::MAX` without a budget.
+    budget: AtomicUsize,
+    parent: Option<Arc<GroupMemory>>,
+}
+
+impl GroupMemory {
+    pub(super) fn new(parent: Option<Arc<GroupMemory>>) -> Arc<Self> {
+        return Arc::new(Self{ used: AtomicIsize::new(0), budget: AtomicUsize::new(usize::MAX), parent });
+    }
+
+    pub(super) fn used(&self) -> usize {
+        return self.used.load(Ordering::Relaxed).max(0) as usize;
+    }
+
+    pub(super) fn set_budget(&self, budget: Option<usize>) {
+        self.budget.store(budget.unwrap_or(usize::MAX), Ordering::Relaxed);
+    }
+
+    // Whether the group or one of its parents used up its budget.
+    pub(super) fn exhausted(&self) -> bool {
+        let mut group = Some(self);
+        while let Some(current) = group {
+            if current.used() >= current.budget.load(Ordering::Relaxed) {
+                return true;
+            }
+            group = current.parent.as_deref();
+        }
+        return false;
+    }
+}
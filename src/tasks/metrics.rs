@@ -0,0 +1,104 @@
+use std::{
+    sync::{Mutex, atomic::{AtomicU64, Ordering}},
+    time::Duration,
+};
+
+use super::{TaskStatus, Unpoison};
+
+// *************************************************************************************************
+/// Snapshot of the counters of a task system, returned by
+/// [`TaskSystem::metrics`](super::TaskSystem::metrics). Counters only cover tasks with a handle,
+/// detached and logged jobs don't show up.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Metrics {
+    /// Tasks created so far.
+    pub submitted: u64,
+    /// Tasks that completed with an output.
+    pub completed: u64,
+    /// Tasks that panicked, timed out or whose inputs failed.
+    pub failed: u64,
+    /// Tasks that were cancelled.
+    pub cancelled: u64,
+    /// Tasks that were aborted before they started.
+    pub aborted: u64,
+    /// Jobs waiting in the queues for a worker.
+    pub queued: usize,
+    /// Time each worker spent running jobs, indexed by worker.
+    pub worker_busy: Vec<Duration>,
+    /// Average time from submitting a task to a worker starting it.
+    pub average_wait: Duration,
+    /// Panics escaping plain jobs, which kill a worker thread and have it replaced, or are caught
+    /// on a thread helping the workers.
+    pub worker_panics: u64,
+    /// Time spent running the tasks of each tenant of
+    /// [`TaskSystem::run_for_tenant`](super::TaskSystem::run_for_tenant), indexed by tenant.
+    pub tenant_busy: Vec<Duration>,
+}
+
+// *************************************************************************************************
+#[derive(Default)]
+pub(super) struct TaskCounters {
+    submitted: AtomicU64,
+    completed: AtomicU64,
+    failed: AtomicU64,
+    cancelled: AtomicU64,
+    aborted: AtomicU64,
+    started: AtomicU64,
+    wait_nanos: AtomicU64,
+    worker_panics: AtomicU64,
+    tenant_busy: Mutex<Vec<Duration>>,
+}
+
+impl TaskCounters {
+    pub(super) fn record_submitted(&self) {
+        self.submitted.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(super) fn record_started(&self, wait: Duration) {
+        self.started.fetch_add(1, Ordering::Relaxed);
+        self.wait_nanos.fetch_add(wait.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    pub(super) fn record_finished(&self, status: TaskStatus) {
+        let counter = match status {
+            TaskStatus::Completed => &self.completed,
+            TaskStatus::Cancelled => &self.cancelled,
+            TaskStatus::Aborted => &self.aborted,
+            _ => &self.failed,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(super) fn record_worker_panic(&self) {
+        self.worker_panics.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(super) fn record_tenant_busy(&self, tenant: usize, busy: Duration) {
+        let mut tenant_busy = self.tenant_busy.lock().unpoison();
+        if tenant_busy.len() <= tenant {
+            tenant_busy.resize(tenant + 1, Duration::ZERO);
+        }
+        tenant_busy[tenant] += busy;
+    }
+
+    pub(super) fn snapshot(&self, queued: usize, worker_busy: Vec<Duration>) -> Metrics {
+        let started = self.started.load(Ordering::Relaxed);
+        let average_wait = match started {
+            0 => Duration::ZERO,
+            started => Duration::from_nanos(self.wait_nanos.load(Ordering::Relaxed) / started),
+        };
+
+        return Metrics{
+            submitted: self.submitted.load(Ordering::Relaxed),
+            completed: self.completed.load(Ordering::Relaxed),
+            failed: self.failed.load(Ordering::Relaxed),
+            cancelled: self.cancelled.load(Ordering::Relaxed),
+            aborted: self.aborted.load(Ordering::Relaxed),
+            queued,
+            worker_busy,
+            average_wait,
+            worker_panics: self.worker_panics.load(Ordering::Relaxed),
+            tenant_busy: self.tenant_busy.lock().unpoison().clone(),
+        };
+    }
+}
@@ -0,0 +1,102 @@
+use std::mem::MaybeUninit;
+
+use super::sync::{UnsafeCell, atomic::{AtomicUsize, Ordering}};
+
+// *************************************************************************************************
+// Bounded lock-free queue for any number of producers and consumers, after Dmitry Vyukov's. Every
+// slot carries a sequence number telling whether it is free for the push at a position or holds the
+// value for the pop at it, so a push or pop only claims its position with a compare-and-swap.
+pub(super) struct MpmcQueue<T> {
+    slots: Box<[Slot<T>]>,
+    mask: usize,
+    // Next positions to push to and to pop from, kept on separate cache lines so producers and
+    // consumers don't invalidate each other's.
+    tail: CacheAligned<AtomicUsize>,
+    head: CacheAligned<AtomicUsize>,
+}
+
+struct Slot<T> {
+    // The position a push to this slot must be at while the slot is free, or that position plus
+    // one once it holds a value.
+    sequence: AtomicUsize,
+    value: UnsafeCell<MaybeUninit<T>>,
+}
+
+#[repr(align(128))]
+struct CacheAligned<T>(T);
+
+// SAFETY: a value is only accessed by the one thread that claimed its position.
+unsafe impl<T> Send for MpmcQueue<T> where T: Send {}
+unsafe impl<T> Sync for MpmcQueue<T> where T: Send {}
+
+impl<T> MpmcQueue<T> {
+    // Creates a queue holding up to `capacity` values, rounded up to a power of two.
+    pub(super) fn new(capacity: usize) -> Self {
+        let capacity = capacity.max(2).next_power_of_two();
+        return Self{
+            slots: (0..capacity).map(|position| Slot{
+                sequence: AtomicUsize::new(position),
+                value: UnsafeCell::new(MaybeUninit::uninit()),
+            }).collect(),
+            mask: capacity - 1,
+            tail: CacheAligned(AtomicUsize::new(0)),
+            head: CacheAligned(AtomicUsize::new(0)),
+        };
+    }
+
+    // Appends `value`, or hands it back if the queue is full.
+    pub(super) fn push(&self, value: T) -> Result<(), T> {
+        let mut position = self.tail.0.load(Ordering::Relaxed);
+        loop {
+            let slot = &self.slots[position & self.mask];
+            let lag = slot.sequence.load(Ordering::Acquire).wrapping_sub(position) as isize;
+            if lag == 0 {
+                match self.tail.0.compare_exchange_weak(position, position.wrapping_add(1), Ordering::Relaxed, Ordering::Relaxed) {
+                    Ok(_) => {
+                        // SAFETY: the slot is free and the position was claimed by this thread.
+                        slot.value.with_mut(|value_slot| unsafe { (*value_slot).write(value) });
+                        slot.sequence.store(position.wrapping_add(1), Ordering::Release);
+                        return Ok(());
+                    },
+                    Err(current) => position = current,
+                }
+            } else if lag < 0 {
+                // The slot still holds the value pushed a lap ago.
+                return Err(value);
+            } else {
+                position = self.tail.0.load(Ordering::Relaxed);
+            }
+        }
+    }
+
+    // Takes the oldest value, `None` if the queue is empty.
+    pub(super) fn pop(&self) -> Option<T> {
+        let mut position = self.head.0.load(Ordering::Relaxed);
+        loop {
+            let slot = &self.slots[position & self.mask];
+            let lag = slot.sequence.load(Ordering::Acquire).wrapping_sub(position.wrapping_add(1)) as isize;
+            if lag == 0 {
+                match self.head.0.compare_exchange_weak(position, position.wrapping_add(1), Ordering::Relaxed, Ordering::Relaxed) {
+                    Ok(_) => {
+                        // SAFETY: the slot holds a value and the position was claimed by this thread.
+                        let value = slot.value.with_mut(|value| unsafe { (*value).assume_init_read() });
+                        slot.sequence.store(position.wrapping_add(self.mask + 1), Ordering::Release);
+                        return Some(value);
+                    },
+                    Err(current) => position = current,
+                }
+            } else if lag < 0 {
+                // Nothing was pushed at this position yet.
+                return None;
+            } else {
+                position = self.head.0.load(Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+impl<T> Drop for MpmcQueue<T> {
+    fn drop(&mut self) {
+        while self.pop().is_some() {}
+    }
+}
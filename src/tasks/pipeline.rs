@@ -0,0 +1,261 @@
+use std::{
+    sync::Arc,
+    any::Any,
+    collections::VecDeque,
+    marker::PhantomData,
+    panic::{self, AssertUnwindSafe},
+};
+
+use super::{Job, PanicLogger, TaskSystem, Unpoison, panic_message, scheduler::WorkerPool};
+use super::sync::{Condvar, Mutex, MutexGuard};
+
+type Item = Box<dyn Any + Send>;
+type StageFn = Arc<dyn Fn(Item) -> Item + Send + Sync>;
+
+// *************************************************************************************************
+/// Builder of a chain of stages that items flow through, each stage running on up to a given
+/// number of workers at once.
+///
+/// Items pass between stages through queues holding up to [`capacity`](Pipeline::capacity) of
+/// them. A stage only takes an item while the next queue has room for its result, so a slow stage
+/// holds back the ones before it instead of piling up their outputs. Items can overtake each other
+/// in stages running on more than one worker.
+///
+/// ```
+/// use task_system::{Pipeline, TaskSystem};
+///
+/// let system = TaskSystem::new(4);
+/// let pipeline = Pipeline::new(&system)
+///     .stage(|line: &str| line.len(), 2)
+///     .stage(|len| len * 10, 1)
+///     .build();
+/// pipeline.push("abc");
+/// pipeline.push("de");
+/// pipeline.close();
+/// let mut outputs: Vec<usize> = pipeline.collect();
+/// outputs.sort();
+/// assert_eq!(outputs, vec![20, 30]);
+/// ```
+pub struct Pipeline<I, O> {
+    pool: Arc<WorkerPool>,
+    panic_logger: Arc<Mutex<PanicLogger>>,
+    stages: Vec<(StageFn, usize)>,
+    capacity: usize,
+    types: PhantomData<fn(I) -> O>,
+}
+
+impl<I> Pipeline<I, I> where I: Send + 'static {
+    /// Starts a pipeline without stages, which runs its stages on the workers of `system`.
+    pub fn new(system: &TaskSystem) -> Self {
+        return Self{
+            pool: system.pool.clone(),
+            panic_logger: system.panic_logger.clone(),
+            stages: Vec::new(),
+            capacity: 16,
+            types: PhantomData,
+        };
+    }
+}
+
+impl<I, O> Pipeline<I, O> where I: Send + 'static, O: Send + 'static {
+    /// Sets how many items each queue between stages holds, 16 by default.
+    ///
+    /// # Panics
+    /// Panics if `capacity` is 0.
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        assert!(capacity > 0, "pipeline queues must hold at least one item");
+        self.capacity = capacity;
+        return self;
+    }
+
+    /// Adds a stage passing the outputs of the previous one through `fun` on up to `workers`
+    /// workers at once. Items for which `fun` panics are dropped and the panic is reported to the
+    /// panic logger.
+    ///
+    /// # Panics
+    /// Panics if `workers` is 0.
+    pub fn stage<F, P>(mut self, fun: F, workers: usize) -> Pipeline<I, P>
+        where F: Fn(O) -> P + Send + Sync + 'static, P: Send + 'static
+    {
+        assert!(workers > 0, "pipeline stages need at least one worker");
+        self.stages.push((Arc::new(move |item: Item| -> Item {
+            return Box::new(fun(*item.downcast::<O>().unwrap()));
+        }), workers));
+        return Pipeline{
+            pool: self.pool,
+            panic_logger: self.panic_logger,
+            stages: self.stages,
+            capacity: self.capacity,
+            types: PhantomData,
+        };
+    }
+
+    /// Finishes the pipeline, returning the handle to push inputs into and pull outputs from.
+    pub fn build(self) -> PipelineHandle<I, O> {
+        let n_stages = self.stages.len();
+        return PipelineHandle{
+            shared: Arc::new(Shared{
+                pool: self.pool,
+                panic_logger: self.panic_logger,
+                stages: self.stages,
+                capacity: self.capacity,
+                state: Mutex::new(State{
+                    queues: (0..=n_stages).map(|_| VecDeque::new()).collect(),
+                    running: vec![0; n_stages],
+                    closed: false,
+                }),
+                changed: Condvar::new(),
+            }),
+            types: PhantomData,
+        };
+    }
+}
+
+// *************************************************************************************************
+/// Running pipeline returned by [`Pipeline::build`]. Iterating it pulls outputs until it is
+/// closed and drained.
+pub struct PipelineHandle<I, O> {
+    shared: Arc<Shared>,
+    types: PhantomData<fn(I) -> O>,
+}
+
+impl<I, O> PipelineHandle<I, O> where I: Send + 'static, O: Send + 'static {
+    /// Queues `input` for the first stage, blocking while its queue is full.
+    ///
+    /// # Panics
+    /// Panics if the pipeline was closed.
+    pub fn push(&self, input: I) {
+        let mut state = self.shared.state.lock().unpoison();
+        assert!(!state.closed, "pushed into a closed pipeline");
+        while state.queues[0].len() >= self.shared.capacity {
+            state = self.shared.changed.wait(state).unpoison();
+        }
+        state.queues[0].push_back(Box::new(input));
+        self.shared.dispatch(state);
+    }
+
+    /// Queues `input` for the first stage unless its queue is full or the pipeline was closed, in
+    /// which case `input` is handed back.
+    pub fn try_push(&self, input: I) -> Result<(), I> {
+        let mut state = self.shared.state.lock().unpoison();
+        if state.closed || state.queues[0].len() >= self.shared.capacity {
+            return Err(input);
+        }
+        state.queues[0].push_back(Box::new(input));
+        self.shared.dispatch(state);
+        return Ok(());
+    }
+
+    /// Stops taking inputs. Items already pushed still make it through.
+    pub fn close(&self) {
+        self.shared.state.lock().unpoison().closed = true;
+        self.shared.changed.notify_all();
+    }
+
+    /// Takes the next output, blocking until one is ready. Returns `None` once the pipeline is
+    /// closed and no items are left in it.
+    pub fn pull(&self) -> Option<O> {
+        let mut state = self.shared.state.lock().unpoison();
+        loop {
+            if let Some(output) = state.queues.last_mut().unwrap().pop_front() {
+                self.shared.dispatch(state);
+                return Some(*output.downcast::<O>().unwrap());
+            }
+            if state.closed && state.is_empty() {
+                return None;
+            }
+            state = self.shared.changed.wait(state).unpoison();
+        }
+    }
+
+    /// Takes the next output if one is ready.
+    pub fn try_pull(&self) -> Option<O> {
+        let mut state = self.shared.state.lock().unpoison();
+        let output = state.queues.last_mut().unwrap().pop_front()?;
+        self.shared.dispatch(state);
+        return Some(*output.downcast::<O>().unwrap());
+    }
+}
+
+impl<I, O> Iterator for PipelineHandle<I, O> where I: Send + 'static, O: Send + 'static {
+    type Item = O;
+
+    fn next(&mut self) -> Option<O> {
+        return self.pull();
+    }
+}
+
+// *************************************************************************************************
+struct Shared {
+    pool: Arc<WorkerPool>,
+    panic_logger: Arc<Mutex<PanicLogger>>,
+    stages: Vec<(StageFn, usize)>,
+    capacity: usize,
+    state: Mutex<State>,
+    // Signalled when an output arrives, the first queue gets room or the pipeline closes.
+    changed: Condvar,
+}
+
+struct State {
+    // Items waiting for each stage, followed by the outputs.
+    queues: Vec<VecDeque<Item>>,
+    // Items each stage is working on, each holding a place in the next queue.
+    running: Vec<usize>,
+    closed: bool,
+}
+
+impl State {
+    fn is_empty(&self) -> bool {
+        return self.queues.iter().all(VecDeque::is_empty) && self.running.iter().all(|&running| running == 0);
+    }
+}
+
+impl Shared {
+    // Starts every item a stage has a free worker and room in the next queue for, going from the
+    // last stage so the room it makes is taken by the stages before it.
+    fn dispatch(self: &Arc<Self>, mut state: MutexGuard<State>) {
+        let mut started = Vec::new();
+        let mut progressed = true;
+        while progressed {
+            progressed = false;
+            for stage in (0..self.stages.len()).rev() {
+                while state.running[stage] < self.stages[stage].1
+                    && state.queues[stage + 1].len() + state.running[stage] < self.capacity
+                {
+                    let Some(item) = state.queues[stage].pop_front() else { break; };
+                    state.running[stage] += 1;
+                    started.push((stage, item));
+                    progressed = true;
+                }
+            }
+        }
+        drop(state);
+        self.changed.notify_all();
+
+        for (stage, item) in started {
+            let shared = self.clone();
+            let busy = self.pool.registry.busy_guard();
+            self.pool.execute(Job::new(move || {
+                let _busy = busy;
+                shared.run(stage, item);
+            }));
+        }
+    }
+
+    fn run(self: Arc<Self>, stage: usize, item: Item) {
+        let output = panic::catch_unwind(AssertUnwindSafe(|| (self.stages[stage].0)(item)));
+        let output = match output {
+            Ok(output) => Some(output),
+            Err(payload) => {
+                let logger = self.panic_logger.lock().unpoison().clone();
+                logger(&panic_message(payload.as_ref()));
+                None
+            },
+        };
+
+        let mut state = self.state.lock().unpoison();
+        state.running[stage] -= 1;
+        state.queues[stage + 1].extend(output);
+        self.dispatch(state);
+    }
+}
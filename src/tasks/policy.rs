@@ -0,0 +1,292 @@
+use std::{
+    sync::{Arc, Mutex},
+    collections::{BinaryHeap, VecDeque},
+    cmp::Ordering,
+    time::Instant,
+    fmt,
+};
+
+use super::{Job, Priority, Unpoison};
+
+// *************************************************************************************************
+/// Queued job handed to a [`Scheduler`], along with what a policy may order it by.
+pub struct ScheduledJob {
+    pub(super) job: Job,
+    priority: Priority,
+    sequence: u64,
+    submitted: Instant,
+    deadline: Option<Instant>,
+    tenant: usize,
+}
+
+impl ScheduledJob {
+    pub(super) fn new(job: Job, priority: Priority, sequence: u64, deadline: Option<Instant>) -> Self {
+        return Self{ job, priority, sequence, submitted: Instant::now(), deadline, tenant: 0 };
+    }
+
+    pub(super) fn for_tenant(mut self, tenant: usize) -> Self {
+        self.tenant = tenant;
+        return self;
+    }
+
+    /// Priority the job was submitted with.
+    pub fn priority(&self) -> Priority {
+        return self.priority;
+    }
+
+    /// Position in submission order, increasing with every job.
+    pub fn sequence(&self) -> u64 {
+        return self.sequence;
+    }
+
+    /// When the job was submitted.
+    pub fn submitted(&self) -> Instant {
+        return self.submitted;
+    }
+
+    /// When the job should be done by, if it has a deadline.
+    pub fn deadline(&self) -> Option<Instant> {
+        return self.deadline;
+    }
+
+    /// Tenant given with [`TaskSystem::run_for_tenant`](super::TaskSystem::run_for_tenant), 0 for
+    /// jobs submitted otherwise.
+    pub fn tenant(&self) -> usize {
+        return self.tenant;
+    }
+}
+
+impl fmt::Debug for ScheduledJob {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return f.debug_struct("ScheduledJob")
+            .field("priority", &self.priority)
+            .field("sequence", &self.sequence)
+            .field("deadline", &self.deadline)
+            .field("tenant", &self.tenant)
+            .finish();
+    }
+}
+
+// *************************************************************************************************
+/// Decides which queued job runs next in a task system built with
+/// [`TaskSystem::with_scheduler`](super::TaskSystem::with_scheduler). Every worker pops from the
+/// same scheduler, under a lock held for the duration of each call.
+pub trait Scheduler: Send + 'static {
+    /// Adds a job to the queue.
+    fn push(&mut self, job: ScheduledJob);
+
+    /// Takes the job to run next.
+    fn pop(&mut self) -> Option<ScheduledJob>;
+
+    /// Number of queued jobs.
+    fn len(&self) -> usize;
+
+    /// Whether no job is queued.
+    fn is_empty(&self) -> bool {
+        return self.len() == 0;
+    }
+}
+
+// *************************************************************************************************
+/// Runs jobs in submission order.
+#[derive(Debug, Default)]
+pub struct FifoScheduler {
+    jobs: VecDeque<ScheduledJob>,
+}
+
+impl Scheduler for FifoScheduler {
+    fn push(&mut self, job: ScheduledJob) {
+        self.jobs.push_back(job);
+    }
+
+    fn pop(&mut self) -> Option<ScheduledJob> {
+        return self.jobs.pop_front();
+    }
+
+    fn len(&self) -> usize {
+        return self.jobs.len();
+    }
+}
+
+/// Runs the most recently submitted job first.
+#[derive(Debug, Default)]
+pub struct LifoScheduler {
+    jobs: Vec<ScheduledJob>,
+}
+
+impl Scheduler for LifoScheduler {
+    fn push(&mut self, job: ScheduledJob) {
+        self.jobs.push(job);
+    }
+
+    fn pop(&mut self) -> Option<ScheduledJob> {
+        return self.jobs.pop();
+    }
+
+    fn len(&self) -> usize {
+        return self.jobs.len();
+    }
+}
+
+// *************************************************************************************************
+// Orders a heap of jobs by a key, the smallest key popping first and ties going by submission.
+struct Keyed<K> {
+    key: K,
+    job: ScheduledJob,
+}
+
+impl<K: Ord> PartialEq for Keyed<K> {
+    fn eq(&self, other: &Self) -> bool {
+        return self.cmp(other) == Ordering::Equal;
+    }
+}
+
+impl<K: Ord> Eq for Keyed<K> {}
+
+impl<K: Ord> PartialOrd for Keyed<K> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        return Some(self.cmp(other));
+    }
+}
+
+impl<K: Ord> Ord for Keyed<K> {
+    // Reversed, so the max-heap yields the smallest key first.
+    fn cmp(&self, other: &Self) -> Ordering {
+        return (&other.key, other.job.sequence).cmp(&(&self.key, self.job.sequence));
+    }
+}
+
+/// Runs higher priority jobs first, and jobs of the same priority in submission order. Unlike the
+/// default scheduler, the order holds across all workers.
+#[derive(Default)]
+pub struct PriorityScheduler {
+    jobs: BinaryHeap<Keyed<Priority>>,
+}
+
+impl Scheduler for PriorityScheduler {
+    fn push(&mut self, job: ScheduledJob) {
+        self.jobs.push(Keyed{ key: job.priority, job });
+    }
+
+    fn pop(&mut self) -> Option<ScheduledJob> {
+        return self.jobs.pop().map(|keyed| keyed.job);
+    }
+
+    fn len(&self) -> usize {
+        return self.jobs.len();
+    }
+}
+
+/// Runs the job with the earliest deadline first. Jobs without a deadline run after all others, in
+/// submission order.
+#[derive(Default)]
+pub struct DeadlineScheduler {
+    jobs: BinaryHeap<Keyed<(bool, Option<Instant>)>>,
+}
+
+impl Scheduler for DeadlineScheduler {
+    fn push(&mut self, job: ScheduledJob) {
+        let key = (job.deadline.is_none(), job.deadline);
+        self.jobs.push(Keyed{ key, job });
+    }
+
+    fn pop(&mut self) -> Option<ScheduledJob> {
+        return self.jobs.pop().map(|keyed| keyed.job);
+    }
+
+    fn len(&self) -> usize {
+        return self.jobs.len();
+    }
+}
+
+// *************************************************************************************************
+/// Shares the time of the workers between the tenants of
+/// [`TaskSystem::run_for_tenant`](super::TaskSystem::run_for_tenant) in proportion to their
+/// weights, for as long as they have jobs queued. Runs the queued job of the tenant that ran the
+/// least relative to its weight, and the jobs of a tenant in submission order. A tenant is charged
+/// once its jobs finish, and one that had nothing queued starts again level with the others rather
+/// than making up for the time it idled. Tenants without a weight get 1.
+///
+/// ```
+/// use task_system::{TaskSystem, WeightedFairScheduler};
+///
+/// // Tenant 0 gets 70% of the workers and tenant 1 the rest while both are busy.
+/// let system = TaskSystem::with_scheduler(WeightedFairScheduler::new(&[7, 3]));
+/// let task = system.run_for_tenant(1, || 42);
+/// assert_eq!(task.join(), Ok(42));
+/// ```
+pub struct WeightedFairScheduler {
+    weights: Vec<u32>,
+    queues: Vec<VecDeque<ScheduledJob>>,
+    // Time each tenant ran divided by its weight, in seconds.
+    virtual_time: Arc<Mutex<Vec<f64>>>,
+    len: usize,
+}
+
+impl WeightedFairScheduler {
+    /// Creates a scheduler with the weight of each tenant, indexed by tenant.
+    ///
+    /// # Panics
+    /// Panics if a weight is 0.
+    pub fn new(weights: &[u32]) -> Self {
+        assert!(weights.iter().all(|&weight| weight > 0), "tenant weights must not be zero");
+        return Self{
+            weights: weights.to_vec(),
+            queues: weights.iter().map(|_| VecDeque::new()).collect(),
+            virtual_time: Arc::new(Mutex::new(vec![0.0; weights.len()])),
+            len: 0,
+        };
+    }
+}
+
+impl Scheduler for WeightedFairScheduler {
+    fn push(&mut self, job: ScheduledJob) {
+        let tenant = job.tenant;
+        if self.queues.len() <= tenant {
+            self.weights.resize(tenant + 1, 1);
+            self.queues.resize_with(tenant + 1, VecDeque::new);
+        }
+
+        let mut virtual_time = self.virtual_time.lock().unpoison();
+        if virtual_time.len() <= tenant {
+            virtual_time.resize(tenant + 1, 0.0);
+        }
+        if self.queues[tenant].is_empty() {
+            let lowest = self.queues.iter().zip(virtual_time.iter())
+                .filter(|(queue, _)| !queue.is_empty())
+                .map(|(_, &time)| time)
+                .reduce(f64::min);
+            if let Some(lowest) = lowest {
+                virtual_time[tenant] = virtual_time[tenant].max(lowest);
+            }
+        }
+        drop(virtual_time);
+
+        self.queues[tenant].push_back(job);
+        self.len += 1;
+    }
+
+    fn pop(&mut self) -> Option<ScheduledJob> {
+        let virtual_time = self.virtual_time.lock().unpoison();
+        let tenant = (0..self.queues.len())
+            .filter(|&tenant| !self.queues[tenant].is_empty())
+            .min_by(|&a, &b| virtual_time[a].total_cmp(&virtual_time[b]))?;
+        drop(virtual_time);
+
+        let mut job = self.queues[tenant].pop_front().unwrap();
+        self.len -= 1;
+        let fun = job.job;
+        let weight = f64::from(self.weights[tenant]);
+        let virtual_time = self.virtual_time.clone();
+        job.job = Job::new(move || {
+            let started = Instant::now();
+            fun.run();
+            virtual_time.lock().unpoison()[tenant] += started.elapsed().as_secs_f64() / weight;
+        });
+        return Some(job);
+    }
+
+    fn len(&self) -> usize {
+        return self.len;
+    }
+}
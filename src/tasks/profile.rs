@@ -0,0 +1,109 @@
+use std::{
+    sync::Mutex,
+    collections::{HashMap, VecDeque},
+    time::Duration,
+};
+
+use super::Unpoison;
+
+// *************************************************************************************************
+/// Where the time of a task went, returned by [`Task::timings`](super::Task::timings). A duration
+/// is `None` until the task got past that step.
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Default)]
+pub struct TaskTimings {
+    /// Time from being queued until a worker started the task.
+    pub queued: Option<Duration>,
+    /// Time from starting until the task finished.
+    pub running: Option<Duration>,
+}
+
+/// Execution times of the tasks sharing a name, returned by
+/// [`TaskSystem::profile_report`](super::TaskSystem::profile_report).
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct TaskProfile {
+    /// Name given with [`TaskSystem::run_named`](super::TaskSystem::run_named), `None` for all
+    /// unnamed tasks together.
+    pub name: Option<String>,
+    /// Tasks that ran to the end.
+    pub count: u64,
+    /// Time spent running them.
+    pub total: Duration,
+    /// Average time one of them ran.
+    pub mean: Duration,
+    /// Time within which 95% of the most recent runs finished.
+    pub p95: Duration,
+}
+
+// *************************************************************************************************
+#[derive(Default)]
+struct Samples {
+    count: u64,
+    total: Duration,
+    // The latest runs, which the percentile is taken over so memory stays bounded.
+    recent: VecDeque<Duration>,
+}
+
+impl Samples {
+    const RECENT: usize = 1024;
+
+    fn record(&mut self, running: Duration) {
+        self.count += 1;
+        self.total += running;
+        if self.recent.len() == Self::RECENT {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(running);
+    }
+
+    fn profile(&self, name: Option<String>) -> TaskProfile {
+        let mut recent: Vec<Duration> = self.recent.iter().copied().collect();
+        recent.sort();
+        // Nearest rank, the smallest sample at or above 95% of them.
+        let p95 = match recent.len() {
+            0 => Duration::ZERO,
+            len => recent[(len * 95).div_ceil(100) - 1],
+        };
+
+        return TaskProfile{
+            name,
+            count: self.count,
+            total: self.total,
+            mean: Duration::from_nanos((self.total.as_nanos() / self.count.max(1) as u128) as u64),
+            p95,
+        };
+    }
+}
+
+// Execution times of finished tasks by name, kept when profiling is enabled on the builder.
+#[derive(Default)]
+pub(super) struct Profiler {
+    named: Mutex<HashMap<String, Samples>>,
+    unnamed: Mutex<Samples>,
+}
+
+impl Profiler {
+    pub(super) fn record(&self, name: Option<&str>, running: Duration) {
+        match name {
+            Some(name) => {
+                let mut named = self.named.lock().unpoison();
+                match named.get_mut(name) {
+                    Some(samples) => samples.record(running),
+                    None => named.entry(name.to_string()).or_default().record(running),
+                }
+            },
+            None => self.unnamed.lock().unpoison().record(running),
+        }
+    }
+
+    pub(super) fn report(&self) -> Vec<TaskProfile> {
+        let mut profiles: Vec<TaskProfile> = self.named.lock().unpoison().iter()
+            .map(|(name, samples)| samples.profile(Some(name.clone())))
+            .collect();
+        let unnamed = self.unnamed.lock().unpoison();
+        if unnamed.count > 0 {
+            profiles.push(unnamed.profile(None));
+        }
+        profiles.sort_by(|a, b| b.total.cmp(&a.total).then_with(|| a.name.cmp(&b.name)));
+        return profiles;
+    }
+}
@@ -0,0 +1,51 @@
+use std::sync::{Arc, Mutex, atomic::{AtomicU32, Ordering}};
+
+use super::{Unpoison, registry::TaskMeta};
+
+type ProgressListener = Box<dyn Fn(f32) + Send>;
+
+// *************************************************************************************************
+// Progress of a task as the bits of an `f32`, along with the callbacks interested in it.
+#[derive(Default)]
+pub(super) struct ProgressState {
+    value: AtomicU32,
+    listeners: Mutex<Vec<ProgressListener>>,
+}
+
+impl ProgressState {
+    pub(super) fn get(&self) -> f32 {
+        return f32::from_bits(self.value.load(Ordering::Relaxed));
+    }
+
+    pub(super) fn subscribe(&self, listener: ProgressListener) {
+        self.listeners.lock().unpoison().push(listener);
+    }
+}
+
+// *************************************************************************************************
+/// Lets a task started with [`TaskSystem::run_with_progress`](super::TaskSystem::run_with_progress)
+/// report how far along it is, read through [`Task::progress`](super::Task::progress).
+#[derive(Clone)]
+pub struct Progress {
+    pub(super) meta: Arc<TaskMeta>,
+}
+
+impl Progress {
+    /// Sets the progress, clamped to `0.0..=1.0`, and calls the listeners registered with
+    /// [`Task::on_progress`](super::Task::on_progress). Counts as a
+    /// [`heartbeat`](super::heartbeat).
+    pub fn set(&self, value: f32) {
+        let value = if value.is_nan() { 0.0 } else { value.clamp(0.0, 1.0) };
+        let listeners = self.meta.progress.listeners.lock().unpoison();
+        self.meta.progress.value.store(value.to_bits(), Ordering::Relaxed);
+        self.meta.beat();
+        for listener in listeners.iter() {
+            listener(value);
+        }
+    }
+
+    /// Last progress set.
+    pub fn get(&self) -> f32 {
+        return self.meta.progress.get();
+    }
+}
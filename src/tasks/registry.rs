@@ -0,0 +1,285 @@
+use std::{
+    sync::{Arc, OnceLock, atomic::AtomicIsize},
+    collections::HashMap,
+    time::{Duration, Instant},
+    backtrace::Backtrace,
+    fmt,
+};
+
+use super::{TaskStatus, Unpoison, memory::GroupMemory, metrics::TaskCounters, profile::Profiler, progress::ProgressState, timeline::TraceRecorder};
+use super::sync::{Condvar, Mutex, atomic::{self, AtomicU8, AtomicU64, AtomicUsize, Ordering}};
+
+// *************************************************************************************************
+/// Identifier of a task, unique within the process.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Copy, Clone)]
+pub struct TaskId(u64);
+
+impl fmt::Display for TaskId {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return write!(formatter, "#{}", self.0);
+    }
+}
+
+/// Snapshot of an unfinished task, returned by [`TaskSystem::tasks`](super::TaskSystem::tasks).
+#[derive(Debug, Clone)]
+pub struct TaskInfo {
+    /// Identifier of the task.
+    pub id: TaskId,
+    /// Name given with [`TaskSystem::run_named`](super::TaskSystem::run_named).
+    pub name: Option<String>,
+    /// Status at the time of the snapshot.
+    pub status: TaskStatus,
+    /// Time since the task was submitted.
+    pub age: Duration,
+    /// Progress reported through [`Progress`](super::Progress), 0 for tasks that don't.
+    pub progress: f32,
+}
+
+/// Transition of a task from one status to another, reported to the hook of
+/// [`TaskSystemBuilder::on_status_change`](super::TaskSystemBuilder::on_status_change).
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub struct StatusChange {
+    /// Identifier of the task.
+    pub id: TaskId,
+    /// Status before the change.
+    pub old: TaskStatus,
+    /// Status after the change.
+    pub new: TaskStatus,
+    /// When the task was submitted.
+    pub submitted: Instant,
+    /// When the status changed.
+    pub at: Instant,
+}
+
+pub(super) type StatusHook = Arc<dyn Fn(&StatusChange) + Send + Sync>;
+
+// *************************************************************************************************
+// Output independent part of a task's state, shared with the registry.
+pub(super) struct TaskMeta {
+    pub(super) id: TaskId,
+    pub(super) name: Option<String>,
+    pub(super) submitted: Instant,
+    pub(super) status: AtomicU8,
+    pub(super) progress: ProgressState,
+    // Nanoseconds from `submitted` until the task started or last reported it is alive.
+    alive: AtomicU64,
+    // Worker running the task, `usize::MAX` for other threads.
+    pub(super) worker: AtomicUsize,
+    // Where the task last called `heartbeat`.
+    pub(super) backtrace: Mutex<Option<Arc<Backtrace>>>,
+    // Bytes the task allocated and didn't free, see `TrackingAllocator`.
+    pub(super) memory: AtomicIsize,
+    // Memory of the work group the task belongs to.
+    pub(super) group_memory: OnceLock<Arc<GroupMemory>>,
+}
+
+impl TaskMeta {
+    pub(super) fn beat(&self) {
+        self.alive.store(self.submitted.elapsed().as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    pub(super) fn last_beat(&self) -> u64 {
+        return self.alive.load(Ordering::Relaxed);
+    }
+
+    // Time since the task started or last reported it is alive.
+    pub(super) fn silence(&self) -> Duration {
+        return self.submitted.elapsed().saturating_sub(Duration::from_nanos(self.last_beat()));
+    }
+}
+
+// Tasks that haven't finished yet. Tasks register when created and leave once they finish, so the
+// map is split into shards to keep submitting and finishing threads from contending on one lock.
+pub(super) struct TaskRegistry {
+    shards: Vec<Mutex<Shard>>,
+    pub(super) counters: TaskCounters,
+    pub(super) profiler: Option<Profiler>,
+    pub(super) recorder: Option<TraceRecorder>,
+    pub(super) on_status_change: Option<StatusHook>,
+    // Tasks queued or running, and the condvar notified once none are or something else threads
+    // waiting for the pool may wait for changed, see `WorkerPool::wait_until`. `changes` counts
+    // the notifications, so a waiter can tell whether it missed one.
+    busy: AtomicUsize,
+    changes: AtomicU64,
+    idle: Mutex<()>,
+    idle_condvar: Condvar,
+    // Threads in `WorkerPool::help_until`, notified whenever a task finishes or a job is queued.
+    helpers: AtomicUsize,
+}
+
+#[derive(Default)]
+struct Shard {
+    tasks: HashMap<TaskId, Arc<TaskMeta>>,
+    // Finished tasks, whose metas are reused by tasks registering later.
+    spare: Vec<Arc<TaskMeta>>,
+}
+
+impl TaskRegistry {
+    const SHARDS: usize = 32;
+    // Finished tasks kept per shard for their allocations to be reused.
+    const SPARE: usize = 64;
+
+    pub(super) fn new(profiling: bool, trace_capacity: Option<usize>, on_status_change: Option<StatusHook>) -> Arc<Self> {
+        return Arc::new(Self{
+            shards: (0..Self::SHARDS).map(|_| Mutex::new(Shard::default())).collect(),
+            counters: TaskCounters::default(),
+            profiler: profiling.then(Profiler::default),
+            recorder: trace_capacity.map(TraceRecorder::new),
+            on_status_change,
+            busy: AtomicUsize::new(0),
+            changes: AtomicU64::new(0),
+            idle: Mutex::new(()),
+            idle_condvar: Condvar::new(),
+            helpers: AtomicUsize::new(0),
+        });
+    }
+
+    fn shard(&self, id: TaskId) -> &Mutex<Shard> {
+        return &self.shards[id.0 as usize % Self::SHARDS];
+    }
+
+    pub(super) fn register(&self, name: Option<String>) -> Arc<TaskMeta> {
+        static NEXT_ID: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+
+        let id = TaskId(NEXT_ID.fetch_add(1, Ordering::Relaxed));
+        let fresh = TaskMeta{
+            id,
+            name,
+            submitted: Instant::now(),
+            status: AtomicU8::new(TaskStatus::None as u8),
+            progress: ProgressState::default(),
+            alive: AtomicU64::new(0),
+            worker: AtomicUsize::new(usize::MAX),
+            backtrace: Mutex::new(None),
+            memory: AtomicIsize::new(0),
+            group_memory: OnceLock::new(),
+        };
+        let mut shard = self.shard(id).lock().unpoison();
+        // Reuses the allocation of a finished task once nothing else refers to it.
+        let mut spare = shard.spare.pop();
+        let meta = match spare.as_mut().and_then(Arc::get_mut) {
+            Some(slot) => {
+                *slot = fresh;
+                spare.unwrap()
+            },
+            None => Arc::new(fresh),
+        };
+        shard.tasks.insert(id, meta.clone());
+        drop(shard);
+        self.counters.record_submitted();
+        return meta;
+    }
+
+    pub(super) fn unregister(&self, id: TaskId) {
+        let mut shard = self.shard(id).lock().unpoison();
+        if let Some(meta) = shard.tasks.remove(&id) {
+            if shard.spare.len() < Self::SPARE {
+                shard.spare.push(meta);
+            }
+        }
+    }
+
+    // Whether tasks of the status count as busy for `wait_idle`.
+    pub(super) fn is_busy(status: TaskStatus) -> bool {
+        return matches!(status, TaskStatus::Queued | TaskStatus::Running);
+    }
+
+    pub(super) fn enter_busy(&self) {
+        self.busy.fetch_add(1, Ordering::SeqCst);
+    }
+
+    pub(super) fn leave_busy(&self) {
+        if self.busy.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.notify_waiters();
+        }
+    }
+
+    pub(super) fn is_idle(&self) -> bool {
+        return self.busy.load(Ordering::SeqCst) == 0;
+    }
+
+    // Wakes the threads blocked in `wait_changed`.
+    pub(super) fn notify_waiters(&self) {
+        self.changes.fetch_add(1, Ordering::SeqCst);
+        // Taking the lock orders the notification after the check of a thread about to wait.
+        drop(self.idle.lock().unpoison());
+        self.idle_condvar.notify_all();
+    }
+
+    // Notifies the waiters if some of them help until a task finishes. Called once a task finished
+    // or a job was queued.
+    pub(super) fn notify_helpers(&self) {
+        // Pairs with the fence in `enter_helping`, so either the helper sees the change or this
+        // sees the helper.
+        atomic::fence(Ordering::SeqCst);
+        if self.helpers.load(Ordering::Relaxed) > 0 {
+            self.notify_waiters();
+        }
+    }
+
+    pub(super) fn enter_helping(&self) {
+        self.helpers.fetch_add(1, Ordering::Relaxed);
+        atomic::fence(Ordering::SeqCst);
+    }
+
+    pub(super) fn leave_helping(&self) {
+        self.helpers.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    // Number of notifications so far, to be passed to `wait_changed`.
+    pub(super) fn changes(&self) -> u64 {
+        return self.changes.load(Ordering::SeqCst);
+    }
+
+    // Counts a job without a task as busy until the guard is dropped.
+    pub(super) fn busy_guard(self: &Arc<Self>) -> BusyGuard {
+        self.enter_busy();
+        return BusyGuard(self.clone());
+    }
+
+    // Blocks until waiters are notified or `deadline` passed, returning right away if they were
+    // since `changes` was read.
+    pub(super) fn wait_changed(&self, changes: u64, deadline: Option<Instant>) {
+        let idle = self.idle.lock().unpoison();
+        if self.changes() != changes {
+            return;
+        }
+        match deadline {
+            Some(deadline) => drop(self.idle_condvar.wait_timeout(idle, deadline.saturating_duration_since(Instant::now())).unpoison()),
+            None => drop(self.idle_condvar.wait(idle).unpoison()),
+        }
+    }
+
+    pub(super) fn running(&self) -> Vec<Arc<TaskMeta>> {
+        let mut running = Vec::new();
+        for shard in &self.shards {
+            running.extend(shard.lock().unpoison().tasks.values()
+                .filter(|meta| TaskStatus::from_u8(meta.status.load(Ordering::Acquire)) == TaskStatus::Running)
+                .cloned());
+        }
+        return running;
+    }
+
+    pub(super) fn snapshot(&self) -> Vec<TaskInfo> {
+        let mut tasks = Vec::new();
+        for shard in &self.shards {
+            tasks.extend(shard.lock().unpoison().tasks.values().map(|meta| TaskInfo{
+                id: meta.id,
+                name: meta.name.clone(),
+                status: TaskStatus::from_u8(meta.status.load(Ordering::Acquire)),
+                age: meta.submitted.elapsed(),
+                progress: meta.progress.get(),
+            }));
+        }
+        tasks.sort_by_key(|task| task.id);
+        return tasks;
+    }
+}
+
+pub(super) struct BusyGuard(Arc<TaskRegistry>);
+
+impl Drop for BusyGuard {
+    fn drop(&mut self) {
+        self.0.leave_busy();
+    }
+}
@@ -0,0 +1,145 @@
+use std::{
+    sync::{Arc, Mutex},
+    collections::HashMap,
+};
+
+use super::{TaskPayload, Unpoison};
+
+// *************************************************************************************************
+/// Output of a [`RemoteTask`], encoded to travel back from wherever the task ran. With the `serde`
+/// feature, serde types become outputs by implementing [`SerdeOutput`].
+pub trait TaskOutput: Sized + Send + 'static {
+    /// Encodes the output.
+    fn to_bytes(&self) -> Vec<u8>;
+
+    /// Decodes an output encoded by [`TaskOutput::to_bytes`], `None` if `bytes` are invalid.
+    fn from_bytes(bytes: &[u8]) -> Option<Self>;
+}
+
+impl TaskOutput for () {
+    fn to_bytes(&self) -> Vec<u8> {
+        return Vec::new();
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        return bytes.is_empty().then_some(());
+    }
+}
+
+impl TaskOutput for Vec<u8> {
+    fn to_bytes(&self) -> Vec<u8> {
+        return self.clone();
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        return Some(bytes.to_vec());
+    }
+}
+
+impl TaskOutput for String {
+    fn to_bytes(&self) -> Vec<u8> {
+        return self.as_bytes().to_vec();
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        return String::from_utf8(bytes.to_vec()).ok();
+    }
+}
+
+/// Output encoded as JSON with serde, which only needs the marker to be a [`TaskOutput`]. Encoding
+/// panics if serde fails to serialize the output.
+#[cfg(feature = "serde")]
+pub trait SerdeOutput: serde::Serialize + serde::de::DeserializeOwned + Send + 'static {}
+
+#[cfg(feature = "serde")]
+impl<O> TaskOutput for O where O: SerdeOutput {
+    fn to_bytes(&self) -> Vec<u8> {
+        return serde_json::to_vec(self).expect("failed to serialize a task output");
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        return serde_json::from_slice(bytes).ok();
+    }
+}
+
+/// Payload of a task that can run in another process, see
+/// [`TaskSystem::run_remote`](super::TaskSystem::run_remote).
+pub trait RemoteTask: TaskPayload {
+    /// What the task completes with.
+    type Output: TaskOutput;
+}
+
+// *************************************************************************************************
+/// Task encoded as the [`TaskPayload::KIND`] of its payload and the payload's bytes, which an
+/// [`Executor`] can ship to another process.
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TaskDescriptor {
+    /// [`TaskPayload::KIND`] of the payload.
+    pub kind: String,
+    /// Encoded payload.
+    pub payload: Vec<u8>,
+}
+
+impl TaskDescriptor {
+    /// Encodes `payload`.
+    pub fn new<P>(payload: &P) -> Self where P: TaskPayload {
+        return Self{ kind: P::KIND.to_string(), payload: payload.to_bytes() };
+    }
+
+    /// Decodes the payload, `None` if it isn't a `P` or is invalid.
+    pub fn decode<P>(&self) -> Option<P> where P: TaskPayload {
+        if self.kind != P::KIND {
+            return None;
+        }
+        return P::from_bytes(&self.payload);
+    }
+}
+
+/// Completes the task of a descriptor handed to an [`Executor`]. Dropping it without sending
+/// cancels the task.
+pub struct Reply {
+    pub(super) send: Box<dyn FnOnce(Result<Vec<u8>, String>) + Send>,
+}
+
+impl Reply {
+    /// Completes the task with the encoded output, or fails it as if it panicked with the
+    /// message.
+    pub fn send(self, result: Result<Vec<u8>, String>) {
+        (self.send)(result);
+    }
+}
+
+/// Runs the tasks of [`TaskSystem::run_remote`](super::TaskSystem::run_remote), set with
+/// [`TaskSystemBuilder::executor`](super::TaskSystemBuilder::executor). An executor sending
+/// descriptors to another process has that process run them with
+/// [`TaskSystem::run_descriptor`](super::TaskSystem::run_descriptor) and sends the output back
+/// through the [`Reply`].
+pub trait Executor: Send + Sync + 'static {
+    /// Starts running `descriptor`, replying once it finished. May be called on any thread and
+    /// should return quickly.
+    fn execute(&self, descriptor: TaskDescriptor, reply: Reply);
+}
+
+// *************************************************************************************************
+pub(super) type DescriptorHandler = Arc<dyn Fn(&[u8]) -> Vec<u8> + Send + Sync>;
+
+// The executor of a task system and the handlers of the descriptor kinds it runs itself.
+pub(super) struct RemoteTasks {
+    pub(super) executor: Option<Arc<dyn Executor>>,
+    handlers: Mutex<HashMap<String, DescriptorHandler>>,
+}
+
+impl RemoteTasks {
+    pub(super) fn new(executor: Option<Arc<dyn Executor>>) -> Self {
+        return Self{ executor, handlers: Mutex::new(HashMap::new()) };
+    }
+
+    pub(super) fn register(&self, kind: &str, handler: DescriptorHandler) {
+        self.handlers.lock().unpoison().insert(kind.to_string(), handler);
+    }
+
+    pub(super) fn handler(&self, kind: &str) -> Option<DescriptorHandler> {
+        return self.handlers.lock().unpoison().get(kind).cloned();
+    }
+}
@@ -0,0 +1,193 @@
+use std::{
+    sync::{Arc, Mutex},
+    collections::{BTreeMap, VecDeque},
+    io::{self, BufRead, BufReader, Read, Write},
+};
+
+use super::{Unpoison, policy::{ScheduledJob, Scheduler}, scheduler};
+
+// *************************************************************************************************
+/// Job taken from the queue, as recorded by a [`RecordingScheduler`].
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub struct ScheduleEntry {
+    /// [`ScheduledJob::sequence`] of the job.
+    pub sequence: u64,
+    /// Index of the worker that took it, `None` for other threads.
+    pub worker: Option<usize>,
+}
+
+/// Order in which jobs were taken from the queue, recorded by a [`RecordingScheduler`] and
+/// enforced by a [`ReplayScheduler`].
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct Schedule {
+    /// Jobs in the order they were taken.
+    pub entries: Vec<ScheduleEntry>,
+}
+
+impl Schedule {
+    /// Writes the schedule as text, one `<sequence> <worker>` line per job with `-` for jobs not
+    /// taken by a worker.
+    pub fn save<W>(&self, mut out: W) -> io::Result<()> where W: Write {
+        for entry in &self.entries {
+            match entry.worker {
+                Some(worker) => writeln!(out, "{} {}", entry.sequence, worker)?,
+                None => writeln!(out, "{} -", entry.sequence)?,
+            }
+        }
+        return out.flush();
+    }
+
+    /// Reads a schedule written by [`Schedule::save`].
+    pub fn load<R>(input: R) -> io::Result<Self> where R: Read {
+        let invalid = |line: &str| io::Error::new(io::ErrorKind::InvalidData, format!("invalid schedule entry {:?}", line));
+        let mut entries = Vec::new();
+        for line in BufReader::new(input).lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let mut fields = line.split_whitespace();
+            let sequence = fields.next().and_then(|field| field.parse().ok()).ok_or_else(|| invalid(&line))?;
+            let worker = match fields.next() {
+                Some("-") => None,
+                Some(field) => Some(field.parse().map_err(|_| invalid(&line))?),
+                None => return Err(invalid(&line)),
+            };
+            entries.push(ScheduleEntry{ sequence, worker });
+        }
+        return Ok(Self{ entries });
+    }
+}
+
+// *************************************************************************************************
+/// Hands out jobs in the order of another scheduler while recording that order, to be replayed
+/// with a [`ReplayScheduler`] when chasing a bug that depends on it.
+///
+/// ```
+/// use task_system::{FifoScheduler, RecordingScheduler, TaskIteratorExt, TaskSystem};
+///
+/// let scheduler = RecordingScheduler::new(FifoScheduler::default());
+/// let recording = scheduler.recording();
+/// let system = TaskSystem::builder().workers(2).scheduler(scheduler).build();
+/// let tasks: Vec<_> = (0..4).map(|i| system.run(move || i)).collect();
+/// tasks.await_all().unwrap();
+/// assert_eq!(recording.schedule().entries.len(), 4);
+/// ```
+pub struct RecordingScheduler<S> {
+    inner: S,
+    recording: ScheduleRecording,
+}
+
+impl<S> RecordingScheduler<S> where S: Scheduler {
+    /// Records the order in which `inner` hands out jobs.
+    pub fn new(inner: S) -> Self {
+        return Self{ inner, recording: ScheduleRecording::default() };
+    }
+
+    /// Handle to read the schedule recorded so far, also once the scheduler was moved into a
+    /// task system.
+    pub fn recording(&self) -> ScheduleRecording {
+        return self.recording.clone();
+    }
+}
+
+impl<S> Scheduler for RecordingScheduler<S> where S: Scheduler {
+    fn push(&mut self, job: ScheduledJob) {
+        self.inner.push(job);
+    }
+
+    fn pop(&mut self) -> Option<ScheduledJob> {
+        let job = self.inner.pop()?;
+        self.recording.entries.lock().unpoison().push(ScheduleEntry{
+            sequence: job.sequence(),
+            worker: scheduler::current_worker_index(),
+        });
+        return Some(job);
+    }
+
+    fn len(&self) -> usize {
+        return self.inner.len();
+    }
+}
+
+/// Schedule recorded by a [`RecordingScheduler`], returned by [`RecordingScheduler::recording`].
+#[derive(Clone, Default)]
+pub struct ScheduleRecording {
+    entries: Arc<Mutex<Vec<ScheduleEntry>>>,
+}
+
+impl ScheduleRecording {
+    /// Jobs handed out so far.
+    pub fn schedule(&self) -> Schedule {
+        return Schedule{ entries: self.entries.lock().unpoison().clone() };
+    }
+}
+
+// *************************************************************************************************
+/// Hands out jobs in the order of a recorded [`Schedule`], meant for a task system built with
+/// [`TaskSystemBuilder::build_deterministic`](super::TaskSystemBuilder::build_deterministic) so
+/// the jobs also run one at a time in that order. Jobs are matched by
+/// [`ScheduledJob::sequence`], so the program has to submit them in the same order as when it was
+/// recorded. A job missing from the schedule, or asked for while the one recorded next isn't
+/// queued, runs in submission order. The workers of the recording are ignored.
+///
+/// ```
+/// use std::sync::{Arc, Mutex};
+/// use task_system::{LifoScheduler, RecordingScheduler, ReplayScheduler, TaskSystem, TaskSystemBuilder};
+///
+/// fn run(builder: TaskSystemBuilder) -> Vec<u32> {
+///     let system = builder.build_deterministic();
+///     let order = Arc::new(Mutex::new(Vec::new()));
+///     for i in 0..3 {
+///         let order = order.clone();
+///         system.run(move || order.lock().unwrap().push(i));
+///     }
+///     system.run_until_idle();
+///     return order.lock().unwrap().clone();
+/// }
+///
+/// let scheduler = RecordingScheduler::new(LifoScheduler::default());
+/// let recording = scheduler.recording();
+/// let recorded = run(TaskSystem::builder().scheduler(scheduler));
+/// assert_eq!(recorded, vec![2, 1, 0]);
+///
+/// let replayed = run(TaskSystem::builder().scheduler(ReplayScheduler::new(recording.schedule())));
+/// assert_eq!(replayed, recorded);
+/// ```
+pub struct ReplayScheduler {
+    // Sequences in the recorded order, the next one to run first.
+    expected: VecDeque<u64>,
+    jobs: BTreeMap<u64, ScheduledJob>,
+}
+
+impl ReplayScheduler {
+    /// Replays `schedule`.
+    pub fn new(schedule: Schedule) -> Self {
+        return Self{
+            expected: schedule.entries.iter().map(|entry| entry.sequence).collect(),
+            jobs: BTreeMap::new(),
+        };
+    }
+}
+
+impl Scheduler for ReplayScheduler {
+    fn push(&mut self, job: ScheduledJob) {
+        self.jobs.insert(job.sequence(), job);
+    }
+
+    fn pop(&mut self) -> Option<ScheduledJob> {
+        let next = self.expected.front().copied().and_then(|sequence| self.jobs.remove(&sequence));
+        if let Some(job) = next {
+            self.expected.pop_front();
+            return Some(job);
+        }
+
+        let (sequence, job) = self.jobs.pop_first()?;
+        self.expected.retain(|&expected| expected != sequence);
+        return Some(job);
+    }
+
+    fn len(&self) -> usize {
+        return self.jobs.len();
+    }
+}
@@ -0,0 +1,1254 @@
+use std::{
+    sync::{Arc, Weak},
+    collections::VecDeque,
+    cell::Cell,
+    any::Any,
+    time::{Duration, Instant},
+    panic::{self, AssertUnwindSafe},
+    mem,
+    ops::Range,
+};
+
+use super::{ExecutionOrder, Job, PanicLogger, Priority, Unpoison, mpmc::MpmcQueue, panic_message, policy::{ScheduledJob, Scheduler}, registry::TaskRegistry};
+use super::sync::{Condvar, Mutex, MutexGuard, atomic::{self, AtomicBool, AtomicU64, AtomicUsize, Ordering}, thread};
+
+// *************************************************************************************************
+#[cfg(not(loom))]
+thread_local! {
+    // Identifies the pool (by address) and the index of the worker running on this thread.
+    static CURRENT_WORKER: Cell<Option<(usize, usize)>> = const { Cell::new(None) };
+}
+// Loom threads all run on the same OS thread, so its own thread locals have to be used.
+#[cfg(loom)]
+loom::thread_local! {
+    static CURRENT_WORKER: Cell<Option<(usize, usize)>> = Cell::new(None);
+}
+
+pub(super) fn current_worker_index() -> Option<usize> {
+    return CURRENT_WORKER.with(|worker| worker.get()).map(|(_, index)| index);
+}
+
+type Lanes = [VecDeque<Job>; Priority::COUNT];
+
+// Moves every job up one lane, higher lanes first so no job skips a lane. Returns how many jobs
+// left each lane and how many joined it.
+fn promote(lanes: &mut Lanes) -> [(usize, usize); Priority::COUNT] {
+    let mut moved = [(0, 0); Priority::COUNT];
+    for lane in 1..Priority::COUNT {
+        let (higher, lower) = lanes.split_at_mut(lane);
+        moved[lane].0 = lower[0].len();
+        moved[lane - 1].1 = lower[0].len();
+        higher[lane - 1].extend(lower[0].drain(..));
+    }
+    return moved;
+}
+
+// *************************************************************************************************
+#[derive(Default, Clone)]
+pub(super) struct ThreadOptions {
+    // Workers are named `<name>-<index>`.
+    pub(super) name: Option<String>,
+    pub(super) stack_size: Option<usize>,
+    pub(super) on_start: Option<Arc<dyn Fn() + Send + Sync>>,
+    pub(super) on_panic: Option<WorkerPanicHook>,
+    // Worker `i` is pinned to `cores[i % cores.len()]`.
+    pub(super) cores: Vec<usize>,
+    // Sizes of the worker groups, which take consecutive worker indices.
+    pub(super) groups: Vec<usize>,
+    // How long workers beyond the first `core_workers`, the available parallelism by default, idle
+    // before their thread exits.
+    pub(super) keep_alive: Option<Duration>,
+    pub(super) core_workers: Option<usize>,
+    // Runs jobs on the submitting thread instead, for targets without threads.
+    pub(super) inline: bool,
+}
+
+pub(super) type WorkerPanicHook = Arc<dyn Fn(usize, &str) + Send + Sync>;
+
+// Pins the calling thread to `core`. Returns whether the OS accepted it.
+#[cfg(all(feature = "affinity", target_os = "linux"))]
+fn pin_to_core(core: usize) -> bool {
+    if core >= libc::CPU_SETSIZE as usize {
+        return false;
+    }
+    // SAFETY: the set is a plain bit mask, `core` is within it and pid 0 is the calling thread.
+    unsafe {
+        let mut set: libc::cpu_set_t = mem::zeroed();
+        libc::CPU_SET(core, &mut set);
+        return libc::sched_setaffinity(0, mem::size_of::<libc::cpu_set_t>(), &set) == 0;
+    }
+}
+
+#[cfg(not(all(feature = "affinity", target_os = "linux")))]
+fn pin_to_core(_core: usize) -> bool {
+    return false;
+}
+
+// CPUs of every NUMA node in node order, empty if the system doesn't tell.
+#[cfg(all(feature = "affinity", target_os = "linux"))]
+pub(super) fn numa_nodes() -> Vec<Vec<usize>> {
+    let mut nodes = Vec::new();
+    let entries = match std::fs::read_dir("/sys/devices/system/node") {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+    for entry in entries.flatten() {
+        let node = entry.file_name().to_str()
+            .and_then(|name| name.strip_prefix("node"))
+            .and_then(|id| id.parse::<usize>().ok());
+        let cpus = std::fs::read_to_string(entry.path().join("cpulist")).ok()
+            .and_then(|list| parse_cpu_list(&list))
+            .filter(|cpus| !cpus.is_empty());
+        if let (Some(node), Some(cpus)) = (node, cpus) {
+            nodes.push((node, cpus));
+        }
+    }
+    nodes.sort();
+    return nodes.into_iter().map(|(_, cpus)| cpus).collect();
+}
+
+#[cfg(all(feature = "affinity", not(target_os = "linux")))]
+pub(super) fn numa_nodes() -> Vec<Vec<usize>> {
+    return Vec::new();
+}
+
+// Parses a kernel CPU list like `0-3,8-11`.
+#[cfg(all(feature = "affinity", target_os = "linux"))]
+pub(super) fn parse_cpu_list(list: &str) -> Option<Vec<usize>> {
+    let mut cpus = Vec::new();
+    for range in list.trim().split(',').filter(|range| !range.is_empty()) {
+        match range.split_once('-') {
+            Some((first, last)) => cpus.extend(first.parse::<usize>().ok()?..=last.parse::<usize>().ok()?),
+            None => cpus.push(range.parse().ok()?),
+        }
+    }
+    return Some(cpus);
+}
+
+// *************************************************************************************************
+struct InjectorState {
+    lanes: Lanes,
+    // Workers blocked on the condvar and wake-ups sent to them that haven't been picked up yet.
+    // Waking a worker that is already on its way only costs a syscall and a context switch, so a
+    // notification is only sent when there is a waiter left without one.
+    waiters: usize,
+    notified: usize,
+    closing: bool,
+    // Replaces the lanes and the worker deques when set.
+    custom: Option<Box<dyn Scheduler>>,
+}
+
+impl InjectorState {
+    fn wake_one(&mut self, condvar: &Condvar) {
+        if self.waiters > self.notified {
+            self.notified += 1;
+            condvar.notify_one();
+        }
+    }
+
+    fn wake(&mut self, count: usize, condvar: &Condvar) {
+        let count = count.min(self.waiters.saturating_sub(self.notified));
+        self.notified += count;
+        for _ in 0..count {
+            condvar.notify_one();
+        }
+    }
+
+    fn has_jobs(&self) -> bool {
+        if let Some(custom) = &self.custom {
+            return !custom.is_empty();
+        }
+        return self.lanes.iter().any(|lane| !lane.is_empty());
+    }
+}
+
+// *************************************************************************************************
+// Every worker owns a deque per priority lane. The owner pops at the back so recently spawned LIFO
+// work runs while its data is still warm, FIFO work is pushed at the front to run after everything
+// already queued. Other workers steal from the front.
+struct WorkerQueue {
+    lanes: Mutex<Lanes>,
+    // Jobs routed to this worker, which run before its other jobs and are never stolen.
+    pinned: Mutex<VecDeque<Job>>,
+    paused: AtomicBool,
+    // Set while the worker's thread is gone for idling past the keep-alive.
+    expired: AtomicBool,
+    busy_nanos: AtomicU64,
+}
+
+impl WorkerQueue {
+    fn new() -> Self {
+        return Self{
+            lanes: Mutex::new(Default::default()),
+            pinned: Mutex::new(VecDeque::new()),
+            paused: AtomicBool::new(false),
+            expired: AtomicBool::new(false),
+            busy_nanos: AtomicU64::new(0),
+        };
+    }
+
+    fn paused(&self) -> bool {
+        return self.paused.load(Ordering::SeqCst);
+    }
+
+    fn push(&self, priority: Priority, order: ExecutionOrder, job: Job) {
+        let lane = &mut self.lanes.lock().unpoison()[priority.lane()];
+        match order {
+            ExecutionOrder::Lifo => lane.push_back(job),
+            ExecutionOrder::Fifo => lane.push_front(job),
+        }
+    }
+
+    fn push_all(&self, priority: Priority, order: ExecutionOrder, jobs: Vec<Job>) {
+        let lane = &mut self.lanes.lock().unpoison()[priority.lane()];
+        for job in jobs {
+            match order {
+                ExecutionOrder::Lifo => lane.push_back(job),
+                ExecutionOrder::Fifo => lane.push_front(job),
+            }
+        }
+    }
+
+    fn pop(&self, lane: usize) -> Option<Job> {
+        return self.lanes.lock().unpoison()[lane].pop_back();
+    }
+
+    fn steal(&self, lane: usize) -> Option<Job> {
+        return self.lanes.lock().unpoison()[lane].pop_front();
+    }
+
+    fn pop_pinned(&self) -> Option<Job> {
+        return self.pinned.lock().unpoison().pop_front();
+    }
+
+    fn has_jobs(&self) -> bool {
+        return self.lanes.lock().unpoison().iter().any(|lane| !lane.is_empty()) || !self.pinned.lock().unpoison().is_empty();
+    }
+
+    fn len(&self) -> usize {
+        return self.lanes.lock().unpoison().iter().map(|lane| lane.len()).sum::<usize>() + self.pinned.lock().unpoison().len();
+    }
+
+    fn drain_into(&self, jobs: &mut Vec<Job>) {
+        for lane in self.lanes.lock().unpoison().iter_mut() {
+            jobs.extend(lane.drain(..));
+        }
+        jobs.extend(self.pinned.lock().unpoison().drain(..));
+    }
+
+    fn promote(&self) {
+        promote(&mut self.lanes.lock().unpoison());
+    }
+
+    fn take_lanes(&self) -> Lanes {
+        return mem::take(&mut *self.lanes.lock().unpoison());
+    }
+}
+
+// *************************************************************************************************
+// Jobs routed to a group of workers, e.g. the ones of a NUMA node. Only the group's workers take
+// them, unless none of them is running anymore.
+struct GroupQueue {
+    workers: Range<usize>,
+    jobs: Mutex<VecDeque<Job>>,
+    // Lets workers skip the lock while the queue is empty.
+    queued: AtomicUsize,
+}
+
+impl GroupQueue {
+    fn pop(&self) -> Option<Job> {
+        if self.queued.load(Ordering::SeqCst) == 0 {
+            return None;
+        }
+        let job = self.jobs.lock().unpoison().pop_front();
+        if job.is_some() {
+            self.queued.fetch_sub(1, Ordering::SeqCst);
+        }
+        return job;
+    }
+}
+
+// *************************************************************************************************
+// Thread currently or last run for a worker queue. A retired worker's thread clears `running` on its
+// way out and is joined when the slot is reused or the pool shuts down.
+#[derive(Default)]
+struct WorkerSlot {
+    handle: Option<thread::JoinHandle<()>>,
+    running: bool,
+}
+
+// *************************************************************************************************
+pub(super) struct WorkerPool {
+    // Lets submitters start expired workers again.
+    this: Weak<Self>,
+    // Jobs submitted from outside of the pool, along with the idle worker bookkeeping.
+    injector: Mutex<InjectorState>,
+    // Jobs submitted from outside of the pool without taking the injector lock, moved to the
+    // injector's lanes by whoever takes jobs from them next.
+    inbox: [MpmcQueue<Job>; Priority::COUNT],
+    // Jobs in the injector's lanes and the inbox of each lane, counted before they are added.
+    injected: [AtomicUsize; Priority::COUNT],
+    condvar: Condvar,
+    // Paused workers wait separately so waking a single worker for new work never hits one of them.
+    paused_condvar: Condvar,
+    sleeping: AtomicUsize,
+    // One queue per possible worker. Workers with an index below `target` run, at most `spawned`
+    // queues were ever used.
+    workers: Vec<WorkerQueue>,
+    groups: Vec<GroupQueue>,
+    // Group of every worker, if it belongs to one.
+    group_of: Vec<Option<usize>>,
+    slots: Mutex<Vec<WorkerSlot>>,
+    target: AtomicUsize,
+    spawned: AtomicUsize,
+    active: AtomicUsize,
+    // Workers whose thread exited after idling, see `ThreadOptions::keep_alive`.
+    expired: AtomicUsize,
+    core_workers: usize,
+    // Set while a thread runs queued jobs inline, so jobs they submit wait for it.
+    draining: AtomicBool,
+    options: ThreadOptions,
+    // Default order of jobs spawned from workers.
+    order: ExecutionOrder,
+    pub(super) registry: Arc<TaskRegistry>,
+    // Receives panics of jobs run by threads helping the workers, see `run_helping`.
+    panic_logger: Arc<Mutex<PanicLogger>>,
+    // Jobs run by `TaskSystem::pump_main_tasks`, gone once the task system is.
+    pub(super) main_tasks: Weak<Mutex<VecDeque<Job>>>,
+    // Whether jobs go through a custom scheduler, fixed at creation.
+    custom: bool,
+    next_sequence: AtomicU64,
+    // Interval after which jobs still queued move up a lane, and when that is due next in
+    // nanoseconds since `created`.
+    aging: Option<Duration>,
+    next_aging: AtomicU64,
+    created: Instant,
+    // Set once the pool stopped running jobs. Jobs submitted from then on are dropped, which
+    // cancels their tasks.
+    rejecting: AtomicBool,
+}
+
+impl WorkerPool {
+    // Jobs each lane's inbox holds before submitters fall back to the injector lock.
+    const INBOX_CAPACITY: usize = 4096;
+
+    #[allow(clippy::too_many_arguments)]
+    pub(super) fn new(n_workers: usize, max_workers: usize, options: &ThreadOptions, order: ExecutionOrder, aging: Option<Duration>, registry: Arc<TaskRegistry>, panic_logger: Arc<Mutex<PanicLogger>>, main_tasks: Weak<Mutex<VecDeque<Job>>>, custom: Option<Box<dyn Scheduler>>) -> Arc<Self> {
+        assert!(n_workers <= max_workers, "{} workers exceed the maximum of {}", n_workers, max_workers);
+        let grouped: usize = options.groups.iter().sum();
+        assert!(grouped <= max_workers, "{} grouped workers exceed the maximum of {}", grouped, max_workers);
+
+        let mut groups = Vec::new();
+        let mut group_of = vec![None; max_workers];
+        for (group, &size) in options.groups.iter().enumerate() {
+            let start = groups.last().map_or(0, |group: &GroupQueue| group.workers.end);
+            group_of[start..start + size].fill(Some(group));
+            groups.push(GroupQueue{
+                workers: start..start + size,
+                jobs: Mutex::new(VecDeque::new()),
+                queued: AtomicUsize::new(0),
+            });
+        }
+
+        let pool = Arc::new_cyclic(|this| Self{
+            this: this.clone(),
+            injector: Mutex::new(InjectorState{
+                lanes: Default::default(),
+                waiters: 0,
+                notified: 0,
+                closing: false,
+                custom: None,
+            }),
+            inbox: [(); Priority::COUNT].map(|_| MpmcQueue::new(Self::INBOX_CAPACITY)),
+            injected: Default::default(),
+            condvar: Condvar::new(),
+            paused_condvar: Condvar::new(),
+            sleeping: AtomicUsize::new(0),
+            workers: (0..max_workers).map(|_| WorkerQueue::new()).collect(),
+            groups,
+            group_of,
+            slots: Mutex::new((0..max_workers).map(|_| WorkerSlot::default()).collect()),
+            target: AtomicUsize::new(0),
+            spawned: AtomicUsize::new(0),
+            active: AtomicUsize::new(0),
+            expired: AtomicUsize::new(0),
+            core_workers: options.core_workers.unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |n| n.get())),
+            draining: AtomicBool::new(false),
+            options: options.clone(),
+            order,
+            registry,
+            panic_logger,
+            main_tasks,
+            custom: custom.is_some(),
+            next_sequence: AtomicU64::new(0),
+            aging,
+            next_aging: AtomicU64::new(aging.map_or(0, |aging| aging.as_nanos() as u64)),
+            created: Instant::now(),
+            rejecting: AtomicBool::new(false),
+        });
+        pool.injector.lock().unpoison().custom = custom;
+
+        // Without workers, jobs only run when someone calls `run_injected`.
+        if n_workers > 0 {
+            pool.set_worker_count(n_workers);
+        }
+        return pool;
+    }
+
+    // Starts workers up to `n_workers`, or lets the ones above it exit once they finish their
+    // current job. Their queued jobs move to the injector.
+    pub(super) fn set_worker_count(self: &Arc<Self>, n_workers: usize) {
+        assert!(n_workers > 0, "a task system needs at least one worker");
+        assert!(n_workers <= self.workers.len(), "{} workers exceed the maximum of {}", n_workers, self.workers.len());
+
+        let mut slots = self.slots.lock().unpoison();
+        if self.injector.lock().unpoison().closing {
+            return;
+        }
+
+        self.target.store(n_workers, Ordering::SeqCst);
+        self.spawned.fetch_max(n_workers, Ordering::SeqCst);
+        // Expired workers are started below like any other whose thread is gone.
+        for worker in &self.workers {
+            if worker.expired.swap(false, Ordering::SeqCst) {
+                self.expired.fetch_sub(1, Ordering::SeqCst);
+            }
+        }
+        for (index, slot) in slots.iter_mut().enumerate().take(n_workers) {
+            // A retiring worker that didn't exit yet simply carries on.
+            if slot.running {
+                continue;
+            }
+            if let Some(handle) = slot.handle.take() {
+                let _ = handle.join();
+            }
+            slot.handle = Some(self.spawn_worker(index));
+            slot.running = true;
+        }
+        drop(slots);
+
+        let injector = self.injector.lock().unpoison();
+        self.condvar.notify_all();
+        self.paused_condvar.notify_all();
+        drop(injector);
+        self.registry.notify_waiters();
+    }
+
+    fn spawn_worker(self: &Arc<Self>, index: usize) -> thread::JoinHandle<()> {
+        let mut builder = thread::Builder::new();
+        if let Some(name) = &self.options.name {
+            builder = builder.name(format!("{}-{}", name, index));
+        }
+        if let Some(stack_size) = self.options.stack_size {
+            builder = builder.stack_size(stack_size);
+        }
+
+        let pool = self.clone();
+        return builder
+            .spawn(move || {
+                if !pool.options.cores.is_empty() {
+                    pin_to_core(pool.options.cores[index % pool.options.cores.len()]);
+                }
+                if let Some(on_start) = &pool.options.on_start {
+                    on_start();
+                }
+                if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(|| pool.worker_loop(index))) {
+                    pool.worker_panicked(index, payload.as_ref());
+                }
+            })
+            .expect("failed to spawn a worker thread");
+    }
+
+    // Reports a worker whose thread is about to exit from a panic and starts a replacement, unless
+    // the worker was retired or the pool is closing.
+    fn worker_panicked(self: &Arc<Self>, index: usize, payload: &(dyn Any + Send)) {
+        self.registry.counters.record_worker_panic();
+        if let Some(on_panic) = &self.options.on_panic {
+            let message = panic_message(payload);
+            let _ = panic::catch_unwind(AssertUnwindSafe(|| on_panic(index, &message)));
+        }
+
+        let mut slots = self.slots.lock().unpoison();
+        if self.retiring(index) || self.injector.lock().unpoison().closing {
+            slots[index].running = false;
+            return;
+        }
+        // Replacing the handle detaches the current thread, which exits right after.
+        slots[index].handle = Some(self.spawn_worker(index));
+    }
+
+    pub(super) fn execute(&self, job: Job) {
+        self.execute_with_priority(Priority::Normal, job);
+    }
+
+    pub(super) fn execute_with_priority(&self, priority: Priority, job: Job) {
+        self.execute_ordered(priority, self.order, job);
+    }
+
+    pub(super) fn execute_ordered(&self, priority: Priority, order: ExecutionOrder, job: Job) {
+        if self.custom {
+            return self.schedule(ScheduledJob::new(job, priority, self.next_sequence.fetch_add(1, Ordering::Relaxed), None));
+        }
+
+        match self.local_worker() {
+            Some(_) if self.rejecting.load(Ordering::SeqCst) => drop(job),
+            Some(index) => {
+                self.workers[index].push(priority, order, job);
+                // Pairs with the fence in `find_job` so a worker going to sleep either sees this job
+                // or is counted as sleeping here.
+                atomic::fence(Ordering::SeqCst);
+                if self.sleeping.load(Ordering::SeqCst) > 0 {
+                    self.injector.lock().unpoison().wake_one(&self.condvar);
+                } else {
+                    self.revive(0..self.workers.len());
+                }
+            },
+            None => self.inject(priority, job),
+        }
+    }
+
+    // Queues a job behind everything queued so far, even when called from a worker, whose own
+    // jobs would otherwise come first.
+    pub(super) fn execute_last(&self, job: Job) {
+        if self.custom {
+            return self.schedule(ScheduledJob::new(job, Priority::Normal, self.next_sequence.fetch_add(1, Ordering::Relaxed), None));
+        }
+        self.inject(Priority::Normal, job);
+    }
+
+    // Queues a job through the inbox, so submitting threads only contend on the injector lock when
+    // a worker needs waking or the inbox is full.
+    fn inject(&self, priority: Priority, job: Job) {
+        if self.rejecting.load(Ordering::SeqCst) {
+            drop(job);
+            return;
+        }
+        let lane = priority.lane();
+        self.injected[lane].fetch_add(1, Ordering::SeqCst);
+        if let Err(job) = self.inbox[lane].push(job) {
+            let mut injector = self.injector.lock().unpoison();
+            // Moves the jobs queued before this one ahead of it.
+            self.absorb(&mut injector);
+            injector.lanes[lane].push_back(job);
+        }
+
+        // Pairs with the fence in `find_job` like a push to a worker's own deque. A job queued
+        // once the pool started rejecting may have missed the drain, so it is drained again.
+        atomic::fence(Ordering::SeqCst);
+        if self.rejecting.load(Ordering::SeqCst) {
+            drop(self.drain());
+            return;
+        }
+        if self.sleeping.load(Ordering::SeqCst) > 0 {
+            self.injector.lock().unpoison().wake_one(&self.condvar);
+        }
+        self.revive_if_busy();
+    }
+
+    // Moves the jobs of the inboxes to the injector's lanes, behind the ones already there.
+    fn absorb(&self, injector: &mut InjectorState) {
+        for (lane, inbox) in injector.lanes.iter_mut().zip(self.inbox.iter()) {
+            lane.extend(std::iter::from_fn(|| inbox.pop()));
+        }
+    }
+
+    // Queues all jobs taking each lock once and wakes as many sleeping workers as there are jobs.
+    pub(super) fn execute_batch(&self, priority: Priority, jobs: Vec<Job>) {
+        let count = jobs.len();
+        if self.custom {
+            let mut injector = self.injector.lock().unpoison();
+            if self.rejecting.load(Ordering::SeqCst) {
+                drop(injector);
+                drop(jobs);
+                return;
+            }
+            let custom = injector.custom.as_mut().unwrap();
+            for job in jobs {
+                custom.push(ScheduledJob::new(job, priority, self.next_sequence.fetch_add(1, Ordering::Relaxed), None));
+            }
+            injector.wake(count, &self.condvar);
+            drop(injector);
+            return self.revive_if_busy();
+        }
+
+        match self.local_worker() {
+            Some(_) if self.rejecting.load(Ordering::SeqCst) => drop(jobs),
+            Some(index) => {
+                self.workers[index].push_all(priority, self.order, jobs);
+                atomic::fence(Ordering::SeqCst);
+                if self.sleeping.load(Ordering::SeqCst) > 0 {
+                    // The current worker takes one of the jobs itself.
+                    self.injector.lock().unpoison().wake(count.saturating_sub(1), &self.condvar);
+                }
+            },
+            None => {
+                let mut injector = self.injector.lock().unpoison();
+                if self.rejecting.load(Ordering::SeqCst) {
+                    drop(injector);
+                    drop(jobs);
+                    return;
+                }
+                self.absorb(&mut injector);
+                injector.lanes[priority.lane()].extend(jobs);
+                self.injected[priority.lane()].fetch_add(count, Ordering::SeqCst);
+                injector.wake(count, &self.condvar);
+            },
+        }
+        self.revive_if_busy();
+    }
+
+    // Queues a job that only the worker at `index` runs, or any worker once it was retired. All
+    // sleeping workers are woken, as the condvar can't pick the right one.
+    pub(super) fn execute_on(&self, index: usize, job: Job) {
+        assert!(index < self.worker_count(), "worker index {} out of range", index);
+        self.execute_near(Some(index), job);
+    }
+
+    // Queues a job for the worker at `index` like `execute_on`, or for any worker if there is no
+    // such worker (anymore).
+    pub(super) fn execute_near(&self, index: Option<usize>, job: Job) {
+        let index = match index {
+            Some(index) if index < self.workers.len() => index,
+            _ => return self.execute(job),
+        };
+        let mut injector = self.injector.lock().unpoison();
+        if self.rejecting.load(Ordering::SeqCst) {
+            drop(injector);
+            drop(job);
+            return;
+        }
+        if self.retiring(index) {
+            drop(injector);
+            return self.execute(job);
+        }
+        self.workers[index].pinned.lock().unpoison().push_back(job);
+        injector.notified = injector.waiters;
+        self.condvar.notify_all();
+        drop(injector);
+        // Checked after the push, which `expire` can't miss as both hold the injector lock.
+        if self.workers[index].expired.load(Ordering::SeqCst) {
+            self.revive(index..index + 1);
+        }
+    }
+
+    // Queues a job for the workers of `group`, waking them all like `execute_on`.
+    pub(super) fn execute_on_group(&self, group: usize, job: Job) {
+        assert!(group < self.groups.len(), "worker group {} out of range", group);
+        let mut injector = self.injector.lock().unpoison();
+        if self.rejecting.load(Ordering::SeqCst) {
+            drop(injector);
+            drop(job);
+            return;
+        }
+        self.groups[group].jobs.lock().unpoison().push_back(job);
+        self.groups[group].queued.fetch_add(1, Ordering::SeqCst);
+        injector.notified = injector.waiters;
+        self.condvar.notify_all();
+        drop(injector);
+        if self.sleeping.load(Ordering::SeqCst) == 0 {
+            self.revive(self.groups[group].workers.clone());
+        }
+    }
+
+    // Deadlines only matter to custom schedulers, the default one treats the job as any other.
+    pub(super) fn execute_with_deadline(&self, deadline: Instant, job: Job) {
+        if self.custom {
+            return self.schedule(ScheduledJob::new(job, Priority::Normal, self.next_sequence.fetch_add(1, Ordering::Relaxed), Some(deadline)));
+        }
+        self.execute(job);
+    }
+
+    // Queues a job of `tenant`, adding the time it runs to the tenant in the metrics.
+    pub(super) fn execute_for_tenant(&self, tenant: usize, job: Job) {
+        let registry = self.registry.clone();
+        let job: Job = Job::new(move || {
+            let started = Instant::now();
+            job.run();
+            registry.counters.record_tenant_busy(tenant, started.elapsed());
+        });
+        if self.custom {
+            return self.schedule(ScheduledJob::new(job, Priority::Normal, self.next_sequence.fetch_add(1, Ordering::Relaxed), None).for_tenant(tenant));
+        }
+        self.execute(job);
+    }
+
+    fn schedule(&self, job: ScheduledJob) {
+        let mut injector = self.injector.lock().unpoison();
+        if self.rejecting.load(Ordering::SeqCst) {
+            drop(injector);
+            drop(job);
+            return;
+        }
+        injector.custom.as_mut().unwrap().push(job);
+        injector.wake_one(&self.condvar);
+        drop(injector);
+        self.revive_if_busy();
+    }
+
+    // Blocks until `idle` holds or `deadline` passed. Returns whether it holds. Without workers the
+    // calling thread runs the queued jobs meanwhile. Whatever `idle` checks has to call
+    // `TaskRegistry::notify_waiters` once it holds.
+    pub(super) fn wait_until<F>(&self, deadline: Option<Instant>, idle: F) -> bool where F: Fn() -> bool {
+        loop {
+            let changes = self.registry.changes();
+            if self.worker_count() == 0 && self.help() {
+                continue;
+            }
+            if idle() {
+                return true;
+            }
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                return false;
+            }
+            // Also woken by jobs queued without workers and by the worker count changing.
+            self.registry.wait_changed(changes, deadline);
+        }
+    }
+
+    // Runs jobs with `help` until `done` holds, blocking while there are none to run. Whatever
+    // `done` checks has to hold once a task finished.
+    pub(super) fn help_until<H, D>(&self, help: H, done: D) where H: Fn() -> bool, D: Fn() -> bool {
+        self.registry.enter_helping();
+        loop {
+            let changes = self.registry.changes();
+            if done() {
+                break;
+            }
+            if !help() {
+                self.registry.wait_changed(changes, None);
+            }
+        }
+        self.registry.leave_helping();
+    }
+
+    pub(super) fn inline(&self) -> bool {
+        return self.options.inline;
+    }
+
+    pub(super) fn worker_count(&self) -> usize {
+        return self.target.load(Ordering::SeqCst);
+    }
+
+    pub(super) fn active_count(&self) -> usize {
+        return self.active.load(Ordering::SeqCst);
+    }
+
+    pub(super) fn queue_len(&self) -> usize {
+        let mut injector = self.injector.lock().unpoison();
+        self.absorb(&mut injector);
+        let injected: usize = match &injector.custom {
+            Some(custom) => custom.len(),
+            None => injector.lanes.iter().map(|lane| lane.len()).sum(),
+        };
+        drop(injector);
+        let grouped: usize = self.groups.iter().map(|group| group.queued.load(Ordering::SeqCst)).sum();
+        return injected + grouped + self.workers.iter().map(|worker| worker.len()).sum::<usize>();
+    }
+
+    pub(super) fn worker_busy_times(&self) -> Vec<Duration> {
+        return self.workers[..self.worker_count()].iter()
+            .map(|worker| Duration::from_nanos(worker.busy_nanos.load(Ordering::Relaxed)))
+            .collect();
+    }
+
+    pub(super) fn set_paused(&self, index: usize, paused: bool) {
+        assert!(index < self.worker_count(), "worker index {} out of range", index);
+        let _injector = self.injector.lock().unpoison();
+        self.workers[index].paused.store(paused, Ordering::SeqCst);
+        self.condvar.notify_all();
+        self.paused_condvar.notify_all();
+    }
+
+    // Runs one queued job on the calling thread if it is an active worker of this pool, so a worker
+    // waiting for another task keeps the pool busy instead of blocking it. Returns whether a job
+    // ran.
+    pub(super) fn help_once(&self) -> bool {
+        let index = match self.local_worker() {
+            Some(index) => index,
+            None if self.worker_count() == 0 => return self.run_injected(),
+            None => return false,
+        };
+        if self.workers[index].paused() || self.retiring(index) || self.rejecting.load(Ordering::SeqCst) {
+            return false;
+        }
+
+        let job = match self.pop_local(index) {
+            Some(job) => Some(job),
+            None => self.find_job(&mut self.injector.lock().unpoison(), index),
+        };
+        match job {
+            Some(job) => {
+                self.run_helping(job);
+                return true;
+            },
+            None => return false,
+        }
+    }
+
+    // Runs one queued job on the calling thread, which need not be a worker, taking it from the
+    // queue or from a worker's deque. Jobs pinned to a worker or queued for a group are left to the
+    // workers. Returns whether a job ran.
+    pub(super) fn help(&self) -> bool {
+        if self.local_worker().is_some() || self.worker_count() == 0 {
+            return self.help_once();
+        }
+
+        self.age();
+        let job = {
+            let mut injector = self.injector.lock().unpoison();
+            if self.rejecting.load(Ordering::SeqCst) {
+                return false;
+            }
+            self.absorb(&mut injector);
+            match injector.custom.as_mut() {
+                Some(custom) => custom.pop().map(|job| job.job),
+                None => (0..Priority::COUNT).find_map(|lane| {
+                    if let Some(job) = injector.lanes[lane].pop_front() {
+                        self.injected[lane].fetch_sub(1, Ordering::SeqCst);
+                        return Some(job);
+                    }
+                    let n_workers = self.spawned.load(Ordering::SeqCst);
+                    return (0..n_workers).find_map(|victim| self.workers[victim].steal(lane));
+                }),
+            }
+        };
+        match job {
+            Some(job) => {
+                self.run_helping(job);
+                return true;
+            },
+            None => return false,
+        }
+    }
+
+    // Runs the next job submitted from outside of the workers on the calling thread, in priority
+    // and then submission order. Returns whether a job ran.
+    pub(super) fn run_injected(&self) -> bool {
+        self.age();
+        let job = {
+            let mut injector = self.injector.lock().unpoison();
+            if self.rejecting.load(Ordering::SeqCst) {
+                return false;
+            }
+            match self.pop_injected(&mut injector) {
+                Some(job) => job,
+                None => return false,
+            }
+        };
+        self.run_helping(job);
+        return true;
+    }
+
+    // Runs a job on a thread that helps the workers. A panic is counted like one killing a worker
+    // and reported to the panic logger, but leaves the thread alive.
+    fn run_helping(&self, job: Job) {
+        if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(|| job.run())) {
+            self.registry.counters.record_worker_panic();
+            let logger = self.panic_logger.lock().unpoison().clone();
+            logger(&panic_message(payload.as_ref()));
+        }
+    }
+
+    fn pop_injected(&self, injector: &mut InjectorState) -> Option<Job> {
+        if let Some(custom) = injector.custom.as_mut() {
+            return custom.pop().map(|job| job.job);
+        }
+        self.absorb(injector);
+        for (lane, injected) in injector.lanes.iter_mut().zip(self.injected.iter()) {
+            if let Some(job) = lane.pop_front() {
+                injected.fetch_sub(1, Ordering::SeqCst);
+                return Some(job);
+            }
+        }
+        return None;
+    }
+
+    // Stops the workers once the queues are drained, or after their current job if `now` is set,
+    // and joins them. Whatever is left in the queues is dropped afterwards.
+    pub(super) fn shutdown(&self, now: bool) {
+        {
+            let mut injector = self.injector.lock().unpoison();
+            injector.closing = true;
+            if now {
+                self.rejecting.store(true, Ordering::SeqCst);
+            }
+            self.condvar.notify_all();
+            self.paused_condvar.notify_all();
+        }
+        if now {
+            drop(self.drain());
+        }
+
+        // A worker shutting down its own pool can't wait for itself.
+        let handles: Vec<_> = self.slots.lock().unpoison().iter_mut()
+            .filter_map(|slot| slot.handle.take())
+            .collect();
+        let current = thread::current().id();
+        for handle in handles {
+            if handle.thread().id() != current {
+                let _ = handle.join();
+            }
+        }
+
+        {
+            let _injector = self.injector.lock().unpoison();
+            self.rejecting.store(true, Ordering::SeqCst);
+        }
+        drop(self.drain());
+    }
+
+    // Collects the queued jobs. They must be dropped without holding any lock, as dropping a job
+    // finishes its task and runs the task's continuations.
+    fn drain(&self) -> Vec<Job> {
+        let mut jobs = Vec::new();
+        {
+            let mut injector = self.injector.lock().unpoison();
+            self.absorb(&mut injector);
+            for (lane, injected) in injector.lanes.iter_mut().zip(self.injected.iter()) {
+                injected.fetch_sub(lane.len(), Ordering::SeqCst);
+                jobs.extend(lane.drain(..));
+            }
+            if let Some(custom) = injector.custom.as_mut() {
+                jobs.extend(std::iter::from_fn(|| custom.pop()).map(|job| job.job));
+            }
+        }
+        for worker in &self.workers {
+            worker.drain_into(&mut jobs);
+        }
+        for group in &self.groups {
+            jobs.extend(group.jobs.lock().unpoison().drain(..));
+            group.queued.store(0, Ordering::SeqCst);
+        }
+        return jobs;
+    }
+
+    fn id(&self) -> usize {
+        return self as *const Self as usize;
+    }
+
+    fn local_worker(&self) -> Option<usize> {
+        return CURRENT_WORKER.with(|worker| worker.get())
+            .filter(|(pool, _)| *pool == self.id())
+            .map(|(_, index)| index);
+    }
+
+    // Takes work from the worker's own deque as long as nothing of at least the same priority is
+    // waiting in the injector, without touching the injector lock.
+    fn pop_local(&self, index: usize) -> Option<Job> {
+        if let Some(job) = self.workers[index].pop_pinned().or_else(|| self.pop_group(index)) {
+            return Some(job);
+        }
+        if self.custom {
+            return None;
+        }
+        for lane in 0..Priority::COUNT {
+            if let Some(job) = self.workers[index].pop(lane) {
+                return Some(job);
+            }
+            if self.injected[lane].load(Ordering::SeqCst) > 0 {
+                return None;
+            }
+        }
+        return None;
+    }
+
+    fn find_job(&self, injector: &mut MutexGuard<'_, InjectorState>, index: usize) -> Option<Job> {
+        atomic::fence(Ordering::SeqCst);
+
+        if let Some(job) = self.workers[index].pop_pinned().or_else(|| self.pop_group(index)) {
+            return Some(job);
+        }
+        if let Some(custom) = injector.custom.as_mut() {
+            return custom.pop().map(|job| job.job);
+        }
+        self.absorb(injector);
+
+        for lane in 0..Priority::COUNT {
+            if let Some(job) = self.workers[index].pop(lane) {
+                return Some(job);
+            }
+            if let Some(job) = injector.lanes[lane].pop_front() {
+                self.injected[lane].fetch_sub(1, Ordering::SeqCst);
+                return Some(job);
+            }
+
+            if let Some(job) = self.victims(index).find_map(|victim| self.workers[victim].steal(lane)) {
+                return Some(job);
+            }
+        }
+        return None;
+    }
+
+    // Takes a job routed to the worker's group, or to a group none of whose workers runs anymore.
+    fn pop_group(&self, index: usize) -> Option<Job> {
+        if let Some(job) = self.group_of[index].and_then(|group| self.groups[group].pop()) {
+            return Some(job);
+        }
+        let n_workers = self.worker_count();
+        return self.groups.iter()
+            .filter(|group| group.workers.start >= n_workers)
+            .find_map(|group| group.pop());
+    }
+
+    // Workers to steal from, the ones of the same group first so memory stays local as long as
+    // they have work to spare.
+    fn victims(&self, index: usize) -> impl Iterator<Item = usize> + '_ {
+        let n_workers = self.spawned.load(Ordering::SeqCst);
+        let group = self.group_of[index];
+        let others = (1..n_workers).map(move |offset| (index + offset) % n_workers);
+        return others.clone().filter(move |&victim| self.group_of[victim] == group)
+            .chain(others.filter(move |&victim| self.group_of[victim] != group));
+    }
+
+    // Once per aging interval, moves every job that is still queued up one lane, so a low priority
+    // job waits at most an interval per lane before it competes with the highest priority work. The
+    // thread that claims the tick does the moving, without holding the injector lock and a worker
+    // deque lock at once.
+    fn age(&self) {
+        let interval = match self.aging {
+            Some(interval) if !self.custom => interval.as_nanos() as u64,
+            _ => return,
+        };
+        let now = self.created.elapsed().as_nanos() as u64;
+        let due = self.next_aging.load(Ordering::Relaxed);
+        if now < due || self.next_aging.compare_exchange(due, now + interval, Ordering::Relaxed, Ordering::Relaxed).is_err() {
+            return;
+        }
+
+        {
+            let mut injector = self.injector.lock().unpoison();
+            self.absorb(&mut injector);
+            let moved = promote(&mut injector.lanes);
+            for ((left, joined), injected) in moved.iter().zip(self.injected.iter()) {
+                injected.fetch_add(*joined, Ordering::SeqCst);
+                injected.fetch_sub(*left, Ordering::SeqCst);
+            }
+        }
+        for worker in &self.workers[..self.spawned.load(Ordering::SeqCst)] {
+            worker.promote();
+        }
+    }
+
+    fn drained(&self, injector: &InjectorState) -> bool {
+        return !injector.has_jobs()
+            && self.workers.iter().all(|worker| !worker.has_jobs())
+            && self.groups.iter().all(|group| group.queued.load(Ordering::SeqCst) == 0);
+    }
+
+    fn retiring(&self, index: usize) -> bool {
+        return index >= self.target.load(Ordering::SeqCst);
+    }
+
+    // Lets a worker above the target count exit, unless the count was raised again in the meantime.
+    fn retire(&self, index: usize) -> bool {
+        let mut slots = self.slots.lock().unpoison();
+        if !self.retiring(index) {
+            return false;
+        }
+
+        let lanes = self.workers[index].take_lanes();
+        let mut injector = self.injector.lock().unpoison();
+        for ((lane, jobs), injected) in injector.lanes.iter_mut().zip(lanes).zip(self.injected.iter()) {
+            injected.fetch_add(jobs.len(), Ordering::SeqCst);
+            lane.extend(jobs);
+        }
+        // Taken under the injector lock, so nothing is routed to the worker after this.
+        let pinned = mem::take(&mut *self.workers[index].pinned.lock().unpoison());
+        match injector.custom.as_mut() {
+            Some(custom) => {
+                for job in pinned {
+                    custom.push(ScheduledJob::new(job, Priority::Normal, self.next_sequence.fetch_add(1, Ordering::Relaxed), None));
+                }
+            },
+            None => {
+                self.injected[Priority::Normal.lane()].fetch_add(pinned.len(), Ordering::SeqCst);
+                injector.lanes[Priority::Normal.lane()].extend(pinned);
+            },
+        }
+        if injector.has_jobs() {
+            injector.wake_one(&self.condvar);
+        }
+        // The group's jobs may be left to the other workers now.
+        if self.group_of[index].is_some_and(|group| self.groups[group].queued.load(Ordering::SeqCst) > 0) {
+            injector.notified = injector.waiters;
+            self.condvar.notify_all();
+        }
+        drop(injector);
+
+        slots[index].running = false;
+        return true;
+    }
+
+    fn idle_timeout(&self, index: usize) -> Option<Duration> {
+        return self.options.keep_alive.filter(|_| index >= self.core_workers);
+    }
+
+    // Lets a worker that idled for the keep-alive exit, unless work showed up meanwhile.
+    fn expire(&self, index: usize) -> bool {
+        if self.idle_timeout(index).is_none() {
+            return false;
+        }
+        let mut slots = self.slots.lock().unpoison();
+        // Counted as expired before looking at the inboxes, so a submitter either sees the worker
+        // expired and revives it or queued its job in time to be seen here.
+        self.workers[index].expired.store(true, Ordering::SeqCst);
+        self.expired.fetch_add(1, Ordering::SeqCst);
+        atomic::fence(Ordering::SeqCst);
+        let mut injector = self.injector.lock().unpoison();
+        self.absorb(&mut injector);
+        let group_jobs = self.group_of[index].is_some_and(|group| self.groups[group].queued.load(Ordering::SeqCst) > 0);
+        if self.retiring(index) || injector.closing || injector.has_jobs() || self.workers[index].has_jobs() || group_jobs {
+            self.workers[index].expired.store(false, Ordering::SeqCst);
+            self.expired.fetch_sub(1, Ordering::SeqCst);
+            return false;
+        }
+        drop(injector);
+
+        slots[index].running = false;
+        return true;
+    }
+
+    fn revive_if_busy(&self) {
+        if self.options.inline {
+            return self.run_inline();
+        }
+        // Without workers, the jobs are run by the threads waiting in `wait_until`.
+        if self.worker_count() == 0 {
+            return self.registry.notify_waiters();
+        }
+        // Threads helping until a task finishes may run the job.
+        self.registry.notify_helpers();
+        if self.sleeping.load(Ordering::SeqCst) == 0 {
+            self.revive(0..self.workers.len());
+        }
+    }
+
+    // Runs the queued jobs on the calling thread, unless it or another one already does. Jobs
+    // queued meanwhile run after the current one, like a microtask queue.
+    fn run_inline(&self) {
+        while !self.draining.swap(true, Ordering::SeqCst) {
+            while self.run_injected() {}
+            self.draining.store(false, Ordering::SeqCst);
+            // Another thread may have queued a job after the last one was taken.
+            if self.rejecting.load(Ordering::SeqCst) || self.queue_len() == 0 {
+                return;
+            }
+        }
+    }
+
+    // Starts the first expired worker among `candidates` again, as work was queued that no
+    // sleeping worker may pick up.
+    fn revive(&self, candidates: Range<usize>) {
+        if self.expired.load(Ordering::SeqCst) == 0 {
+            return;
+        }
+        let pool = match self.this.upgrade() {
+            Some(pool) => pool,
+            None => return,
+        };
+        let mut slots = self.slots.lock().unpoison();
+        if self.injector.lock().unpoison().closing {
+            return;
+        }
+        let target = self.worker_count();
+        let index = match candidates.take_while(|&index| index < target).find(|&index| self.workers[index].expired.load(Ordering::SeqCst)) {
+            Some(index) => index,
+            None => return,
+        };
+        self.workers[index].expired.store(false, Ordering::SeqCst);
+        self.expired.fetch_sub(1, Ordering::SeqCst);
+        if let Some(handle) = slots[index].handle.take() {
+            let _ = handle.join();
+        }
+        slots[index].handle = Some(pool.spawn_worker(index));
+        slots[index].running = true;
+    }
+
+    fn next_job(&self, index: usize) -> Option<Job> {
+        if self.rejecting.load(Ordering::SeqCst) || self.retiring(index) {
+            return None;
+        }
+        self.age();
+
+        let worker = &self.workers[index];
+        if !worker.paused() {
+            if let Some(job) = self.pop_local(index) {
+                return Some(job);
+            }
+        }
+
+        let mut injector = self.injector.lock().unpoison();
+        let mut idled = false;
+        loop {
+            if self.retiring(index) {
+                return None;
+            }
+            if worker.paused() {
+                if injector.closing {
+                    return None;
+                }
+                injector = self.paused_condvar.wait(injector).unpoison();
+                continue;
+            }
+
+            // Counted as sleeping before looking for work, see `execute_with_priority`.
+            self.sleeping.fetch_add(1, Ordering::SeqCst);
+
+            if let Some(job) = self.find_job(&mut injector, index) {
+                self.sleeping.fetch_sub(1, Ordering::SeqCst);
+                // Pass the wake-up on so idle workers join in one by one while work remains, starting
+                // expired ones once no worker is left sleeping.
+                if injector.has_jobs() {
+                    if injector.waiters > injector.notified {
+                        injector.wake_one(&self.condvar);
+                    } else {
+                        drop(injector);
+                        self.revive(0..self.workers.len());
+                    }
+                }
+                return Some(job);
+            }
+            if (injector.closing && self.drained(&injector)) || idled {
+                self.sleeping.fetch_sub(1, Ordering::SeqCst);
+                return None;
+            }
+
+            injector.waiters += 1;
+            injector = match self.idle_timeout(index) {
+                Some(idle) => {
+                    let (injector, result) = self.condvar.wait_timeout(injector, idle).unpoison();
+                    idled = result.timed_out();
+                    injector
+                },
+                None => self.condvar.wait(injector).unpoison(),
+            };
+            injector.waiters -= 1;
+            injector.notified = injector.notified.saturating_sub(1);
+            self.sleeping.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+
+    fn worker_loop(self: &Arc<Self>, index: usize) {
+        CURRENT_WORKER.with(|worker| worker.set(Some((self.id(), index))));
+
+        loop {
+            while let Some(job) = self.next_job(index) {
+                let start = Instant::now();
+                self.active.fetch_add(1, Ordering::SeqCst);
+                let result = panic::catch_unwind(AssertUnwindSafe(|| job.run()));
+                self.active.fetch_sub(1, Ordering::SeqCst);
+                self.workers[index].busy_nanos.fetch_add(start.elapsed().as_nanos() as u64, Ordering::Relaxed);
+                // Tasks catch their own panics, so this one escaped a plain job or a completion
+                // callback. The thread is replaced rather than trusted to carry on.
+                if let Err(payload) = result {
+                    panic::resume_unwind(payload);
+                }
+            }
+
+            // Out of jobs because the pool is closing, or the worker was retired or idled out.
+            if self.rejecting.load(Ordering::SeqCst) || self.injector.lock().unpoison().closing || self.retire(index) || self.expire(index) {
+                return;
+            }
+        }
+    }
+}
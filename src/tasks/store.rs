@@ -0,0 +1,226 @@
+use std::{
+    sync::{Arc, Mutex},
+    collections::{BTreeMap, HashMap, HashSet},
+    convert::{TryFrom, TryInto},
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use super::Unpoison;
+
+// *************************************************************************************************
+/// Input of a task that can be written to a [`TaskStore`] and read back after a restart, see
+/// [`TaskSystem::run_serialized`](super::TaskSystem::run_serialized).
+pub trait TaskPayload: Sized + Send + 'static {
+    /// Name the handler of these payloads is registered under, unique among payload types and
+    /// stable across versions of the program.
+    const KIND: &'static str;
+
+    /// Encodes the payload.
+    fn to_bytes(&self) -> Vec<u8>;
+
+    /// Decodes a payload encoded by [`TaskPayload::to_bytes`], `None` if `bytes` are invalid.
+    fn from_bytes(bytes: &[u8]) -> Option<Self>;
+}
+
+/// Payload encoded as JSON with serde, which only needs its [`TaskPayload::KIND`] to be a
+/// [`TaskPayload`]. Encoding panics if serde fails to serialize the payload.
+#[cfg(feature = "serde")]
+pub trait SerdePayload: serde::Serialize + serde::de::DeserializeOwned + Send + 'static {
+    /// See [`TaskPayload::KIND`].
+    const KIND: &'static str;
+}
+
+#[cfg(feature = "serde")]
+impl<P> TaskPayload for P where P: SerdePayload {
+    const KIND: &'static str = <P as SerdePayload>::KIND;
+
+    fn to_bytes(&self) -> Vec<u8> {
+        return serde_json::to_vec(self).expect("failed to serialize a task payload");
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        return serde_json::from_slice(bytes).ok();
+    }
+}
+
+/// Task kept in a [`TaskStore`] until it ran.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct StoredTask {
+    /// Identifier assigned by the store.
+    pub id: u64,
+    /// [`TaskPayload::KIND`] of the payload.
+    pub kind: String,
+    /// Encoded payload.
+    pub payload: Vec<u8>,
+}
+
+/// Storage of the tasks submitted with
+/// [`TaskSystem::run_serialized`](super::TaskSystem::run_serialized) that didn't run yet, set with
+/// [`TaskSystemBuilder::task_store`](super::TaskSystemBuilder::task_store).
+pub trait TaskStore: Send + Sync + 'static {
+    /// Saves a task before it is queued and returns its identifier.
+    fn push(&self, kind: &str, payload: &[u8]) -> io::Result<u64>;
+
+    /// Forgets a task once it ran.
+    fn remove(&self, id: u64) -> io::Result<()>;
+
+    /// Tasks saved and not removed, in the order they were pushed.
+    fn pending(&self) -> io::Result<Vec<StoredTask>>;
+}
+
+// *************************************************************************************************
+/// [`TaskStore`] appending to a log file, which is compacted when opened. A record cut short by a
+/// crash is ignored.
+pub struct FileTaskStore {
+    path: PathBuf,
+    state: Mutex<FileState>,
+}
+
+struct FileState {
+    file: File,
+    next_id: u64,
+    pending: BTreeMap<u64, StoredTask>,
+}
+
+impl FileTaskStore {
+    const PUSH: u8 = 1;
+    const REMOVE: u8 = 2;
+
+    /// Opens the store at `path`, creating the file if it doesn't exist.
+    pub fn open<P>(path: P) -> io::Result<Self> where P: AsRef<Path> {
+        let path = path.as_ref().to_path_buf();
+        let mut log = Vec::new();
+        match File::open(&path) {
+            Ok(mut file) => {
+                file.read_to_end(&mut log)?;
+            },
+            Err(error) if error.kind() == io::ErrorKind::NotFound => {},
+            Err(error) => return Err(error),
+        }
+        let pending = Self::replay(&log);
+        let next_id = pending.keys().next_back().map_or(0, |id| id + 1);
+
+        // Rewrites the pending tasks only, then swaps the files so a crash leaves either version.
+        let compacted = path.with_extension("compacting");
+        let mut file = File::create(&compacted)?;
+        for task in pending.values() {
+            file.write_all(&Self::push_record(task))?;
+        }
+        file.sync_all()?;
+        fs::rename(&compacted, &path)?;
+
+        return Ok(Self{
+            path: path.clone(),
+            state: Mutex::new(FileState{
+                file: OpenOptions::new().append(true).open(&path)?,
+                next_id,
+                pending,
+            }),
+        });
+    }
+
+    /// Path of the log file.
+    pub fn path(&self) -> &Path {
+        return &self.path;
+    }
+
+    fn replay(mut log: &[u8]) -> BTreeMap<u64, StoredTask> {
+        let mut pending = BTreeMap::new();
+        while let Some((&tag, rest)) = log.split_first() {
+            let Some((id, rest)) = take_u64(rest) else { break; };
+            match tag {
+                Self::PUSH => {
+                    let Some((kind, rest)) = take_bytes(rest) else { break; };
+                    let Some((payload, rest)) = take_bytes(rest) else { break; };
+                    let Ok(kind) = String::from_utf8(kind.to_vec()) else { break; };
+                    pending.insert(id, StoredTask{ id, kind, payload: payload.to_vec() });
+                    log = rest;
+                },
+                Self::REMOVE => {
+                    pending.remove(&id);
+                    log = rest;
+                },
+                _ => break,
+            }
+        }
+        return pending;
+    }
+
+    fn push_record(task: &StoredTask) -> Vec<u8> {
+        let mut record = vec![Self::PUSH];
+        record.extend_from_slice(&task.id.to_le_bytes());
+        for bytes in [task.kind.as_bytes(), &task.payload] {
+            record.extend_from_slice(&(bytes.len() as u64).to_le_bytes());
+            record.extend_from_slice(bytes);
+        }
+        return record;
+    }
+}
+
+impl TaskStore for FileTaskStore {
+    fn push(&self, kind: &str, payload: &[u8]) -> io::Result<u64> {
+        let mut state = self.state.lock().unpoison();
+        let task = StoredTask{ id: state.next_id, kind: kind.to_string(), payload: payload.to_vec() };
+        state.file.write_all(&Self::push_record(&task))?;
+        state.file.sync_data()?;
+        state.next_id += 1;
+        let id = task.id;
+        state.pending.insert(id, task);
+        return Ok(id);
+    }
+
+    fn remove(&self, id: u64) -> io::Result<()> {
+        let mut state = self.state.lock().unpoison();
+        if state.pending.remove(&id).is_none() {
+            return Ok(());
+        }
+        let mut record = vec![Self::REMOVE];
+        record.extend_from_slice(&id.to_le_bytes());
+        return state.file.write_all(&record);
+    }
+
+    fn pending(&self) -> io::Result<Vec<StoredTask>> {
+        return Ok(self.state.lock().unpoison().pending.values().cloned().collect());
+    }
+}
+
+fn take_u64(bytes: &[u8]) -> Option<(u64, &[u8])> {
+    if bytes.len() < 8 {
+        return None;
+    }
+    let (value, rest) = bytes.split_at(8);
+    return Some((u64::from_le_bytes(value.try_into().unwrap()), rest));
+}
+
+fn take_bytes(bytes: &[u8]) -> Option<(&[u8], &[u8])> {
+    let (len, rest) = take_u64(bytes)?;
+    let len = usize::try_from(len).ok().filter(|&len| len <= rest.len())?;
+    return Some(rest.split_at(len));
+}
+
+// *************************************************************************************************
+pub(super) type PayloadHandler = Arc<dyn Fn(&[u8]) + Send + Sync>;
+
+// The store of a task system and the handlers of the payload kinds it runs.
+pub(super) struct DurableTasks {
+    pub(super) store: Option<Arc<dyn TaskStore>>,
+    pub(super) handlers: Mutex<HashMap<&'static str, PayloadHandler>>,
+    // Stored tasks queued by this process and yet to run, which resuming skips.
+    pub(super) queued: Arc<Mutex<HashSet<u64>>>,
+}
+
+impl DurableTasks {
+    pub(super) fn new(store: Option<Arc<dyn TaskStore>>) -> Self {
+        return Self{
+            store,
+            handlers: Mutex::new(HashMap::new()),
+            queued: Arc::new(Mutex::new(HashSet::new())),
+        };
+    }
+
+    pub(super) fn handler(&self, kind: &str) -> Option<PayloadHandler> {
+        return self.handlers.lock().unpoison().get(kind).cloned();
+    }
+}
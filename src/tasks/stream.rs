@@ -0,0 +1,136 @@
+use std::{
+    sync::{Arc, Mutex, Condvar},
+    collections::VecDeque,
+    time::Instant,
+};
+
+use super::{GetValueError, Job, Task, TaskBase, TaskStatus, Unpoison, sealed::Dependency};
+
+// *************************************************************************************************
+struct StreamState<O> {
+    items: VecDeque<O>,
+    // Set once the producing task finished, nothing arrives after that.
+    closed: bool,
+}
+
+// Items sent by a streaming task, waiting for the consumer.
+struct StreamChannel<O> {
+    state: Mutex<StreamState<O>>,
+    available: Condvar,
+}
+
+impl<O> StreamChannel<O> {
+    fn close(&self) {
+        self.state.lock().unpoison().closed = true;
+        self.available.notify_all();
+    }
+}
+
+// *************************************************************************************************
+/// Handed to tasks started with [`TaskSystem::run_stream`](super::TaskSystem::run_stream) to send
+/// their items.
+pub struct StreamSender<O> {
+    channel: Arc<StreamChannel<O>>,
+}
+
+impl<O> StreamSender<O> {
+    /// Hands `item` over to the consumer.
+    pub fn send(&self, item: O) {
+        self.channel.state.lock().unpoison().items.push_back(item);
+        self.channel.available.notify_one();
+    }
+}
+
+// *************************************************************************************************
+/// Handle to a task that produces items while it runs. Iterating it yields the items as they
+/// arrive and ends once the task finished and everything it sent was received.
+pub struct StreamTask<O> {
+    task: Task<()>,
+    channel: Arc<StreamChannel<O>>,
+}
+
+impl<O> StreamTask<O> where O: Send + 'static {
+    pub(super) fn new<S>(start: S) -> Self where S: FnOnce(StreamSender<O>) -> Task<()> {
+        let channel = Arc::new(StreamChannel{
+            state: Mutex::new(StreamState{ items: VecDeque::new(), closed: false }),
+            available: Condvar::new(),
+        });
+        let task = start(StreamSender{ channel: channel.clone() });
+
+        let closing = channel.clone();
+        task.on_completed(Job::new(move || closing.close()));
+        return Self{ task, channel };
+    }
+}
+
+impl<O> StreamTask<O> {
+    /// Waits for the next item. Returns `None` once the task finished and every item it sent was
+    /// received.
+    pub fn recv(&mut self) -> Option<O> {
+        let mut mutex = self.channel.state.lock().unpoison();
+        loop {
+            if let Some(item) = mutex.items.pop_front() {
+                return Some(item);
+            }
+            if mutex.closed {
+                return None;
+            }
+            mutex = self.channel.available.wait(mutex).unpoison();
+        }
+    }
+
+    /// Takes the next item if one arrived already.
+    pub fn try_recv(&mut self) -> Option<O> {
+        return self.channel.state.lock().unpoison().items.pop_front();
+    }
+
+    /// Outcome of the producing task, see [`Task::value`].
+    pub fn value(&mut self) -> Result<(), GetValueError> {
+        return self.task.value();
+    }
+
+    /// Cancels the producing task, see [`Task::cancel`]. Items sent so far can still be received.
+    pub fn cancel(&self) {
+        self.task.cancel();
+    }
+}
+
+impl<O> Iterator for StreamTask<O> {
+    type Item = O;
+
+    fn next(&mut self) -> Option<O> {
+        return self.recv();
+    }
+}
+
+impl<O> TaskBase for StreamTask<O> {
+    fn status(&self) -> TaskStatus {
+        return self.task.status();
+    }
+
+    fn queued(&self) -> bool {
+        return self.task.queued();
+    }
+
+    fn running(&self) -> bool {
+        return self.task.running();
+    }
+
+    fn completed(&self) -> bool {
+        return self.task.completed();
+    }
+
+    fn wait(&mut self) {
+        self.task.wait();
+    }
+
+    fn wait_deadline(&mut self, deadline: Instant) -> bool {
+        return self.task.wait_deadline(deadline);
+    }
+}
+
+impl<O> Dependency for StreamTask<O> {
+    fn on_completed(&self, continuation: Job) {
+        self.task.on_completed(continuation);
+    }
+}
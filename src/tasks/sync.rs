@@ -0,0 +1,34 @@
+// Synchronization primitives of the task states, the registry, the scheduler and the lock-free
+// queue, taken from `loom` when building with `RUSTFLAGS="--cfg loom"` so the way they work
+// together can be model checked, see the `loom` tests. Counters only kept for statistics, and
+// statics, stay on `std` as loom can't create its primitives in a `const`.
+
+#[cfg(not(loom))]
+pub(super) use std::{
+    sync::{Condvar, Mutex, MutexGuard, atomic},
+    thread,
+};
+#[cfg(loom)]
+pub(super) use loom::{
+    sync::{Condvar, Mutex, MutexGuard, atomic},
+    thread,
+};
+
+#[cfg(loom)]
+pub(super) use loom::cell::UnsafeCell;
+
+// `std::cell::UnsafeCell` with the closure based access of `loom::cell::UnsafeCell`, which is how
+// loom tracks accesses to the value.
+#[cfg(not(loom))]
+pub(super) struct UnsafeCell<T>(std::cell::UnsafeCell<T>);
+
+#[cfg(not(loom))]
+impl<T> UnsafeCell<T> {
+    pub(super) fn new(value: T) -> Self {
+        return Self(std::cell::UnsafeCell::new(value));
+    }
+
+    pub(super) fn with_mut<R>(&self, fun: impl FnOnce(*mut T) -> R) -> R {
+        return fun(self.0.get());
+    }
+}
@@ -0,0 +1,118 @@
+use std::{
+    sync::Mutex,
+    collections::VecDeque,
+    time::{Duration, Instant},
+    fmt::Write as _,
+    io::{self, Write},
+};
+
+use super::{TaskId, Unpoison};
+
+// *************************************************************************************************
+/// One task run, as recorded when [`TaskSystemBuilder::record_trace`] is enabled and returned by
+/// [`TaskSystem::trace`](super::TaskSystem::trace).
+///
+/// [`TaskSystemBuilder::record_trace`]: super::TaskSystemBuilder::record_trace
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct TraceEvent {
+    /// Identifier of the task.
+    pub id: TaskId,
+    /// Name given with [`TaskSystem::run_named`](super::TaskSystem::run_named).
+    pub name: Option<String>,
+    /// Index of the worker that ran the task, `None` for other threads.
+    pub worker: Option<usize>,
+    /// When the task started, relative to the creation of the task system.
+    pub start: Duration,
+    /// How long the task ran.
+    pub duration: Duration,
+}
+
+// *************************************************************************************************
+// The latest task runs, oldest first.
+pub(super) struct TraceRecorder {
+    created: Instant,
+    capacity: usize,
+    events: Mutex<VecDeque<TraceEvent>>,
+}
+
+impl TraceRecorder {
+    pub(super) fn new(capacity: usize) -> Self {
+        return Self{
+            created: Instant::now(),
+            capacity,
+            events: Mutex::new(VecDeque::new()),
+        };
+    }
+
+    pub(super) fn record(&self, id: TaskId, name: Option<&str>, worker: Option<usize>, started: Instant, finished: Instant) {
+        let event = TraceEvent{
+            id,
+            name: name.map(String::from),
+            worker,
+            start: started.saturating_duration_since(self.created),
+            duration: finished.saturating_duration_since(started),
+        };
+
+        let mut events = self.events.lock().unpoison();
+        if events.len() == self.capacity {
+            events.pop_front();
+        }
+        events.push_back(event);
+    }
+
+    pub(super) fn events(&self) -> Vec<TraceEvent> {
+        return self.events.lock().unpoison().iter().cloned().collect();
+    }
+}
+
+// *************************************************************************************************
+// Writes the events in the Trace Event Format read by `chrome://tracing` and Perfetto, one row per
+// worker and a last one for tasks run by other threads.
+pub(super) fn write_chrome_trace<W>(events: &[TraceEvent], n_workers: usize, mut out: W) -> io::Result<()> where W: Write {
+    // Workers retired since may show up in older events.
+    let n_workers = events.iter().filter_map(|event| event.worker).map(|worker| worker + 1).fold(n_workers, usize::max);
+    let other = n_workers;
+    let mut json = String::from("{\"traceEvents\":[");
+    for worker in 0..=n_workers {
+        let name = if worker == other { String::from("other threads") } else { format!("worker {}", worker) };
+        let _ = write!(json, "{{\"name\":\"thread_name\",\"ph\":\"M\",\"pid\":1,\"tid\":{},\"args\":{{\"name\":\"{}\"}}}},", worker, name);
+    }
+    for event in events {
+        let name = match &event.name {
+            Some(name) => escape(name),
+            None => event.id.to_string(),
+        };
+        let _ = write!(
+            json,
+            "{{\"name\":\"{}\",\"cat\":\"task\",\"ph\":\"X\",\"pid\":1,\"tid\":{},\"ts\":{},\"dur\":{},\"args\":{{\"id\":\"{}\"}}}},",
+            name,
+            event.worker.unwrap_or(other),
+            micros(event.start),
+            micros(event.duration),
+            event.id,
+        );
+    }
+    json.pop();
+    json.push_str("],\"displayTimeUnit\":\"ms\"}");
+    out.write_all(json.as_bytes())?;
+    return out.flush();
+}
+
+fn micros(duration: Duration) -> String {
+    return format!("{}.{:03}", duration.as_micros(), duration.subsec_nanos() % 1_000);
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for character in text.chars() {
+        match character {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            character if character.is_control() => {
+                let _ = write!(escaped, "\\u{:04x}", character as u32);
+            },
+            character => escaped.push(character),
+        }
+    }
+    return escaped;
+}
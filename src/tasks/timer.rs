@@ -0,0 +1,116 @@
+use std::{
+    sync::{Arc, Mutex, Condvar},
+    collections::BinaryHeap,
+    cmp::Ordering,
+    time::Instant,
+    thread,
+    mem,
+};
+
+use super::{Job, Unpoison};
+
+// *************************************************************************************************
+struct Entry {
+    at: Instant,
+    // Keeps entries due at the same instant in scheduling order.
+    id: u64,
+    job: Job,
+}
+
+impl PartialEq for Entry {
+    fn eq(&self, other: &Self) -> bool {
+        return self.cmp(other) == Ordering::Equal;
+    }
+}
+
+impl Eq for Entry {}
+
+impl PartialOrd for Entry {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        return Some(self.cmp(other));
+    }
+}
+
+impl Ord for Entry {
+    // Reversed, so the max-heap yields the earliest entry first.
+    fn cmp(&self, other: &Self) -> Ordering {
+        return (other.at, other.id).cmp(&(self.at, self.id));
+    }
+}
+
+// *************************************************************************************************
+struct TimerState {
+    entries: BinaryHeap<Entry>,
+    next_id: u64,
+    closing: bool,
+}
+
+// Runs jobs on a dedicated thread once their time arrives. Jobs should only hand work over to the
+// pool, anything slow delays every later entry.
+pub(super) struct Timer {
+    state: Mutex<TimerState>,
+    condvar: Condvar,
+}
+
+impl Timer {
+    pub(super) fn new() -> Arc<Self> {
+        let timer = Arc::new(Self{
+            state: Mutex::new(TimerState{
+                entries: BinaryHeap::new(),
+                next_id: 0,
+                closing: false,
+            }),
+            condvar: Condvar::new(),
+        });
+
+        let thread_timer = timer.clone();
+        thread::spawn(move || thread_timer.timer_loop());
+        return timer;
+    }
+
+    pub(super) fn schedule(&self, at: Instant, job: Job) {
+        let mut state = self.state.lock().unpoison();
+        let id = state.next_id;
+        state.next_id += 1;
+
+        let earliest = state.entries.peek().is_none_or(|entry| at < entry.at);
+        state.entries.push(Entry{ at, id, job });
+        if earliest {
+            self.condvar.notify_one();
+        }
+    }
+
+    // Stops the timer thread, dropping every entry that isn't due yet.
+    pub(super) fn close(&self) {
+        let entries = {
+            let mut state = self.state.lock().unpoison();
+            state.closing = true;
+            self.condvar.notify_one();
+            mem::take(&mut state.entries)
+        };
+        // Dropped outside of the lock, as dropping an entry may cancel tasks and run their
+        // continuations.
+        drop(entries);
+    }
+
+    fn timer_loop(&self) {
+        let mut state = self.state.lock().unpoison();
+        loop {
+            if state.closing {
+                return;
+            }
+
+            let now = Instant::now();
+            match state.entries.peek().map(|entry| entry.at) {
+                Some(at) if at <= now => {
+                    let entry = state.entries.pop().unwrap();
+                    drop(state);
+                    entry.job.run();
+                    state = self.state.lock().unpoison();
+                },
+                Some(at) => state = self.condvar.wait_timeout(state, at - now).unpoison().0,
+                None => state = self.condvar.wait(state).unpoison(),
+            }
+        }
+    }
+}
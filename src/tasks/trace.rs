@@ -0,0 +1,48 @@
+// Hooks for the optional `tracing` integration. Without the feature they compile to nothing.
+
+use super::{TaskStatus, registry::TaskMeta};
+
+// *************************************************************************************************
+// Span of a task, a child of the span that was current when the task was submitted so traces stay
+// connected across the pool boundary.
+#[cfg(feature = "tracing")]
+pub(super) struct TaskSpan(tracing::Span);
+
+#[cfg(not(feature = "tracing"))]
+pub(super) struct TaskSpan;
+
+impl TaskSpan {
+    #[cfg(feature = "tracing")]
+    pub(super) fn new(meta: &TaskMeta) -> Self {
+        return TaskSpan(tracing::info_span!(
+            parent: tracing::Span::current(),
+            "task",
+            id = %meta.id,
+            name = meta.name.as_deref(),
+        ));
+    }
+
+    #[cfg(not(feature = "tracing"))]
+    pub(super) fn new(_meta: &TaskMeta) -> Self {
+        return TaskSpan;
+    }
+
+    #[cfg(feature = "tracing")]
+    pub(super) fn in_scope<F, R>(&self, fun: F) -> R where F: FnOnce() -> R {
+        return self.0.in_scope(fun);
+    }
+
+    #[cfg(not(feature = "tracing"))]
+    pub(super) fn in_scope<F, R>(&self, fun: F) -> R where F: FnOnce() -> R {
+        return fun();
+    }
+}
+
+// *************************************************************************************************
+#[cfg(feature = "tracing")]
+pub(super) fn status_changed(meta: &TaskMeta, status: TaskStatus) {
+    tracing::debug!(task.id = %meta.id, task.name = meta.name.as_deref(), status = ?status, "task status changed");
+}
+
+#[cfg(not(feature = "tracing"))]
+pub(super) fn status_changed(_meta: &TaskMeta, _status: TaskStatus) {}
@@ -0,0 +1,96 @@
+use std::{
+    sync::{Arc, Weak, atomic::Ordering},
+    cell::RefCell,
+    collections::HashMap,
+    time::{Duration, Instant},
+    backtrace::Backtrace,
+};
+
+use super::{Job, TaskId, Unpoison, registry::{TaskMeta, TaskRegistry}, timer::Timer};
+
+thread_local! {
+    // Tasks running on this thread, the innermost last.
+    static RUNNING: RefCell<Vec<Arc<TaskMeta>>> = const { RefCell::new(Vec::new()) };
+}
+
+// *************************************************************************************************
+/// Running task that went quiet for longer than allowed, reported by the watchdog of
+/// [`TaskSystemBuilder::watchdog`](super::TaskSystemBuilder::watchdog).
+#[derive(Debug, Clone)]
+pub struct HungTask {
+    /// Identifier of the task.
+    pub id: TaskId,
+    /// Name given with [`TaskSystem::run_named`](super::TaskSystem::run_named).
+    pub name: Option<String>,
+    /// Index of the worker running the task, `None` for other threads.
+    pub worker: Option<usize>,
+    /// Time since the task started or last reported it is alive.
+    pub silent: Duration,
+    /// Stack of the task where it last called [`heartbeat`], `None` if it never did. Only
+    /// captured when enabled through `RUST_BACKTRACE`, see [`Backtrace::capture`]; a thread can't
+    /// take the backtrace of another one.
+    pub backtrace: Option<Arc<Backtrace>>,
+}
+
+pub(super) type HungTaskHandler = Arc<dyn Fn(&HungTask) + Send + Sync>;
+
+/// Reports that the task running on the calling thread is alive, so the watchdog of
+/// [`TaskSystemBuilder::watchdog`](super::TaskSystemBuilder::watchdog) leaves it alone for
+/// another period. Does nothing outside of tasks.
+pub fn heartbeat() {
+    RUNNING.with(|running| {
+        if let Some(meta) = running.borrow().last() {
+            meta.beat();
+            *meta.backtrace.lock().unpoison() = Some(Arc::new(Backtrace::capture()));
+        }
+    });
+}
+
+// Runs `fun` as the task of `meta` for `heartbeat`.
+pub(super) fn run_as<F, R>(meta: &Arc<TaskMeta>, fun: F) -> R where F: FnOnce() -> R {
+    RUNNING.with(|running| running.borrow_mut().push(meta.clone()));
+    let result = fun();
+    RUNNING.with(|running| running.borrow_mut().pop());
+    return result;
+}
+
+// *************************************************************************************************
+// Checks the running tasks a few times per `stall` on the timer, reporting each silence once.
+pub(super) fn watch(timer: &Arc<Timer>, registry: Weak<TaskRegistry>, stall: Duration, on_hung: HungTaskHandler) {
+    schedule_check(timer.clone(), registry, stall, on_hung, HashMap::new());
+}
+
+fn schedule_check(timer: Arc<Timer>, registry: Weak<TaskRegistry>, stall: Duration, on_hung: HungTaskHandler, reported: HashMap<TaskId, u64>) {
+    let at = Instant::now() + (stall / 4).max(Duration::from_millis(1));
+    timer.clone().schedule(at, Job::new(move || {
+        let registry_ref = match registry.upgrade() {
+            Some(registry) => registry,
+            None => return,
+        };
+
+        // The last beat each hung task was reported at, so it is reported again only after it
+        // beat and went quiet once more.
+        let mut hung = HashMap::new();
+        for meta in registry_ref.running() {
+            let silent = meta.silence();
+            if silent < stall {
+                continue;
+            }
+            let beat = meta.last_beat();
+            if reported.get(&meta.id) != Some(&beat) {
+                let worker = meta.worker.load(Ordering::Relaxed);
+                on_hung(&HungTask{
+                    id: meta.id,
+                    name: meta.name.clone(),
+                    worker: (worker != usize::MAX).then_some(worker),
+                    silent,
+                    backtrace: meta.backtrace.lock().unpoison().clone(),
+                });
+            }
+            hung.insert(meta.id, beat);
+        }
+        drop(registry_ref);
+
+        schedule_check(timer, registry, stall, on_hung, hung);
+    }));
+}
@@ -0,0 +1,34 @@
+// Lives in its own test binary, as the tracking allocator replaces the allocator of the whole
+// binary.
+#![cfg(not(loom))]
+
+use task_system::{GetValueError, TaskBase, TaskSystem, TrackingAllocator};
+
+#[global_allocator]
+static ALLOCATOR: TrackingAllocator = TrackingAllocator::new(std::alloc::System);
+
+#[test]
+fn allocations_are_charged_to_tasks_and_their_groups() {
+    let system = TaskSystem::new(1);
+    let kept = system.run(|| vec![1u8; 1 << 20]);
+    let freed = system.run(|| vec![1u8; 1 << 20].len());
+    let (mut kept_clone, mut freed_clone) = (kept.clone(), freed.clone());
+    assert_eq!(kept.join().map(|bytes| bytes.len()), Ok(1 << 20));
+    assert_eq!(freed.join(), Ok(1 << 20));
+    kept_clone.wait();
+    freed_clone.wait();
+    assert!(kept_clone.memory_used() >= 1 << 20);
+    assert!(freed_clone.memory_used() < 1 << 10);
+
+    let assets = system.group("assets");
+    let textures = assets.group("textures");
+    assets.set_memory_budget(Some(1 << 20));
+    let texture = textures.run(|| vec![1u8; 1 << 19]).join().unwrap();
+    assert!(assets.memory_used() >= 1 << 19);
+    assert_eq!(textures.run(|| 1).join(), Ok(1));
+    let more = textures.run(|| vec![1u8; 1 << 19]).join().unwrap();
+    assert_eq!(textures.run(|| 1).join(), Err(GetValueError::MemoryBudgetExceeded));
+    assets.set_memory_budget(None);
+    assert_eq!(textures.run(|| 1).join(), Ok(1));
+    drop((texture, more));
+}